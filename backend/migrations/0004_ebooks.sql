@@ -0,0 +1,20 @@
+CREATE TABLE "EbookFile" (
+    "id" INTEGER NOT NULL UNIQUE,
+    "book" INTEGER NOT NULL,
+    "file_name" TEXT NOT NULL, -- Name of the uploaded file, used when downloading
+    "media_type" TEXT NOT NULL,
+    "size" INTEGER NOT NULL, -- Bytes
+    "added_at" INTEGER NOT NULL,
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("book") REFERENCES "Book"("id") ON DELETE CASCADE
+);
+
+-- Personal tokens for clients that cannot do the cookie login flow (e.g. e-readers)
+CREATE TABLE "ApiToken" (
+    "id" TEXT NOT NULL UNIQUE,
+    "name" TEXT NOT NULL,
+    "secret_hash" TEXT NOT NULL,
+    "created_at" INTEGER NOT NULL,
+    "user" INTEGER NOT NULL,
+    FOREIGN KEY("user") REFERENCES "User"("id") ON DELETE CASCADE
+);
@@ -0,0 +1,9 @@
+ALTER TABLE "PhysicalBook" ADD COLUMN "condition" TEXT; -- new, fine, good, fair or poor
+ALTER TABLE "PhysicalBook" ADD COLUMN "acquired_at" TEXT;
+ALTER TABLE "PhysicalBook" ADD COLUMN "purchase_price" REAL;
+ALTER TABLE "PhysicalBook" ADD COLUMN "currency" TEXT; -- ISO 4217 code
+ALTER TABLE "PhysicalBook" ADD COLUMN "source" TEXT; -- bought, gift, inherited or other
+ALTER TABLE "PhysicalBook" ADD COLUMN "owner" INTEGER REFERENCES "User"("id") ON DELETE SET NULL;
+ALTER TABLE "PhysicalBook" ADD COLUMN "signed" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "PhysicalBook" ADD COLUMN "first_edition" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "PhysicalBook" ADD COLUMN "notes" TEXT;
@@ -0,0 +1,2 @@
+-- When a label was last printed for the copy, NULL if it has never had one
+ALTER TABLE "PhysicalBook" ADD COLUMN "labeled_at" INTEGER;
@@ -0,0 +1,3 @@
+-- Short human readable code (e.g. k7m-p2q), assigned to existing copies on startup
+ALTER TABLE "PhysicalBook" ADD COLUMN "code" TEXT;
+CREATE UNIQUE INDEX "PhysicalBookCode" ON "PhysicalBook" ("code");
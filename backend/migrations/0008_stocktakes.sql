@@ -0,0 +1,39 @@
+CREATE TABLE "Stocktake" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"user"	INTEGER NOT NULL,
+	"started_at"	INTEGER NOT NULL,
+	"finished_at"	INTEGER,
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("user") REFERENCES "User"("id") ON DELETE CASCADE
+);
+-- Snapshot of where the copies were supposed to be when the stocktake started
+CREATE TABLE "StocktakeExpected" (
+    "stocktake" INTEGER NOT NULL,
+    "copy" INTEGER NOT NULL,
+    "shelf" INTEGER NOT NULL,
+    UNIQUE("stocktake", "copy"),
+	FOREIGN KEY("stocktake") REFERENCES "Stocktake"("id") ON DELETE CASCADE,
+	FOREIGN KEY("copy") REFERENCES "PhysicalBook"("id") ON DELETE CASCADE,
+	FOREIGN KEY("shelf") REFERENCES "Shelf"("id") ON DELETE CASCADE
+);
+CREATE TABLE "StocktakeShelf" (
+    "stocktake" INTEGER NOT NULL,
+    "shelf" INTEGER NOT NULL,
+    UNIQUE("stocktake", "shelf"),
+	FOREIGN KEY("stocktake") REFERENCES "Stocktake"("id") ON DELETE CASCADE,
+	FOREIGN KEY("shelf") REFERENCES "Shelf"("id") ON DELETE CASCADE
+);
+CREATE TABLE "StocktakeScan" (
+	"id"	INTEGER NOT NULL UNIQUE,
+    "stocktake" INTEGER NOT NULL,
+    "scanned" TEXT NOT NULL, -- Raw input, kept for codes that could not be resolved
+    "copy" INTEGER, -- NULL if the scanned code is unknown
+    "shelf" INTEGER NOT NULL, -- Where the copy was seen
+    "copy_shelf" INTEGER, -- Where the copy belonged when it was scanned
+    "scanned_at" INTEGER NOT NULL,
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("stocktake") REFERENCES "Stocktake"("id") ON DELETE CASCADE,
+	FOREIGN KEY("copy") REFERENCES "PhysicalBook"("id") ON DELETE SET NULL,
+	FOREIGN KEY("shelf") REFERENCES "Shelf"("id") ON DELETE CASCADE,
+	FOREIGN KEY("copy_shelf") REFERENCES "Shelf"("id") ON DELETE SET NULL
+);
@@ -0,0 +1,18 @@
+-- Append-only log of where each copy has been
+CREATE TABLE "CopyMove" (
+	"id"	INTEGER NOT NULL UNIQUE,
+    "copy" INTEGER NOT NULL,
+    "from_shelf" INTEGER, -- NULL when the copy was first added
+    "to_shelf" INTEGER,
+    "user" INTEGER,
+    "moved_at" INTEGER NOT NULL,
+    "reason" TEXT,
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("copy") REFERENCES "PhysicalBook"("id") ON DELETE CASCADE,
+	FOREIGN KEY("from_shelf") REFERENCES "Shelf"("id") ON DELETE SET NULL,
+	FOREIGN KEY("to_shelf") REFERENCES "Shelf"("id") ON DELETE SET NULL,
+	FOREIGN KEY("user") REFERENCES "User"("id") ON DELETE SET NULL
+);
+CREATE INDEX "CopyMoveCopy" ON "CopyMove" ("copy");
+CREATE INDEX "CopyMoveFromShelf" ON "CopyMove" ("from_shelf");
+CREATE INDEX "CopyMoveToShelf" ON "CopyMove" ("to_shelf");
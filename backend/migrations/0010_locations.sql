@@ -0,0 +1,28 @@
+-- Location tree (home > room > bookcase), shelves hang off any node
+CREATE TABLE "Location" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"name"	TEXT NOT NULL,
+	"kind"	TEXT NOT NULL, -- home, room or bookcase
+	"parent"	INTEGER,
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("parent") REFERENCES "Location"("id") ON DELETE SET NULL
+);
+CREATE INDEX "LocationParent" ON "Location" ("parent");
+
+ALTER TABLE "Shelf" ADD COLUMN "location" INTEGER REFERENCES "Location"("id") ON DELETE SET NULL;
+
+-- "Home › Living room › Billy 2" for every location reachable from a root
+CREATE VIEW "LocationPath" AS
+WITH RECURSIVE "Path"("id", "breadcrumb") AS (
+    SELECT id, name FROM Location WHERE parent IS NULL
+    UNION ALL
+    SELECT Location.id, Path.breadcrumb || ' › ' || Location.name
+    FROM Location
+    INNER JOIN Path ON Location.parent = Path.id
+)
+SELECT id, breadcrumb FROM Path;
+
+CREATE VIEW "ShelfPath" AS
+SELECT Shelf.id, COALESCE(LocationPath.breadcrumb || ' › ', '') || Shelf.name AS breadcrumb
+FROM Shelf
+LEFT JOIN LocationPath ON Shelf.location = LocationPath.id;
@@ -0,0 +1,15 @@
+-- Physical geometry in centimetres. Rooms use width and depth as the size of their floor plan,
+-- bookcases sit on it with their back left corner at x/y, turned clockwise by rotation degrees
+ALTER TABLE "Location" ADD COLUMN "width" REAL;
+ALTER TABLE "Location" ADD COLUMN "depth" REAL;
+ALTER TABLE "Location" ADD COLUMN "height" REAL;
+ALTER TABLE "Location" ADD COLUMN "x" REAL;
+ALTER TABLE "Location" ADD COLUMN "y" REAL;
+ALTER TABLE "Location" ADD COLUMN "rotation" REAL;
+-- Bookcases are split into levels from the top and sections from the left
+ALTER TABLE "Location" ADD COLUMN "levels" INTEGER;
+ALTER TABLE "Location" ADD COLUMN "sections" INTEGER;
+
+-- Where a shelf sits inside its bookcase, both starting at 1
+ALTER TABLE "Shelf" ADD COLUMN "level" INTEGER;
+ALTER TABLE "Shelf" ADD COLUMN "section" INTEGER;
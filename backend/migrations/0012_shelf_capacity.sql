@@ -0,0 +1,6 @@
+-- Usable width of a shelf in centimetres, falls back to its share of the bookcase
+ALTER TABLE "Shelf" ADD COLUMN "width" REAL;
+
+-- Used to estimate how much room a copy takes up, thickness in centimetres overrides the estimate
+ALTER TABLE "PhysicalBook" ADD COLUMN "format" TEXT; -- paperback, hardcover or oversized
+ALTER TABLE "PhysicalBook" ADD COLUMN "thickness" REAL;
@@ -0,0 +1 @@
+ALTER TABLE "Shelf" ADD COLUMN "description" TEXT;
@@ -0,0 +1,7 @@
+ALTER TABLE "Book" ADD COLUMN "series" TEXT;
+ALTER TABLE "Book" ADD COLUMN "series_index" REAL; -- Allows for in-between volumes like 2.5
+
+ALTER TABLE "PhysicalBook" ADD COLUMN "position" INTEGER; -- From the left starting at 1, NULL until shelved in order
+ALTER TABLE "PhysicalBook" ADD COLUMN "color" TEXT; -- Spine colour as #rrggbb
+
+ALTER TABLE "Shelf" ADD COLUMN "sort_rule" TEXT; -- author, series, color or manual (the default)
@@ -0,0 +1,12 @@
+-- Saved book filters, evaluated whenever the collection is opened
+CREATE TABLE "Collection" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"name"	TEXT NOT NULL,
+	"owner"	INTEGER NOT NULL,
+	"shared"	INTEGER NOT NULL DEFAULT 0, -- Visible to every user
+	"filter"	TEXT NOT NULL, -- JSON
+	"created_at"	INTEGER NOT NULL,
+	PRIMARY KEY("id" AUTOINCREMENT),
+	UNIQUE("owner", "name"),
+	FOREIGN KEY("owner") REFERENCES "User"("id") ON DELETE CASCADE
+);
@@ -0,0 +1,17 @@
+-- Copies that actually left the shelf, as opposed to reservations which are only planned
+CREATE TABLE "Loan" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"copy"	INTEGER NOT NULL,
+	"user"	INTEGER NOT NULL, -- Borrower
+	"reservation"	INTEGER, -- NULL when lent without a reservation
+	"checked_out_at"	INTEGER NOT NULL,
+	"due_date"	TEXT NOT NULL,
+	"returned_at"	INTEGER, -- NULL while the copy is out
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("copy") REFERENCES "PhysicalBook"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "User"("id") ON DELETE CASCADE,
+	FOREIGN KEY("reservation") REFERENCES "Reservation"("id") ON DELETE SET NULL
+);
+-- A copy can only be out once at a time
+CREATE UNIQUE INDEX "LoanActiveCopy" ON "Loan" ("copy") WHERE "returned_at" IS NULL;
+CREATE INDEX "LoanUser" ON "Loan" ("user");
@@ -0,0 +1,15 @@
+-- People without an account that books are lent to
+CREATE TABLE "Borrower" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"name"	TEXT NOT NULL,
+	"phone"	TEXT,
+	"email"	TEXT,
+	"notes"	TEXT,
+	"created_at"	INTEGER NOT NULL,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+-- The user still records who made the reservation or handed out the copy
+ALTER TABLE "Reservation" ADD COLUMN "borrower" INTEGER REFERENCES "Borrower"("id") ON DELETE CASCADE;
+ALTER TABLE "Loan" ADD COLUMN "borrower" INTEGER REFERENCES "Borrower"("id") ON DELETE CASCADE;
+CREATE INDEX "ReservationBorrower" ON "Reservation" ("borrower");
+CREATE INDEX "LoanBorrower" ON "Loan" ("borrower");
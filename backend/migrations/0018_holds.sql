@@ -0,0 +1,22 @@
+-- Waitlist for books whose copies are all taken
+CREATE TABLE "Hold" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"book"	INTEGER NOT NULL,
+	"user"	INTEGER NOT NULL,
+	"days"	INTEGER NOT NULL, -- How long the user wants the book once it is their turn
+	"created_at"	INTEGER NOT NULL,
+	"status"	TEXT NOT NULL DEFAULT 'waiting', -- waiting, offered, claimed, expired or cancelled
+	"offered_copy"	INTEGER,
+	"offered_at"	INTEGER,
+	"offer_expires_at"	INTEGER,
+	"reservation"	INTEGER, -- Made when the offer was claimed
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("book") REFERENCES "Book"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "User"("id") ON DELETE CASCADE,
+	FOREIGN KEY("offered_copy") REFERENCES "PhysicalBook"("id") ON DELETE SET NULL,
+	FOREIGN KEY("reservation") REFERENCES "Reservation"("id") ON DELETE SET NULL
+);
+-- One place in line per user and book
+CREATE UNIQUE INDEX "HoldActiveUser" ON "Hold" ("book", "user") WHERE "status" IN ('waiting', 'offered');
+-- A copy is only offered to one hold at a time
+CREATE UNIQUE INDEX "HoldOfferedCopy" ON "Hold" ("offered_copy") WHERE "status" = 'offered';
@@ -0,0 +1,21 @@
+-- Groups users for lending policies, e.g. family or guest
+ALTER TABLE "User" ADD COLUMN "role" TEXT;
+
+-- Limits on reservations, either for everyone or scoped to a role, shelf or copy.
+-- NULL limits are taken from a less specific rule
+CREATE TABLE "LendingPolicy" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"role"	TEXT,
+	"shelf"	INTEGER,
+	"copy"	INTEGER,
+	"max_loan_days"	INTEGER,
+	"max_reservations"	INTEGER,
+	"max_days_ahead"	INTEGER,
+	"reference_only"	INTEGER,
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("shelf") REFERENCES "Shelf"("id") ON DELETE CASCADE,
+	FOREIGN KEY("copy") REFERENCES "PhysicalBook"("id") ON DELETE CASCADE,
+	CHECK (("role" IS NOT NULL) + ("shelf" IS NOT NULL) + ("copy" IS NOT NULL) <= 1)
+);
+-- One rule per scope
+CREATE UNIQUE INDEX "LendingPolicyScope" ON "LendingPolicy" (IFNULL("role", ''), IFNULL("shelf", 0), IFNULL("copy", 0));
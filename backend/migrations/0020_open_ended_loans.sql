@@ -0,0 +1,24 @@
+-- Loans without a due date last until the copy is returned, SQLite needs the table rebuilt to drop NOT NULL
+CREATE TABLE "LoanNew" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"copy"	INTEGER NOT NULL,
+	"user"	INTEGER NOT NULL, -- Borrower, or whoever handed the copy out to a borrower without an account
+	"reservation"	INTEGER, -- NULL when lent without a reservation
+	"checked_out_at"	INTEGER NOT NULL,
+	"due_date"	TEXT, -- NULL when lent until returned
+	"returned_at"	INTEGER, -- NULL while the copy is out
+	"borrower"	INTEGER,
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("copy") REFERENCES "PhysicalBook"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "User"("id") ON DELETE CASCADE,
+	FOREIGN KEY("reservation") REFERENCES "Reservation"("id") ON DELETE SET NULL,
+	FOREIGN KEY("borrower") REFERENCES "Borrower"("id") ON DELETE CASCADE
+);
+INSERT INTO "LoanNew" (id, copy, user, reservation, checked_out_at, due_date, returned_at, borrower)
+SELECT id, copy, user, reservation, checked_out_at, due_date, returned_at, borrower FROM "Loan";
+DROP TABLE "Loan";
+ALTER TABLE "LoanNew" RENAME TO "Loan";
+
+CREATE UNIQUE INDEX "LoanActiveCopy" ON "Loan" ("copy") WHERE "returned_at" IS NULL;
+CREATE INDEX "LoanUser" ON "Loan" ("user");
+CREATE INDEX "LoanBorrower" ON "Loan" ("borrower");
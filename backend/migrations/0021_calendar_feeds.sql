@@ -0,0 +1,10 @@
+-- Secret feed URLs for calendar apps, which cannot log in
+CREATE TABLE "CalendarFeed" (
+    "id" TEXT NOT NULL UNIQUE,
+    "name" TEXT NOT NULL,
+    "scope" TEXT NOT NULL DEFAULT 'mine', -- mine, or all for every reservation in the library
+    "secret_hash" TEXT NOT NULL,
+    "created_at" INTEGER NOT NULL,
+    "user" INTEGER NOT NULL,
+    FOREIGN KEY("user") REFERENCES "User"("id") ON DELETE CASCADE
+);
@@ -0,0 +1,13 @@
+ALTER TABLE "User" ADD COLUMN "email" TEXT;
+-- Reminders are only sent to users who asked for them
+ALTER TABLE "User" ADD COLUMN "email_reminders" INTEGER NOT NULL DEFAULT 0;
+
+-- Reminders already sent, so that each goes out once
+CREATE TABLE "SentReminder" (
+    "kind" TEXT NOT NULL, -- reservation_starting, due_soon, overdue or hold_available
+    "subject" INTEGER NOT NULL, -- Id of the reservation, loan or hold the reminder is about
+    "user" INTEGER NOT NULL,
+    "sent_at" INTEGER NOT NULL,
+    PRIMARY KEY("kind", "subject"),
+    FOREIGN KEY("user") REFERENCES "User"("id") ON DELETE CASCADE
+);
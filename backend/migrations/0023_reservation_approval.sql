@@ -0,0 +1,4 @@
+-- Precious copies are only lent with their owner's approval
+ALTER TABLE "PhysicalBook" ADD COLUMN "requires_approval" INTEGER NOT NULL DEFAULT 0;
+-- pending, approved or declined, only approved reservations take the copy
+ALTER TABLE "Reservation" ADD COLUMN "status" TEXT NOT NULL DEFAULT 'approved';
@@ -0,0 +1,31 @@
+-- Keeps the location log of removed copies, which are then only known by their code and title
+CREATE TABLE "CopyMoveKept" (
+	"id"	INTEGER NOT NULL UNIQUE,
+    "copy" INTEGER, -- NULL once the copy was removed
+    "code" TEXT NOT NULL,
+    "title" TEXT NOT NULL,
+    "from_shelf" INTEGER, -- NULL when the copy was first added
+    "to_shelf" INTEGER, -- NULL when the copy was removed
+    "user" INTEGER,
+    "moved_at" INTEGER NOT NULL,
+    "reason" TEXT,
+	PRIMARY KEY("id" AUTOINCREMENT),
+	FOREIGN KEY("copy") REFERENCES "PhysicalBook"("id") ON DELETE SET NULL,
+	FOREIGN KEY("from_shelf") REFERENCES "Shelf"("id") ON DELETE SET NULL,
+	FOREIGN KEY("to_shelf") REFERENCES "Shelf"("id") ON DELETE SET NULL,
+	FOREIGN KEY("user") REFERENCES "User"("id") ON DELETE SET NULL
+);
+
+INSERT INTO CopyMoveKept (id, copy, code, title, from_shelf, to_shelf, user, moved_at, reason)
+SELECT CopyMove.id, CopyMove.copy, PhysicalBook.code, Book.title, CopyMove.from_shelf, CopyMove.to_shelf, CopyMove.user, CopyMove.moved_at, CopyMove.reason
+FROM CopyMove
+INNER JOIN PhysicalBook ON CopyMove.copy = PhysicalBook.id
+INNER JOIN Book ON PhysicalBook.book = Book.id;
+
+DROP TABLE CopyMove;
+ALTER TABLE CopyMoveKept RENAME TO CopyMove;
+
+CREATE INDEX "CopyMoveCopy" ON "CopyMove" ("copy");
+CREATE INDEX "CopyMoveCode" ON "CopyMove" ("code");
+CREATE INDEX "CopyMoveFromShelf" ON "CopyMove" ("from_shelf");
+CREATE INDEX "CopyMoveToShelf" ON "CopyMove" ("to_shelf");
@@ -0,0 +1,3 @@
+-- Admins manage roles and lending policies, the first user to register becomes one
+ALTER TABLE "User" ADD COLUMN "is_admin" INTEGER NOT NULL DEFAULT 0;
+UPDATE "User" SET "is_admin" = 1 WHERE "id" = (SELECT MIN("id") FROM "User");
@@ -1,15 +1,18 @@
-use actix_web::cookie::Cookie;
+use actix_web::{cookie::Cookie, http::header::HeaderValue};
+use base64::{prelude::BASE64_STANDARD, Engine};
 use rand::{rngs::OsRng, TryRngCore};
 use sha2::{Digest, Sha256};
 use sqlx::SqlitePool;
 use time::{Duration, OffsetDateTime};
 use hex;
 
+use crate::database::crud;
+
 // Human readable alphabet (a-z, 0-9 without l, o, 0, 1 to avoid confusion)
 const READABLE_ALPHABET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
 pub const AUTH_COOKIE: &str = "session-token";
 
-#[derive(sqlx::FromRow, serde::Serialize)]
+#[derive(sqlx::FromRow, serde::Serialize, Clone)]
 pub struct Session {
     pub id: String,
     secret_hash: String,
@@ -22,6 +25,49 @@ pub struct Token {
     secret: String
 }
 
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    #[serde(skip)]
+    secret_hash: String,
+    pub created_at: i64,
+    #[serde(skip)]
+    pub user: u32
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct CalendarFeed {
+    pub id: String,
+    pub name: String,
+    pub scope: FeedScope,
+    #[serde(skip)]
+    secret_hash: String,
+    pub created_at: i64,
+    #[serde(skip)]
+    pub user: u32
+}
+
+#[derive(sqlx::Type, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Default)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum FeedScope {
+    /// Reservations and loans of the user, including those for their borrowers
+    #[default]
+    Mine,
+    /// Every reservation in the library
+    All
+}
+
+/// User authenticated through the Authorization header instead of a session cookie
+#[derive(Clone, Copy)]
+pub struct HeaderAuthUser(pub u32);
+
+pub enum Credentials {
+    Bearer(Token),
+    Basic { username: String, password: String }
+}
+
 pub fn parse_auth_cookie(cookie: Option<Cookie<'static>>) -> Option<Token> {
     if let Some(cookie) = cookie {
         let session_token = cookie.to_string();
@@ -36,6 +82,45 @@ pub fn parse_auth_cookie(cookie: Option<Cookie<'static>>) -> Option<Token> {
     None
 }
 
+fn parse_token(token: &str) -> Option<Token> {
+    let (id, secret) = token.split_once('.')?;
+    Some(Token { id: id.to_string(), secret: secret.to_string() })
+}
+
+pub fn parse_authorization_header(header: Option<&HeaderValue>) -> Option<Credentials> {
+    let header = header?.to_str().ok()?;
+    let (scheme, value) = header.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return parse_token(value.trim()).map(Credentials::Bearer);
+    }
+    if scheme.eq_ignore_ascii_case("basic") {
+        let decoded = String::from_utf8(BASE64_STANDARD.decode(value.trim()).ok()?).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        return Some(Credentials::Basic { username: username.to_string(), password: password.to_string() });
+    }
+    None
+}
+
+pub async fn validate_credentials(pool: &SqlitePool, credentials: Credentials) -> Result<Option<u32>, sqlx::Error> {
+    match credentials {
+        Credentials::Bearer(token) => validate_api_token(pool, token).await,
+        Credentials::Basic { username, password } => {
+            // Basic auth accepts either the account password or a personal API token as password
+            if let Some(token) = parse_token(&password) {
+                if let Some(user) = validate_api_token(pool, token).await? {
+                    let owner = crud::get_user(pool, user).await?;
+                    return Ok((owner.username == username).then_some(user));
+                }
+            }
+            match crud::login_user(pool, &username, &password).await {
+                Ok(user) => Ok(user),
+                Err(sqlx::Error::RowNotFound) => Ok(None),
+                Err(err) => Err(err)
+            }
+        }
+    }
+}
+
 pub async fn get_user_from_cookie(pool: &SqlitePool, cookie: Option<Cookie<'static>>) -> Result<Option<u32>, sqlx::Error> {
     let Some(token) = parse_auth_cookie(cookie) else {
         return Ok(None);
@@ -69,7 +154,7 @@ pub async fn create_session(pool: &SqlitePool, user_id: u32) -> Result<Option<(S
         VALUES (?, ?, ?, ?)").bind(id).bind(secret_hash).bind(now).bind(user_id)
         .execute(pool).await?;
     
-    return Ok(Some((session, token)));
+    Ok(Some((session, token)))
 }
 
 pub async fn validate_session(pool: &SqlitePool, token: Token) -> Result<Option<Session>, sqlx::Error> {
@@ -94,6 +179,120 @@ pub async fn invalidate_session(pool: &SqlitePool, session: &Session) -> Result<
     Ok(())
 }
 
+pub async fn create_api_token(pool: &SqlitePool, user_id: u32, name: &str) -> Result<Option<(ApiToken, String)>, sqlx::Error> {
+    let now = OffsetDateTime::now_utc().unix_timestamp(); 
+    let (id, secret) = match (gen_secure_random_str(), gen_secure_random_str()) {
+        (Some(id), Some(secret)) => (id, secret),
+        _ => return Ok(None)
+    };
+    let secret_hash = hex::encode(Sha256::digest(secret.clone()));
+
+    let token = id.clone() + "." + &secret;
+
+    let api_token = ApiToken {
+        id: id.clone(), name: name.to_string(), secret_hash: secret_hash.clone(), created_at: now, user: user_id
+    };
+
+    sqlx::query("
+        INSERT INTO ApiToken (id, name, secret_hash, created_at, user)
+        VALUES (?, ?, ?, ?, ?)").bind(id).bind(name).bind(secret_hash).bind(now).bind(user_id)
+        .execute(pool).await?;
+
+    Ok(Some((api_token, token)))
+}
+
+pub async fn validate_api_token(pool: &SqlitePool, token: Token) -> Result<Option<u32>, sqlx::Error> {
+    let api_token: Option<ApiToken> = sqlx::query_as("
+        SELECT id, name, secret_hash, created_at, user
+        FROM ApiToken
+        WHERE id = ?").bind(&token.id).fetch_optional(pool).await?;
+
+    if let Some(api_token) = api_token {
+        let token_secret_hash = Sha256::digest(token.secret).to_vec();
+        if let Ok(db_secret_hash) = hex::decode(api_token.secret_hash) {
+            if eq_hashes(token_secret_hash, db_secret_hash) {
+                return Ok(Some(api_token.user));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+pub async fn get_api_tokens(pool: &SqlitePool, user_id: u32) -> Result<Vec<ApiToken>, sqlx::Error> {
+    sqlx::query_as("
+        SELECT id, name, secret_hash, created_at, user
+        FROM ApiToken
+        WHERE user = ?
+        ORDER BY created_at").bind(user_id).fetch_all(pool).await
+}
+
+pub async fn revoke_api_token(pool: &SqlitePool, user_id: u32, token_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("
+        DELETE FROM ApiToken
+        WHERE id = ? AND user = ?").bind(token_id).bind(user_id).execute(pool).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn create_calendar_feed(pool: &SqlitePool, user_id: u32, name: &str, scope: FeedScope) -> Result<Option<(CalendarFeed, String)>, sqlx::Error> {
+    let now = OffsetDateTime::now_utc().unix_timestamp(); 
+    let (id, secret) = match (gen_secure_random_str(), gen_secure_random_str()) {
+        (Some(id), Some(secret)) => (id, secret),
+        _ => return Ok(None)
+    };
+    let secret_hash = hex::encode(Sha256::digest(secret.clone()));
+
+    let token = id.clone() + "." + &secret;
+
+    let feed = CalendarFeed {
+        id: id.clone(), name: name.to_string(), scope, secret_hash: secret_hash.clone(), created_at: now, user: user_id
+    };
+
+    sqlx::query("
+        INSERT INTO CalendarFeed (id, name, scope, secret_hash, created_at, user)
+        VALUES (?, ?, ?, ?, ?, ?)").bind(id).bind(name).bind(scope).bind(secret_hash).bind(now).bind(user_id)
+        .execute(pool).await?;
+
+    Ok(Some((feed, token)))
+}
+
+/// The feed the token from its URL belongs to, None for unknown or revoked tokens
+pub async fn validate_calendar_feed(pool: &SqlitePool, token: &str) -> Result<Option<CalendarFeed>, sqlx::Error> {
+    let Some(token) = parse_token(token) else {
+        return Ok(None);
+    };
+    let feed: Option<CalendarFeed> = sqlx::query_as("
+        SELECT id, name, scope, secret_hash, created_at, user
+        FROM CalendarFeed
+        WHERE id = ?").bind(&token.id).fetch_optional(pool).await?;
+
+    if let Some(feed) = feed {
+        let token_secret_hash = Sha256::digest(token.secret).to_vec();
+        if let Ok(db_secret_hash) = hex::decode(&feed.secret_hash) {
+            if eq_hashes(token_secret_hash, db_secret_hash) {
+                return Ok(Some(feed));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+pub async fn get_calendar_feeds(pool: &SqlitePool, user_id: u32) -> Result<Vec<CalendarFeed>, sqlx::Error> {
+    sqlx::query_as("
+        SELECT id, name, scope, secret_hash, created_at, user
+        FROM CalendarFeed
+        WHERE user = ?
+        ORDER BY created_at").bind(user_id).fetch_all(pool).await
+}
+
+pub async fn revoke_calendar_feed(pool: &SqlitePool, user_id: u32, feed_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("
+        DELETE FROM CalendarFeed
+        WHERE id = ? AND user = ?").bind(feed_id).bind(user_id).execute(pool).await?;
+    Ok(result.rows_affected() > 0)
+}
+
 async fn get_session(pool: &SqlitePool, session_id: String) -> Result<Option<Session>, sqlx::Error> {
     let now = OffsetDateTime::now_utc().unix_timestamp(); 
     
@@ -107,10 +306,10 @@ async fn get_session(pool: &SqlitePool, session_id: String) -> Result<Option<Ses
     };
 
     if now - session.created_at < Duration::days(7).whole_seconds() {
-        return Ok(Some(session));
+        Ok(Some(session))
     } else {
         delete_session(pool, session_id).await?;
-        return Ok(None);
+        Ok(None)
     }
 }
 
@@ -127,7 +326,29 @@ fn gen_secure_random_str() -> Option<String> {
         let i = (rand >> 3) as usize;
         result.push(READABLE_ALPHABET[i] as char);
     }
-    return Some(result);
+    Some(result)
+}
+
+/// Six character code in two groups (e.g. k7m-p2q) that is easy to read aloud and type
+pub fn gen_copy_code() -> Option<String> {
+    let mut rand_bytes = [0u8;6];
+    OsRng.try_fill_bytes(&mut rand_bytes).ok()?;
+    let chars: String = rand_bytes.iter()
+        .map(|rand| READABLE_ALPHABET[(rand >> 3) as usize] as char)
+        .collect();
+    Some(format!("{}-{}", &chars[..3], &chars[3..]))
+}
+
+/// Brings user input like "K7M P2Q" or "k7mp2q" to the stored form of a copy code
+pub fn normalize_copy_code(input: &str) -> Option<String> {
+    let chars: String = input.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    if chars.len() != 6 || !chars.bytes().all(|b| READABLE_ALPHABET.contains(&b)) {
+        return None;
+    }
+    Some(format!("{}-{}", &chars[..3], &chars[3..]))
 }
 
 fn eq_hashes(hash1: Vec<u8>, hash2: Vec<u8>) -> bool {
@@ -139,5 +360,88 @@ fn eq_hashes(hash1: Vec<u8>, hash2: Vec<u8>) -> bool {
             return false;
         }
     }
-    return true;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+    use super::*;
+
+    fn header(value: &str) -> HeaderValue {
+        HeaderValue::from_str(value).unwrap()
+    }
+
+    fn basic(credentials: &str) -> HeaderValue {
+        header(&format!("Basic {}", BASE64_STANDARD.encode(credentials)))
+    }
+
+    /// A migrated in-memory database, kept on a single connection so every query sees it
+    async fn memory_pool() -> SqlitePool {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap()
+            .extension("./spellfix1");
+        let pool = SqlitePoolOptions::new().max_connections(1).connect_with(options).await.unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    #[test]
+    fn parses_bearer_tokens() {
+        let Some(Credentials::Bearer(token)) = parse_authorization_header(Some(&header("bearer abc.def "))) else {
+            panic!("expected a bearer token");
+        };
+        assert_eq!((token.id.as_str(), token.secret.as_str()), ("abc", "def"));
+        // A token is an id and a secret
+        assert!(parse_authorization_header(Some(&header("Bearer abcdef"))).is_none());
+    }
+
+    #[test]
+    fn parses_basic_credentials() {
+        let Some(Credentials::Basic { username, password }) = parse_authorization_header(Some(&basic("ann:se:cret"))) else {
+            panic!("expected basic credentials");
+        };
+        // Only the first colon separates the username, passwords may contain more
+        assert_eq!((username.as_str(), password.as_str()), ("ann", "se:cret"));
+    }
+
+    #[test]
+    fn rejects_malformed_authorization_headers() {
+        assert!(parse_authorization_header(None).is_none());
+        assert!(parse_authorization_header(Some(&header("Bearer"))).is_none());
+        assert!(parse_authorization_header(Some(&header("Digest abc.def"))).is_none());
+        assert!(parse_authorization_header(Some(&header("Basic not-base64!"))).is_none());
+        assert!(parse_authorization_header(Some(&basic("no colon"))).is_none());
+        assert!(parse_authorization_header(Some(&header(&format!("Basic {}", BASE64_STANDARD.encode([0xff, b':', 0xfe])))))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn basic_auth_takes_api_tokens_only_under_their_owners_name() {
+        let pool = memory_pool().await;
+        let ann = crate::database::crud::register_user(&pool, "ann", "password").await.unwrap().unwrap();
+        crate::database::crud::register_user(&pool, "bob", "hunter2").await.unwrap().unwrap();
+        let (_, token) = create_api_token(&pool, ann, "e-reader").await.unwrap().unwrap();
+
+        let validate = |credentials: String| {
+            let pool = pool.clone();
+            async move {
+                let credentials = parse_authorization_header(Some(&basic(&credentials))).unwrap();
+                validate_credentials(&pool, credentials).await.unwrap()
+            }
+        };
+        assert_eq!(validate(format!("ann:{token}")).await, Some(ann));
+        assert_eq!(validate(format!("bob:{token}")).await, None);
+        assert_eq!(validate(format!("nobody:{token}")).await, None);
+        assert_eq!(validate("ann:password".to_string()).await, Some(ann));
+        assert_eq!(validate("ann:hunter2".to_string()).await, None);
+        assert_eq!(validate("nobody:password".to_string()).await, None);
+
+        let Credentials::Bearer(bearer) = parse_authorization_header(Some(&header(&format!("Bearer {token}")))).unwrap() else {
+            panic!("expected a bearer token");
+        };
+        assert_eq!(validate_credentials(&pool, Credentials::Bearer(bearer)).await.unwrap(), Some(ann));
+    }
 }
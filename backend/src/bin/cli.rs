@@ -2,6 +2,7 @@ use std::{io::{self, Write}, process::Command};
 
 use hll::database;
 use sqlx::SqlitePool;
+use time::{Duration, OffsetDateTime};
 
 #[tokio::main]
 async fn main() {
@@ -20,7 +21,7 @@ async fn main() {
         print!("-> ");
         io::stdout().flush().unwrap();
         let mut input = String::new();
-        if let Ok(_) = io::stdin().read_line(&mut input) {
+        if io::stdin().read_line(&mut input).is_ok() {
             match input.trim() {
                 "1" => launch_sqlite_repl(),
                 "2" => test_main_features(&db).await,
@@ -38,19 +39,24 @@ async fn test_main_features(pool: &SqlitePool) {
             ----- Test Main Features -----
             1) Search for books
             2) Correct spelling with spellfix
-            3) Back");
+            3) When can I get a book?
+            4) Back");
 
         print!("-> ");
         io::stdout().flush().unwrap();
         let mut input = String::new();
-        if let Ok(_) = io::stdin().read_line(&mut input) {
+        if io::stdin().read_line(&mut input).is_ok() {
             match input.trim() {
                 "1" => println!("Search books"),
                 "2" => match correct_spelling(pool).await {
                     Ok(()) => {},
                     Err(_) => println!("Something went wrong")
                 },
-                "3" => break,
+                "3" => match next_available(pool).await {
+                    Ok(()) => {},
+                    Err(_) => println!("Something went wrong")
+                },
+                "4" => break,
                 _ => println!("Please enter a valid option")
             };
         }
@@ -66,7 +72,7 @@ async fn correct_spelling(pool: &SqlitePool) -> Result<(), sqlx::Error> {
         print!("-> ");
         io::stdout().flush().unwrap();
         let mut input = String::new();
-        if let Ok(_) = io::stdin().read_line(&mut input) {
+        if io::stdin().read_line(&mut input).is_ok() {
             if input == "\n" {
                 return Ok(());
             }
@@ -82,13 +88,51 @@ async fn correct_spelling(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     }
 }
 
+async fn next_available(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    println!("
+        ----- When can I get a book? -----
+            Quit with empty input");
+    loop {
+        print!("ISBN -> ");
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).is_ok() {
+            let isbn = input.trim();
+            if isbn.is_empty() {
+                return Ok(());
+            }
+            let Ok(book) = database::crud::get_book(pool, Some(isbn), None).await else {
+                println!("No book with ISBN {isbn}");
+                continue;
+            };
+            let now = OffsetDateTime::now_utc();
+            let calendar = database::availability::get_book_calendar(pool, &book, None, now, now + Duration::days(1), None).await?;
+            // Free copies are available from the moment the calendar was made
+            let now = OffsetDateTime::now_utc();
+            println!("{}:", calendar.title);
+            for copy in &calendar.copies {
+                let available = match copy.next_available {
+                    _ if copy.reference_only => "reference only".to_string(),
+                    Some(at) if at <= now => "available now".to_string(),
+                    Some(at) => format!("from {}", at.date()),
+                    None => "not before it is returned".to_string(),
+                };
+                println!("  {} on {}: {available}", copy.code, copy.shelf.name);
+            }
+            if calendar.copies.is_empty() {
+                println!("  No copies");
+            }
+        }
+    }
+}
+
 fn launch_sqlite_repl() {
     let result = match Command::new("sqlite3")
         .arg("db/db.sqlite").arg("-cmd").arg(".load ./spellfix1")
         .status() {
             Ok(status) => match status.code() {
                 Some(code) => format!("Exited with status code: {code}"),
-                None => format!("REPL terminated by signal")
+                None => "REPL terminated by signal".to_string()
             },
             Err(_) => String::from("Failed to launch REPL")
         };
@@ -0,0 +1,349 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{database::{crud, holds, loans, policies}, types};
+
+/// Longest range a calendar covers and longest stretch it looks for, in days
+pub const MAX_CALENDAR_DAYS: u32 = 366;
+
+/// A stretch of time a copy can be reserved for
+#[derive(Serialize, Clone)]
+pub struct FreeSlot {
+    pub copy_id: u32,
+    pub code: String,
+    pub shelf: types::Shelf,
+    #[serde(with = "time::serde::iso8601")]
+    pub start: OffsetDateTime,
+    /// None when asked for a reservation until the copy is returned
+    #[serde(with = "time::serde::iso8601::option")]
+    pub end: Option<OffsetDateTime>,
+}
+
+/// A stretch of a calendar, bounded by the requested range
+#[derive(Serialize)]
+pub struct CalendarInterval {
+    #[serde(with = "time::serde::iso8601")]
+    pub start: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    pub end: OffsetDateTime,
+    pub free: bool,
+}
+
+#[derive(Serialize)]
+pub struct CopyCalendar {
+    pub copy_id: u32,
+    pub code: String,
+    pub shelf: types::Shelf,
+    /// Can not be reserved by the user under the lending policy
+    pub reference_only: bool,
+    /// Covers the whole range in order, alternating between free and busy
+    pub intervals: Vec<CalendarInterval>,
+    /// None when the copy is taken for good
+    #[serde(with = "time::serde::iso8601::option")]
+    pub next_available: Option<OffsetDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct BookCalendar {
+    pub uuid: Uuid,
+    pub title: String,
+    pub copies: Vec<CopyCalendar>,
+    /// The earliest over the copies the user may reserve
+    #[serde(with = "time::serde::iso8601::option")]
+    pub next_available: Option<OffsetDateTime>,
+    pub next_available_copy: Option<u32>,
+}
+
+pub enum BookReservation {
+    Reserved { reservation_id: u32, copy_id: u32 },
+    /// No copy is free for the interval, with the earliest one of the same length if any
+    Unavailable(Option<FreeSlot>),
+    /// The free copies are not allowed by the lending policy
+    Refused(policies::Violation),
+}
+
+/// Times the copy is taken for the user, or for anyone without one, sorted by start.
+/// Without an end it is taken for good
+pub async fn get_busy_intervals(
+    pool: &SqlitePool,
+    copy: &types::PhysicalBook,
+    user: Option<u32>,
+) -> Result<Vec<(OffsetDateTime, Option<OffsetDateTime>)>, sqlx::Error> {
+    let mut busy: Vec<(OffsetDateTime, Option<OffsetDateTime>)> = copy.reservations.iter()
+        .filter(|reservation| reservation.takes_copy())
+        .map(|reservation| (reservation.start_date, reservation.end_date))
+        .collect();
+    if let Some(loan) = loans::get_active_loan(pool, copy.id).await? {
+        busy.push(loan.busy_span());
+    }
+    if let Some(offer) = holds::get_copy_offer(pool, copy.id).await? {
+        if Some(offer.user.id) != user {
+            busy.extend(offer.offer_span());
+        }
+    }
+    busy.sort_by_key(|interval| interval.0);
+    Ok(busy)
+}
+
+/// Earliest start from `from` on where `length` fits between the busy intervals,
+/// None when it never does. Without a length the copy has to be free for good
+pub fn earliest_start(
+    busy: &[(OffsetDateTime, Option<OffsetDateTime>)],
+    from: OffsetDateTime,
+    length: Option<Duration>,
+) -> Option<OffsetDateTime> {
+    let mut start = from;
+    // Sorted by start, so pushing past one interval never uncovers an earlier one
+    for (busy_start, busy_end) in busy {
+        // A stretch that runs past the representable dates is as good as open ended
+        let end = length.and_then(|length| start.checked_add(length));
+        if types::spans_intersect(*busy_start, *busy_end, start, end) {
+            start = (*busy_end)?;
+        }
+    }
+    Some(start)
+}
+
+/// Joins busy intervals that overlap or touch, the input sorted by start
+fn merge_busy(busy: &[(OffsetDateTime, Option<OffsetDateTime>)]) -> Vec<(OffsetDateTime, Option<OffsetDateTime>)> {
+    let mut merged: Vec<(OffsetDateTime, Option<OffsetDateTime>)> = vec![];
+    for &(start, end) in busy {
+        match merged.last_mut() {
+            Some((_, last_end)) if last_end.is_none_or(|last_end| start <= last_end) => {
+                *last_end = last_end.zip(end).map(|(last_end, end)| last_end.max(end));
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Free and busy stretches between `from` and `to`
+fn calendar(busy: &[(OffsetDateTime, Option<OffsetDateTime>)], from: OffsetDateTime, to: OffsetDateTime) -> Vec<CalendarInterval> {
+    let mut intervals = vec![];
+    let mut at = from;
+    for (start, end) in merge_busy(busy) {
+        if start >= to {
+            break;
+        }
+        if end.is_some_and(|end| end <= at) {
+            continue;
+        }
+        if start > at {
+            intervals.push(CalendarInterval { start: at, end: start, free: true });
+            at = start;
+        }
+        let end = end.map_or(to, |end| end.min(to));
+        intervals.push(CalendarInterval { start: at, end, free: false });
+        at = end;
+    }
+    if at < to {
+        intervals.push(CalendarInterval { start: at, end: to, free: true });
+    }
+    intervals
+}
+
+/// When the copy is first free from `from` on, for `length` if given
+fn next_available(busy: &[(OffsetDateTime, Option<OffsetDateTime>)], from: OffsetDateTime, length: Option<Duration>) -> Option<OffsetDateTime> {
+    let merged = merge_busy(busy);
+    match length {
+        Some(length) => earliest_start(&merged, from, Some(length)),
+        None => {
+            let mut at = from;
+            for (start, end) in merged {
+                if start <= at && end.is_none_or(|end| at < end) {
+                    at = end?;
+                }
+            }
+            Some(at)
+        }
+    }
+}
+
+/// The copy's calendar between `from` and `to` as the user sees it, or as anyone would without one
+pub async fn get_copy_calendar(
+    pool: &SqlitePool,
+    copy: &types::PhysicalBook,
+    user: Option<u32>,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+    length: Option<Duration>,
+) -> Result<CopyCalendar, sqlx::Error> {
+    let busy = get_busy_intervals(pool, copy, user).await?;
+    let limits = match user {
+        Some(user) => policies::get_holder_limits(pool, user, None, copy.id).await?,
+        None => policies::get_limits(&mut *pool.acquire().await?, None, copy.id).await?,
+    };
+    Ok(CopyCalendar {
+        copy_id: copy.id,
+        code: copy.code.clone(),
+        shelf: copy.shelf.clone(),
+        reference_only: limits.reference_only == Some(true),
+        intervals: calendar(&busy, from, to),
+        next_available: next_available(&busy, from.max(OffsetDateTime::now_utc()), length),
+    })
+}
+
+pub async fn get_book_calendar(
+    pool: &SqlitePool,
+    book: &types::Book,
+    user: Option<u32>,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+    length: Option<Duration>,
+) -> Result<BookCalendar, sqlx::Error> {
+    let mut copies = vec![];
+    for copy_id in &book.copy_ids {
+        if let Some((_, copy)) = crud::get_copy_with_book(pool, *copy_id).await? {
+            copies.push(get_copy_calendar(pool, &copy, user, from, to, length).await?);
+        }
+    }
+    let earliest = copies.iter()
+        .filter(|copy| !copy.reference_only)
+        .filter_map(|copy| copy.next_available.map(|at| (at, copy.copy_id)))
+        .min();
+    Ok(BookCalendar {
+        uuid: book.uuid,
+        title: book.title.clone(),
+        copies,
+        next_available: earliest.map(|(at, _)| at),
+        next_available_copy: earliest.map(|(_, copy_id)| copy_id),
+    })
+}
+
+/// Reserves whichever copy of the book is free, copies on `preferred_shelf` first and
+/// then those that need nobody's approval. Reference only copies are never offered
+pub async fn reserve_book(
+    pool: &SqlitePool,
+    book: &types::Book,
+    user: u32,
+    borrower: Option<u32>,
+    start: OffsetDateTime,
+    end: Option<OffsetDateTime>,
+    preferred_shelf: Option<u32>,
+) -> Result<BookReservation, sqlx::Error> {
+    let mut copies = vec![];
+    for copy_id in &book.copy_ids {
+        if policies::get_holder_limits(pool, user, borrower, *copy_id).await?.reference_only == Some(true) {
+            continue;
+        }
+        if let Some((_, copy)) = crud::get_copy_with_book(pool, *copy_id).await? {
+            copies.push(copy);
+        }
+    }
+    copies.sort_by_key(|copy| (Some(copy.shelf.id) != preferred_shelf, copy.needs_approval_from(user), copy.id));
+
+    let length = end.map(|end| end - start);
+    let mut earliest: Option<FreeSlot> = None;
+    let mut refusal = None;
+    for copy in copies {
+        let busy = get_busy_intervals(pool, &copy, Some(user)).await?;
+        let Some(free_from) = earliest_start(&busy, start, length) else {
+            continue;
+        };
+        if free_from == start {
+            // Checked again on insert, a copy taken in the meantime just moves on to the next
+            match crud::reserve_physical_book(pool, user, borrower, copy.id, start, end).await? {
+                crud::Reserve::Reserved(reservation_id) | crud::Reserve::Requested(reservation_id) => {
+                    return Ok(BookReservation::Reserved { reservation_id, copy_id: copy.id });
+                }
+                crud::Reserve::Unavailable => {}
+                crud::Reserve::Refused(violation) => {
+                    refusal.get_or_insert(violation);
+                }
+            }
+            continue;
+        }
+        if earliest.as_ref().is_none_or(|slot| free_from < slot.start) {
+            earliest = Some(FreeSlot {
+                copy_id: copy.id,
+                code: copy.code.clone(),
+                shelf: copy.shelf.clone(),
+                start: free_from,
+                end: length.and_then(|length| free_from.checked_add(length)),
+            });
+        }
+    }
+    Ok(match (earliest, refusal) {
+        (None, Some(violation)) => BookReservation::Refused(violation),
+        (earliest, _) => BookReservation::Unavailable(earliest),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(n: i64) -> OffsetDateTime {
+        OffsetDateTime::UNIX_EPOCH + Duration::days(n)
+    }
+
+    fn spans(intervals: &[CalendarInterval]) -> Vec<(OffsetDateTime, OffsetDateTime, bool)> {
+        intervals.iter().map(|interval| (interval.start, interval.end, interval.free)).collect()
+    }
+
+    #[test]
+    fn merge_busy_joins_overlapping_and_touching_intervals() {
+        let busy = [
+            (day(0), Some(day(2))),
+            (day(1), Some(day(3))),
+            (day(3), Some(day(4))),
+            (day(6), Some(day(7))),
+            (day(8), None),
+            (day(9), Some(day(10))),
+        ];
+        assert_eq!(merge_busy(&busy), vec![(day(0), Some(day(4))), (day(6), Some(day(7))), (day(8), None)]);
+        assert!(merge_busy(&[]).is_empty());
+    }
+
+    #[test]
+    fn calendar_alternates_free_and_busy_within_the_range() {
+        let busy = [(day(2), Some(day(4))), (day(6), None)];
+        assert_eq!(spans(&calendar(&busy, day(0), day(10))), vec![
+            (day(0), day(2), true),
+            (day(2), day(4), false),
+            (day(4), day(6), true),
+            (day(6), day(10), false),
+        ]);
+    }
+
+    #[test]
+    fn calendar_clips_intervals_to_the_range() {
+        let busy = [(day(-3), Some(day(-1))), (day(-1), Some(day(1))), (day(5), Some(day(6)))];
+        assert_eq!(spans(&calendar(&busy, day(0), day(3))), vec![
+            (day(0), day(1), false),
+            (day(1), day(3), true),
+        ]);
+        assert_eq!(spans(&calendar(&[], day(0), day(3))), vec![(day(0), day(3), true)]);
+    }
+
+    #[test]
+    fn next_available_skips_gaps_that_are_too_short() {
+        let busy = [(day(1), Some(day(3))), (day(4), Some(day(6)))];
+        assert_eq!(next_available(&busy, day(0), Some(Duration::days(2))), Some(day(6)));
+        assert_eq!(next_available(&busy, day(0), Some(Duration::days(1))), Some(day(0)));
+        // Without a length it is the first moment the copy is not taken
+        assert_eq!(next_available(&busy, day(0), None), Some(day(0)));
+        assert_eq!(next_available(&busy, day(2), None), Some(day(3)));
+        assert_eq!(next_available(&[(day(0), Some(day(2))), (day(2), None)], day(1), None), None);
+    }
+
+    #[test]
+    fn earliest_start_fits_the_length_between_busy_intervals() {
+        let busy = [(day(3), Some(day(5)))];
+        assert_eq!(earliest_start(&busy, day(0), Some(Duration::days(2))), Some(day(0)));
+        assert_eq!(earliest_start(&busy, day(0), Some(Duration::days(4))), Some(day(5)));
+        // Free for good only once the last interval is over
+        assert_eq!(earliest_start(&busy, day(0), None), Some(day(5)));
+        assert_eq!(earliest_start(&[(day(3), None)], day(0), Some(Duration::days(1))), Some(day(0)));
+        assert_eq!(earliest_start(&[(day(3), None)], day(0), Some(Duration::days(4))), None);
+    }
+
+    #[test]
+    fn earliest_start_handles_lengths_past_the_representable_dates() {
+        let busy = [(day(3), Some(day(5)))];
+        assert_eq!(earliest_start(&busy, day(0), Some(Duration::days(u32::MAX as i64))), Some(day(5)));
+    }
+}
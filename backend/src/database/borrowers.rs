@@ -0,0 +1,98 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use time::{OffsetDateTime, UtcDateTime};
+
+use crate::{database::{crud, loans}, types};
+
+#[derive(Serialize)]
+pub struct BorrowerSummary {
+    #[serde(flatten)]
+    pub borrower: types::Borrower,
+    /// Copies the borrower has right now
+    pub active_loans: u32,
+    pub overdue_loans: u32,
+}
+
+pub async fn create_borrower(
+    pool: &SqlitePool,
+    name: &str,
+    phone: Option<&str>,
+    email: Option<&str>,
+    notes: Option<&str>,
+) -> Result<u32, sqlx::Error> {
+    sqlx::query_scalar("
+        INSERT INTO Borrower (name, phone, email, notes, created_at)
+        VALUES (?, ?, ?, ?, ?)
+        RETURNING id")
+        .bind(name)
+        .bind(phone)
+        .bind(email)
+        .bind(notes)
+        .bind(UtcDateTime::now().unix_timestamp())
+        .fetch_one(pool).await
+}
+
+pub async fn get_borrower(pool: &SqlitePool, id: u32) -> Result<Option<types::Borrower>, sqlx::Error> {
+    sqlx::query_as("SELECT id, name, phone, email, notes FROM Borrower WHERE id = ?")
+        .bind(id).fetch_optional(pool).await
+}
+
+pub async fn get_borrowers(pool: &SqlitePool) -> Result<Vec<BorrowerSummary>, sqlx::Error> {
+    let borrowers: Vec<types::Borrower> = sqlx::query_as("
+        SELECT id, name, phone, email, notes
+        FROM Borrower
+        ORDER BY name COLLATE NOCASE").fetch_all(pool).await?;
+    let active = loans::get_loans(pool, &loans::LoanFilter { active: true, ..Default::default() }).await?;
+
+    Ok(borrowers.into_iter().map(|borrower| {
+        let loans: Vec<&loans::Loan> = active.iter()
+            .filter(|loan| loan.borrower.as_ref().is_some_and(|b| b.id == borrower.id))
+            .collect();
+        BorrowerSummary {
+            active_loans: loans.len() as u32,
+            overdue_loans: loans.iter().filter(|loan| loan.overdue).count() as u32,
+            borrower,
+        }
+    }).collect())
+}
+
+pub async fn edit_borrower(
+    pool: &SqlitePool,
+    id: u32,
+    name: Option<&str>,
+    phone: Option<Option<String>>,
+    email: Option<Option<String>>,
+    notes: Option<Option<String>>,
+) -> Result<(), sqlx::Error> {
+    let mut qb = crud::update_builder("Borrower", id, |sep| {
+        crud::apply_update(sep, "name", name.map(Some));
+        crud::apply_update(sep, "phone", phone);
+        crud::apply_update(sep, "email", email);
+        crud::apply_update(sep, "notes", notes);
+    });
+    qb.build().execute(pool).await?;
+    Ok(())
+}
+
+/// Also removes the borrower's reservations and past loans
+pub async fn remove_borrower(pool: &SqlitePool, id: u32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM Borrower WHERE id = ?")
+        .bind(id).execute(pool).await?;
+    Ok(())
+}
+
+/// Reservations for the borrower that have not ended yet
+pub async fn get_borrower_reservations(pool: &SqlitePool, id: u32) -> Result<Vec<types::Reservation>, sqlx::Error> {
+    let ids: Vec<u32> = sqlx::query_scalar("SELECT id FROM Reservation WHERE borrower = ? ORDER BY start_date")
+        .bind(id).fetch_all(pool).await?;
+    let now = OffsetDateTime::now_utc();
+    let mut reservations = vec![];
+    for id in ids {
+        if let Some(reservation) = crud::get_reservation(pool, id).await? {
+            if reservation.end_date.is_none_or(|end_date| end_date >= now) {
+                reservations.push(reservation);
+            }
+        }
+    }
+    Ok(reservations)
+}
@@ -0,0 +1,199 @@
+use serde::Serialize;
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+
+use crate::{database::{crud, history}, types};
+
+/// A reservation that is running or upcoming on a copy touched by a bulk operation
+#[derive(Serialize)]
+pub struct AffectedReservation {
+    pub copy_id: u32,
+    pub code: String,
+    pub title: String,
+    pub reservation: types::Reservation,
+}
+
+#[derive(Serialize)]
+pub struct BulkResult {
+    pub dry_run: bool,
+    /// Copies that were (or would be) added, moved or removed
+    pub copies: Vec<u32>,
+    pub reservations: Vec<AffectedReservation>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AffectedReservationRow {
+    copy_id: u32,
+    code: String,
+    title: String,
+    id: u32,
+    user_id: u32,
+    username: String,
+    personal_color: String,
+    borrower_id: Option<u32>,
+    borrower_name: Option<String>,
+    borrower_phone: Option<String>,
+    borrower_email: Option<String>,
+    borrower_notes: Option<String>,
+    created_at: i64,
+    start_date: OffsetDateTime,
+    end_date: Option<OffsetDateTime>,
+    status: types::ReservationStatus,
+}
+
+async fn get_affected_reservations(
+    conn: &mut SqliteConnection,
+    copy_ids: &[u32],
+) -> Result<Vec<AffectedReservation>, sqlx::Error> {
+    if copy_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("
+        SELECT
+            PhysicalBook.id AS copy_id,
+            PhysicalBook.code,
+            Book.title,
+            Reservation.id,
+            User.id AS user_id,
+            User.username,
+            User.personal_color,
+            Borrower.id AS borrower_id,
+            Borrower.name AS borrower_name,
+            Borrower.phone AS borrower_phone,
+            Borrower.email AS borrower_email,
+            Borrower.notes AS borrower_notes,
+            Reservation.created_at,
+            Reservation.start_date,
+            Reservation.end_date,
+            Reservation.status
+        FROM BookReservationMatch
+        INNER JOIN PhysicalBook ON BookReservationMatch.physical_book = PhysicalBook.id
+        INNER JOIN Book ON PhysicalBook.book = Book.id
+        INNER JOIN Reservation ON BookReservationMatch.reservation = Reservation.id
+        INNER JOIN User ON Reservation.user = User.id
+        LEFT JOIN Borrower ON Reservation.borrower = Borrower.id
+        WHERE PhysicalBook.id IN (");
+    let mut separated = query.separated(", ");
+    for id in copy_ids {
+        separated.push_bind(id);
+    }
+    query.push(") ORDER BY Reservation.start_date");
+    let rows: Vec<AffectedReservationRow> = query.build_query_as().fetch_all(&mut *conn).await?;
+
+    // Reservations that already ended or were declined do not matter to anyone
+    let today = OffsetDateTime::now_utc().date();
+    Ok(rows.into_iter()
+        .filter(|row| row.end_date.is_none_or(|end_date| end_date.date() >= today))
+        .filter(|row| row.status != types::ReservationStatus::Declined)
+        .map(|row| AffectedReservation {
+            copy_id: row.copy_id,
+            code: row.code,
+            title: row.title,
+            reservation: types::Reservation {
+                id: row.id,
+                user: types::User { id: row.user_id, username: row.username, personal_color: row.personal_color },
+                borrower: match (row.borrower_id, row.borrower_name) {
+                    (Some(id), Some(name)) => Some(types::Borrower {
+                        id,
+                        name,
+                        phone: row.borrower_phone,
+                        email: row.borrower_email,
+                        notes: row.borrower_notes,
+                    }),
+                    _ => None,
+                },
+                created_at: row.created_at,
+                start_date: row.start_date,
+                end_date: row.end_date,
+                status: row.status,
+            },
+        })
+        .collect())
+}
+
+pub async fn get_shelf_copy_ids(pool: &SqlitePool, shelf_id: u32) -> Result<Vec<u32>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM PhysicalBook WHERE shelf = ? ORDER BY id")
+        .bind(shelf_id)
+        .fetch_all(pool).await
+}
+
+pub async fn add_copies(
+    pool: &SqlitePool,
+    book: u32,
+    shelf: u32,
+    count: u32,
+    user: Option<u32>,
+    dry_run: bool,
+) -> Result<BulkResult, sqlx::Error> {
+    let mut copies = vec![];
+    if !dry_run {
+        let mut tx = pool.begin().await?;
+        for _ in 0..count {
+            copies.push(crud::insert_physical_book(&mut tx, book, shelf, user).await?);
+        }
+        tx.commit().await?;
+    }
+    Ok(BulkResult { dry_run, copies, reservations: vec![] })
+}
+
+pub async fn move_copies(
+    pool: &SqlitePool,
+    copy_ids: &[u32],
+    shelf: u32,
+    user: Option<u32>,
+    reason: Option<&str>,
+    dry_run: bool,
+) -> Result<BulkResult, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT id FROM PhysicalBook WHERE shelf != ");
+    query.push_bind(shelf);
+    query.push(" AND id IN (");
+    let mut separated = query.separated(", ");
+    for id in copy_ids {
+        separated.push_bind(id);
+    }
+    query.push(") ORDER BY id");
+    // Copies already on the shelf are left alone
+    let copies: Vec<u32> = if copy_ids.is_empty() {
+        vec![]
+    } else {
+        query.build_query_scalar().fetch_all(&mut *tx).await?
+    };
+    let reservations = get_affected_reservations(&mut tx, &copies).await?;
+
+    if !dry_run {
+        for id in &copies {
+            crud::move_copy(&mut tx, *id, shelf, user, reason).await?;
+        }
+        tx.commit().await?;
+    }
+    Ok(BulkResult { dry_run, copies, reservations })
+}
+
+/// Removes copies together with their reservations
+pub async fn remove_copies(pool: &SqlitePool, copy_ids: &[u32], user: Option<u32>, dry_run: bool) -> Result<BulkResult, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let reservations = get_affected_reservations(&mut tx, copy_ids).await?;
+
+    if !dry_run {
+        delete_copies(&mut tx, copy_ids, user).await?;
+        tx.commit().await?;
+    }
+    Ok(BulkResult { dry_run, copies: copy_ids.to_vec(), reservations })
+}
+
+/// Deletes the copies and their reservations, the removal stays in each copy's location log
+pub(crate) async fn delete_copies(conn: &mut SqliteConnection, copy_ids: &[u32], user: Option<u32>) -> Result<(), sqlx::Error> {
+    for id in copy_ids {
+        history::record_removal(conn, *id, user).await?;
+        sqlx::query("
+            DELETE FROM Reservation
+            WHERE id IN (SELECT reservation FROM BookReservationMatch WHERE physical_book = ?)")
+            .bind(id)
+            .execute(&mut *conn).await?;
+        sqlx::query("DELETE FROM PhysicalBook WHERE id = ?")
+            .bind(id)
+            .execute(&mut *conn).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use time::OffsetDateTime;
+
+use crate::types;
+
+/// A reserved copy with what a calendar event needs to say about it
+#[derive(sqlx::FromRow)]
+pub struct ReservationEntry {
+    pub id: u32,
+    pub created_at: i64,
+    pub start_date: OffsetDateTime,
+    /// None while the copy is kept until it is returned
+    pub end_date: Option<OffsetDateTime>,
+    pub title: String,
+    pub code: String,
+    pub shelf: String,
+    /// The borrower's name when the reservation is for one, else the username
+    pub holder: String,
+    pub status: types::ReservationStatus,
+}
+
+/// A copy that is out and has to be back by its due date
+#[derive(sqlx::FromRow)]
+pub struct DueEntry {
+    pub loan_id: u32,
+    pub checked_out_at: i64,
+    pub due_date: OffsetDateTime,
+    pub title: String,
+    pub code: String,
+    pub shelf: String,
+    pub holder: String,
+}
+
+const RESERVATION_ENTRY_QUERY: &str = "
+    SELECT
+        Reservation.id,
+        Reservation.created_at,
+        Reservation.start_date,
+        Reservation.end_date,
+        Book.title,
+        PhysicalBook.code,
+        ShelfPath.breadcrumb AS shelf,
+        COALESCE(Borrower.name, User.username) AS holder,
+        Reservation.status
+    FROM Reservation
+    INNER JOIN BookReservationMatch ON Reservation.id = BookReservationMatch.reservation
+    INNER JOIN PhysicalBook ON BookReservationMatch.physical_book = PhysicalBook.id
+    INNER JOIN Book ON PhysicalBook.book = Book.id
+    INNER JOIN ShelfPath ON PhysicalBook.shelf = ShelfPath.id
+    INNER JOIN User ON Reservation.user = User.id
+    LEFT JOIN Borrower ON Reservation.borrower = Borrower.id
+    WHERE Reservation.status != 'declined'";
+
+const DUE_ENTRY_QUERY: &str = "
+    SELECT
+        Loan.id AS loan_id,
+        Loan.checked_out_at,
+        Loan.due_date,
+        Book.title,
+        PhysicalBook.code,
+        ShelfPath.breadcrumb AS shelf,
+        COALESCE(Borrower.name, User.username) AS holder
+    FROM Loan
+    INNER JOIN PhysicalBook ON Loan.copy = PhysicalBook.id
+    INNER JOIN Book ON PhysicalBook.book = Book.id
+    INNER JOIN ShelfPath ON PhysicalBook.shelf = ShelfPath.id
+    INNER JOIN User ON Loan.user = User.id
+    LEFT JOIN Borrower ON Loan.borrower = Borrower.id
+    WHERE Loan.returned_at IS NULL AND Loan.due_date IS NOT NULL";
+
+/// Reservations the user made, for themselves or their borrowers, or every reservation without a user.
+/// Declined ones are left out
+pub async fn get_reservation_entries(pool: &SqlitePool, user: Option<u32>) -> Result<Vec<ReservationEntry>, sqlx::Error> {
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(RESERVATION_ENTRY_QUERY);
+    if let Some(user) = user {
+        query.push(" AND Reservation.user = ").push_bind(user);
+    }
+    query.push(" ORDER BY Reservation.start_date, Reservation.id");
+    query.build_query_as().fetch_all(pool).await
+}
+
+/// Due dates of the copies that are out with the user or their borrowers, or of all of them without a user
+pub async fn get_due_entries(pool: &SqlitePool, user: Option<u32>) -> Result<Vec<DueEntry>, sqlx::Error> {
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(DUE_ENTRY_QUERY);
+    if let Some(user) = user {
+        query.push(" AND Loan.user = ").push_bind(user);
+    }
+    query.push(" ORDER BY Loan.due_date, Loan.id");
+    query.build_query_as().fetch_all(pool).await
+}
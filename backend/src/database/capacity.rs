@@ -0,0 +1,112 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::types;
+
+#[derive(Serialize)]
+pub struct ShelfFill {
+    pub shelf: types::Shelf,
+    /// Usable width in centimetres, None when neither the shelf nor its bookcase has been measured
+    pub width: Option<f64>,
+    pub used: f64,
+    pub free: Option<f64>,
+    /// Share of the width in use, above 1 when overflowing
+    pub fill: Option<f64>,
+    pub overflowing: bool,
+    pub copies: u32,
+}
+
+#[derive(Serialize)]
+pub struct ShelfSuggestion {
+    pub shelf: types::Shelf,
+    pub free: f64,
+    /// Copies by the same authors that are already on the shelf
+    pub related_copies: u32,
+}
+
+#[derive(sqlx::FromRow)]
+struct ShelfWidthRow {
+    #[sqlx(flatten)]
+    shelf: types::Shelf,
+    width: Option<f64>,
+    bookcase_width: Option<f64>,
+    sections: Option<u32>,
+}
+
+#[derive(sqlx::FromRow)]
+struct CopySizeRow {
+    shelf: u32,
+    authors: String,
+    page_count: Option<u16>,
+    format: Option<types::CopyFormat>,
+    thickness: Option<f64>,
+}
+
+pub fn copy_thickness(page_count: Option<u16>, format: Option<types::CopyFormat>, thickness: Option<f64>) -> f64 {
+    thickness.unwrap_or_else(|| format.unwrap_or(types::CopyFormat::Paperback).spine_thickness(page_count))
+}
+
+async fn get_copy_sizes(pool: &SqlitePool) -> Result<Vec<CopySizeRow>, sqlx::Error> {
+    sqlx::query_as("
+        SELECT PhysicalBook.shelf, Book.authors, Book.page_count, PhysicalBook.format, PhysicalBook.thickness
+        FROM PhysicalBook
+        INNER JOIN Book ON PhysicalBook.book = Book.id").fetch_all(pool).await
+}
+
+pub async fn get_shelf_fills(pool: &SqlitePool) -> Result<Vec<ShelfFill>, sqlx::Error> {
+    // Shelves inside a measured bookcase get an equal share of its width
+    let shelves: Vec<ShelfWidthRow> = sqlx::query_as("
+        SELECT
+            Shelf.id,
+            Shelf.name,
+            Shelf.location,
+            ShelfPath.breadcrumb,
+            Shelf.width,
+            Location.width AS bookcase_width,
+            Location.sections
+        FROM Shelf
+        INNER JOIN ShelfPath ON Shelf.id = ShelfPath.id
+        LEFT JOIN Location ON Shelf.location = Location.id AND Location.kind = 'bookcase'
+        ORDER BY Shelf.name").fetch_all(pool).await?;
+    let copies = get_copy_sizes(pool).await?;
+
+    let mut fills: Vec<ShelfFill> = shelves.into_iter().map(|row| {
+        let width = row.width.or(row.bookcase_width.map(|width| width / row.sections.unwrap_or(1).max(1) as f64));
+        let on_shelf: Vec<&CopySizeRow> = copies.iter().filter(|copy| copy.shelf == row.shelf.id).collect();
+        let used: f64 = on_shelf.iter().map(|copy| copy_thickness(copy.page_count, copy.format, copy.thickness)).sum();
+        ShelfFill {
+            shelf: row.shelf,
+            width,
+            used,
+            free: width.map(|width| width - used),
+            fill: width.map(|width| used / width),
+            overflowing: width.is_some_and(|width| used > width),
+            copies: on_shelf.len() as u32,
+        }
+    }).collect();
+    fills.sort_by(|a, b| b.fill.unwrap_or(-1.0).total_cmp(&a.fill.unwrap_or(-1.0)));
+    Ok(fills)
+}
+
+/// Measured shelves with room for the copy, those holding the same authors first
+pub async fn suggest_shelves(pool: &SqlitePool, book: &types::Book, thickness: f64) -> Result<Vec<ShelfSuggestion>, sqlx::Error> {
+    let copies = get_copy_sizes(pool).await?;
+    let mut suggestions: Vec<ShelfSuggestion> = get_shelf_fills(pool).await?.into_iter()
+        .filter_map(|fill| {
+            let free = fill.free.filter(|free| *free >= thickness)?;
+            let related_copies = copies.iter()
+                .filter(|copy| copy.shelf == fill.shelf.id)
+                .filter(|copy| copy.authors.lines().any(|author| book.authors.iter().any(|a| a == author)))
+                .count() as u32;
+            Some(ShelfSuggestion { shelf: fill.shelf, free, related_copies })
+        })
+        .collect();
+    suggestions.sort_by(|a, b| b.related_copies.cmp(&a.related_copies).then(b.free.total_cmp(&a.free)));
+    Ok(suggestions)
+}
+
+pub async fn set_shelf_width(pool: &SqlitePool, shelf: u32, width: Option<f64>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE Shelf SET width = ? WHERE id = ?")
+        .bind(width).bind(shelf).execute(pool).await?;
+    Ok(())
+}
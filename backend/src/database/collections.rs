@@ -0,0 +1,167 @@
+use serde::Serialize;
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use time::UtcDateTime;
+
+use crate::{database::crud, types};
+
+#[derive(Serialize)]
+pub struct Collection {
+    pub id: u32,
+    pub name: String,
+    pub owner: types::User,
+    pub shared: bool,
+    pub filter: types::BookFilter,
+    pub created_at: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct CollectionRow {
+    #[sqlx(rename = "collection_id")]
+    id: u32,
+    name: String,
+    #[sqlx(flatten)]
+    owner: types::User,
+    shared: bool,
+    filter: String,
+    created_at: i64,
+}
+
+impl CollectionRow {
+    fn into_collection(self) -> Collection {
+        Collection {
+            id: self.id,
+            name: self.name,
+            owner: self.owner,
+            shared: self.shared,
+            // Filters are only ever written from a BookFilter, an unreadable one matches everything
+            filter: serde_json::from_str(&self.filter).unwrap_or_default(),
+            created_at: self.created_at,
+        }
+    }
+}
+
+const COLLECTION_QUERY: &str = "
+    SELECT Collection.id AS collection_id, Collection.name, User.id, User.username, User.personal_color, Collection.shared, Collection.filter, Collection.created_at
+    FROM Collection
+    INNER JOIN User ON Collection.owner = User.id";
+
+/// Quotes a value as an FTS5 phrase so it is matched literally
+fn fts_phrase(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/// Combines a search with the full-text parts of a filter into a single MATCH expression
+pub(crate) fn match_expression(search_str: Option<&str>, filter: Option<&types::BookFilter>) -> Option<String> {
+    let mut parts: Vec<String> = search_str.into_iter().map(|search| format!("({search})")).collect();
+    if let Some(filter) = filter {
+        parts.extend(filter.search.as_deref().map(|search| format!("({search})")));
+        parts.extend(filter.title.as_deref().map(|title| format!("title : {}", fts_phrase(title))));
+        parts.extend(filter.author.as_deref().map(|author| format!("authors : {}", fts_phrase(author))));
+        parts.extend(filter.genre.as_deref().map(|genre| format!("genres : {}", fts_phrase(genre))));
+    }
+    (!parts.is_empty()).then(|| parts.join(" AND "))
+}
+
+/// Pushes the conditions of a filter that are not full-text, each starting with AND
+pub(crate) fn push_filter(query: &mut QueryBuilder<Sqlite>, filter: &types::BookFilter) {
+    if let Some(series) = &filter.series {
+        query.push(" AND Book.series = ").push_bind(series.clone()).push(" COLLATE NOCASE");
+    }
+    if let Some(language) = &filter.language {
+        query.push(" AND Book.language = ").push_bind(language.clone()).push(" COLLATE NOCASE");
+    }
+    if let Some(year) = filter.published_after {
+        query.push(" AND Book.publication_year >= ").push_bind(year);
+    }
+    if let Some(year) = filter.published_before {
+        query.push(" AND Book.publication_year < ").push_bind(year);
+    }
+    if let Some(shelves) = &filter.shelves {
+        query.push(" AND EXISTS (SELECT 1 FROM PhysicalBook WHERE PhysicalBook.book = Book.id AND PhysicalBook.shelf IN (");
+        let mut separated = query.separated(", ");
+        for shelf in shelves {
+            separated.push_bind(*shelf);
+        }
+        // An empty list matches nothing rather than being a syntax error
+        if shelves.is_empty() {
+            separated.push("NULL");
+        }
+        query.push("))");
+    }
+    if let Some(location) = filter.location {
+        query.push("
+            AND EXISTS (
+                SELECT 1 FROM PhysicalBook
+                INNER JOIN Shelf ON PhysicalBook.shelf = Shelf.id
+                WHERE PhysicalBook.book = Book.id AND Shelf.location IN (
+                    WITH RECURSIVE Inside(id) AS (
+                        SELECT ")
+            .push_bind(location)
+            .push("
+                        UNION
+                        SELECT Location.id FROM Location INNER JOIN Inside ON Location.parent = Inside.id
+                    )
+                    SELECT id FROM Inside))");
+    }
+}
+
+pub async fn create_collection(
+    pool: &SqlitePool,
+    owner: u32,
+    name: &str,
+    shared: bool,
+    filter: &types::BookFilter,
+) -> Result<u32, sqlx::Error> {
+    sqlx::query_scalar("
+        INSERT INTO Collection (name, owner, shared, filter, created_at)
+        VALUES (?, ?, ?, ?, ?)
+        RETURNING id")
+        .bind(name)
+        .bind(owner)
+        .bind(shared)
+        .bind(serde_json::to_string(filter).unwrap_or_default())
+        .bind(UtcDateTime::now().unix_timestamp())
+        .fetch_one(pool).await
+}
+
+pub async fn get_collection(pool: &SqlitePool, id: u32) -> Result<Option<Collection>, sqlx::Error> {
+    let row: Option<CollectionRow> = sqlx::query_as(&format!("{COLLECTION_QUERY} WHERE Collection.id = ?"))
+        .bind(id).fetch_optional(pool).await?;
+    Ok(row.map(CollectionRow::into_collection))
+}
+
+/// The user's own collections and those shared by others
+pub async fn get_visible_collections(pool: &SqlitePool, user: u32) -> Result<Vec<Collection>, sqlx::Error> {
+    let rows: Vec<CollectionRow> = sqlx::query_as(&format!("{COLLECTION_QUERY}
+        WHERE Collection.owner = ? OR Collection.shared
+        ORDER BY Collection.owner != ?, Collection.name"))
+        .bind(user).bind(user).fetch_all(pool).await?;
+    Ok(rows.into_iter().map(CollectionRow::into_collection).collect())
+}
+
+pub async fn edit_collection(
+    pool: &SqlitePool,
+    id: u32,
+    name: Option<&str>,
+    shared: Option<bool>,
+    filter: Option<&types::BookFilter>,
+) -> Result<(), sqlx::Error> {
+    let mut qb = crud::update_builder("Collection", id, |sep| {
+        crud::apply_update(sep, "name", name.map(Some));
+        crud::apply_update(sep, "shared", shared.map(Some));
+        crud::apply_update(sep, "filter", filter.map(|filter| Some(serde_json::to_string(filter).unwrap_or_default())));
+    });
+    qb.build().execute(pool).await?;
+    Ok(())
+}
+
+pub async fn remove_collection(pool: &SqlitePool, id: u32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM Collection WHERE id = ?")
+        .bind(id).execute(pool).await?;
+    Ok(())
+}
+
+/// Every book currently matching the filter
+pub async fn get_collection_books(pool: &SqlitePool, filter: &types::BookFilter) -> Result<Vec<types::Book>, sqlx::Error> {
+    crud::query_books(pool, None, Some(filter), Some(u32::MAX), false).await
+}
@@ -4,13 +4,13 @@ use argon2::{
 };
 
 use serde::Serialize;
-use sqlx::{query_builder::Separated, QueryBuilder, Sqlite, SqlitePool};
+use sqlx::{query_builder::Separated, QueryBuilder, Sqlite, SqliteConnection, SqliteExecutor, SqlitePool};
 use time::{OffsetDateTime, UtcDateTime};
 
 use rand::{self, Rng};
 use uuid::Uuid;
 
-use crate::{routes, types};
+use crate::{auth, database::{borrowers, bulk, collections, history, holds, loans, policies}, routes, types};
 
 pub async fn get_physical_copies(
     pool: &SqlitePool,
@@ -54,6 +54,21 @@ async fn get_physical_book(
         return Ok(None);
     };
 
+    let copy_details: CopyDetailsIntermediate = sqlx::query_as(
+        "
+        SELECT code, owner, condition, acquired_at, purchase_price, currency, source, signed, first_edition, notes, format, thickness, color, position, requires_approval
+        FROM PhysicalBook
+        WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await?;
+
+    let owner = match copy_details.owner {
+        Some(owner) => Some(get_user(pool, owner).await?),
+        None => None,
+    };
+
     let mut reservations = vec![];
 
     if let Some(reservations_str) = physical_copy.1 {
@@ -69,11 +84,147 @@ async fn get_physical_book(
 
     Ok(Some(types::PhysicalBook {
         id,
+        code: copy_details.code.unwrap_or_default(),
         shelf,
+        owner,
+        details: copy_details.details,
         reservations,
     }))
 }
 
+#[derive(sqlx::FromRow)]
+struct CopyDetailsIntermediate {
+    code: Option<String>,
+    owner: Option<u32>,
+    #[sqlx(flatten)]
+    details: types::CopyDetails,
+}
+
+async fn edit_copy_details(
+    conn: &mut SqliteConnection,
+    id: u32,
+    details: routes::CopyDetailsForm,
+) -> Result<(), sqlx::Error> {
+    let mut qb = update_builder("PhysicalBook", id, |sep| {
+        apply_update(sep, "condition", details.condition);
+        apply_update(sep, "acquired_at", details.acquired_at);
+        apply_update(sep, "purchase_price", details.purchase_price);
+        apply_update(sep, "currency", details.currency);
+        apply_update(sep, "source", details.source);
+        apply_update(sep, "owner", details.owner);
+        apply_update(sep, "signed", details.signed.map(Some));
+        apply_update(sep, "first_edition", details.first_edition.map(Some));
+        apply_update(sep, "notes", details.notes);
+        apply_update(sep, "format", details.format);
+        apply_update(sep, "thickness", details.thickness);
+        apply_update(sep, "color", details.color);
+        apply_update(sep, "requires_approval", details.requires_approval.map(Some));
+    });
+    qb.build().execute(conn).await?;
+
+    Ok(())
+}
+
+/// Changes the details of a copy and moves it to the shelf, creating the shelf if needed.
+/// Either all of it is saved or nothing, returns the id of the new shelf
+pub async fn edit_physical_book(
+    pool: &SqlitePool,
+    id: u32,
+    details: routes::CopyDetailsForm,
+    new_shelf: Option<&str>,
+    user: Option<u32>,
+    reason: Option<&str>,
+) -> Result<Option<u32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    edit_copy_details(&mut tx, id, details).await?;
+    let shelf = match new_shelf {
+        Some(new_shelf) => {
+            let shelf = upsert_shelf(&mut tx, new_shelf).await?;
+            move_copy(&mut tx, id, shelf, user, reason).await?;
+            Some(shelf)
+        }
+        None => None,
+    };
+    tx.commit().await?;
+    Ok(shelf)
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct ValuedCopy {
+    pub copy_id: u32,
+    pub uuid: Uuid,
+    pub title: String,
+    pub shelf: String,
+    pub condition: Option<types::CopyCondition>,
+    pub purchase_price: Option<f64>,
+    pub currency: Option<String>,
+    pub signed: bool,
+    pub first_edition: bool,
+}
+
+#[derive(Serialize)]
+pub struct CurrencyTotal {
+    pub currency: Option<String>,
+    pub total: f64,
+    pub copies: u32,
+}
+
+#[derive(Serialize)]
+pub struct CollectionValue {
+    /// One total per currency since prices are never converted
+    pub totals: Vec<CurrencyTotal>,
+    pub unvalued_copies: u32,
+    pub copies: Vec<ValuedCopy>,
+}
+
+pub async fn get_collection_value(pool: &SqlitePool) -> Result<CollectionValue, sqlx::Error> {
+    let copies: Vec<ValuedCopy> = sqlx::query_as(
+        "
+        SELECT 
+            PhysicalBook.id AS copy_id,
+            Book.uuid,
+            Book.title,
+            Shelf.name AS shelf,
+            PhysicalBook.condition,
+            PhysicalBook.purchase_price,
+            PhysicalBook.currency,
+            PhysicalBook.signed,
+            PhysicalBook.first_edition
+        FROM PhysicalBook
+        INNER JOIN Book ON PhysicalBook.book = Book.id
+        INNER JOIN Shelf ON PhysicalBook.shelf = Shelf.id
+        ORDER BY PhysicalBook.purchase_price IS NULL, PhysicalBook.purchase_price DESC",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut totals: Vec<CurrencyTotal> = vec![];
+    let mut unvalued_copies = 0;
+    for copy in &copies {
+        let Some(price) = copy.purchase_price else {
+            unvalued_copies += 1;
+            continue;
+        };
+        match totals.iter_mut().find(|t| t.currency == copy.currency) {
+            Some(total) => {
+                total.total += price;
+                total.copies += 1;
+            }
+            None => totals.push(CurrencyTotal {
+                currency: copy.currency.clone(),
+                total: price,
+                copies: 1,
+            }),
+        }
+    }
+
+    Ok(CollectionValue {
+        totals,
+        unvalued_copies,
+        copies,
+    })
+}
+
 #[derive(Serialize)]
 pub struct BookReservation {
     pub uuid: Uuid,
@@ -129,75 +280,141 @@ pub async fn move_physical_book(
     pool: &SqlitePool,
     id: u32,
     new_shelf: &str,
+    user: Option<u32>,
+    reason: Option<&str>,
 ) -> Result<Option<u32>, sqlx::Error> {
     let shelf: Option<types::Shelf> = get_or_create_shelf(pool, new_shelf).await?;
     if let Some(shelf) = shelf {
-        sqlx::query(
-            "
-            UPDATE PhysicalBook
-            SET shelf = ?
-            WHERE id = ?",
-        )
-        .bind(shelf.id)
-        .bind(id)
-        .execute(pool)
-        .await?;
+        let mut tx = pool.begin().await?;
+        move_copy(&mut tx, id, shelf.id, user, reason).await?;
+        tx.commit().await?;
         return Ok(Some(shelf.id));
     }
     Ok(None)
 }
 
-pub async fn remove_physical_book(pool: &SqlitePool, id: u32) -> Result<(), sqlx::Error> {
-    let physical_book = get_physical_book(pool, id).await?;
-    if let Some(physical_book) = physical_book {
-        sqlx::query(
-            "
-            DELETE FROM PhysicalBook
-            WHERE id = ?",
-        )
+/// Moves a copy and logs it, returns false when it was missing or already on the shelf
+pub(crate) async fn move_copy(
+    conn: &mut SqliteConnection,
+    id: u32,
+    shelf: u32,
+    user: Option<u32>,
+    reason: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let old_shelf: Option<u32> = sqlx::query_scalar("SELECT shelf FROM PhysicalBook WHERE id = ?")
         .bind(id)
-        .execute(pool)
+        .fetch_optional(&mut *conn)
         .await?;
-        for reservation in physical_book.reservations {
-            // Separate deletions, may be slow, but this is on a small scale
-            remove_reservation(pool, reservation.id).await?;
-        }
+    if old_shelf.is_none_or(|old_shelf| old_shelf == shelf) {
+        return Ok(false);
     }
+    sqlx::query(
+        "
+        UPDATE PhysicalBook
+        SET shelf = ?, position = NULL
+        WHERE id = ?",
+    )
+    .bind(shelf)
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    history::record_move(conn, id, old_shelf, Some(shelf), user, reason).await?;
+    Ok(true)
+}
+
+pub async fn remove_physical_book(pool: &SqlitePool, id: u32, user: Option<u32>) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    bulk::delete_copies(&mut tx, &[id], user).await?;
+    tx.commit().await?;
     Ok(())
 }
 
-pub async fn reserve_physical_book(
-    pool: &SqlitePool,
-    user_id: u32,
+/// Whether nobody else has the copy for any of the time, leaving out the reservation `except`.
+/// Runs on the connection that is writing the reservation, so it sees its own changes
+async fn copy_is_free(
+    conn: &mut SqliteConnection,
     copy_id: u32,
+    user_id: u32,
     start_date: OffsetDateTime,
-    end_date: OffsetDateTime,
+    end_date: Option<OffsetDateTime>,
+    except: u32,
 ) -> Result<bool, sqlx::Error> {
-    let Some(physical_copy) = get_physical_book(pool, copy_id).await? else {
-        return Ok(false);
-    };
-    
-    if start_date.date() < OffsetDateTime::now_utc().date() {
+    let booked: Vec<(OffsetDateTime, Option<OffsetDateTime>)> = sqlx::query_as(
+        "
+        SELECT Reservation.start_date, Reservation.end_date
+        FROM Reservation
+        INNER JOIN BookReservationMatch ON Reservation.id = BookReservationMatch.reservation
+        WHERE BookReservationMatch.physical_book = ? AND Reservation.id != ? AND Reservation.status = 'approved'",
+    )
+    .bind(copy_id)
+    .bind(except)
+    .fetch_all(&mut *conn)
+    .await?;
+    if booked.into_iter().any(|(booked_start, booked_end)| types::spans_intersect(booked_start, booked_end, start_date, end_date)) {
         return Ok(false);
     }
 
-    for reservation in physical_copy.reservations {
-        if reservation.intersects(start_date, end_date) {
+    // Out with someone, also when it was lent ad hoc or is kept past its reservation.
+    // The reservation it was picked up for already covers it
+    if let Some(loan) = loans::get_active_loan(&mut *conn, copy_id).await? {
+        let (checked_out_at, back) = loan.busy_span();
+        if loan.reservation != Some(except) && types::spans_intersect(checked_out_at, back, start_date, end_date) {
             return Ok(false);
         }
     }
-    let now = UtcDateTime::now();
+
+    // Kept for the next user in line until they claim it
+    if let Some(offer) = holds::get_copy_offer(&mut *conn, copy_id).await? {
+        if let Some((offered_at, until)) = offer.offer_span() {
+            if offer.user.id != user_id && types::spans_intersect(offered_at, until, start_date, end_date) {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+pub enum Reserve {
+    Reserved(u32),
+    /// Waits for the approval of the copy's owner, who may still give the time to someone else
+    Requested(u32),
+    /// The copy is missing, someone else has it for some of the time or the reservation would start in the past
+    Unavailable,
+    /// The lending policy does not allow it
+    Refused(policies::Violation),
+}
+
+pub async fn reserve_physical_book(
+    pool: &SqlitePool,
+    user_id: u32,
+    borrower: Option<u32>,
+    copy_id: u32,
+    start_date: OffsetDateTime,
+    end_date: Option<OffsetDateTime>,
+) -> Result<Reserve, sqlx::Error> {
+    if start_date.date() < OffsetDateTime::now_utc().date() {
+        return Ok(Reserve::Unavailable);
+    }
+    let Some(status) = reservation_status(pool, copy_id, user_id).await? else {
+        return Ok(Reserve::Unavailable);
+    };
+
+    // Writing first takes the database's write lock, so concurrent reservations
+    // wait here and check for overlaps only once this one is committed or rolled back
+    let mut tx = pool.begin().await?;
     let reservation_id: u32 = sqlx::query_scalar(
         "
-        INSERT INTO Reservation (user, created_at, start_date, end_date)
-        VALUES (?, ?, ?, ?)
+        INSERT INTO Reservation (user, borrower, created_at, start_date, end_date, status)
+        VALUES (?, ?, ?, ?, ?, ?)
         RETURNING id",
     )
     .bind(user_id)
-    .bind(now.unix_timestamp())
+    .bind(borrower)
+    .bind(UtcDateTime::now().unix_timestamp())
     .bind(start_date)
     .bind(end_date)
-    .fetch_one(pool)
+    .bind(status)
+    .fetch_one(&mut *tx)
     .await?;
 
     sqlx::query(
@@ -207,21 +424,52 @@ pub async fn reserve_physical_book(
     )
     .bind(copy_id)
     .bind(reservation_id)
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
 
-    Ok(true)
+    if let Some(violation) = policies::check_reservation(&mut tx, reservation_id, copy_id, start_date, end_date).await? {
+        tx.rollback().await?;
+        return Ok(Reserve::Refused(violation));
+    }
+    if !copy_is_free(&mut tx, copy_id, user_id, start_date, end_date, reservation_id).await? {
+        tx.rollback().await?;
+        return Ok(Reserve::Unavailable);
+    }
+    tx.commit().await?;
+    Ok(match status {
+        types::ReservationStatus::Pending => Reserve::Requested(reservation_id),
+        _ => Reserve::Reserved(reservation_id),
+    })
+}
+
+/// What a new reservation of the copy by the user starts out as, None when there is no such copy
+async fn reservation_status(
+    executor: impl SqliteExecutor<'_>,
+    copy_id: u32,
+    user_id: u32,
+) -> Result<Option<types::ReservationStatus>, sqlx::Error> {
+    let copy: Option<(bool, Option<u32>)> = sqlx::query_as("SELECT requires_approval, owner FROM PhysicalBook WHERE id = ?")
+        .bind(copy_id)
+        .fetch_optional(executor)
+        .await?;
+    Ok(copy.map(|(requires_approval, owner)| match owner {
+        // A copy without an owner has nobody to approve
+        Some(owner) if requires_approval && owner != user_id => types::ReservationStatus::Pending,
+        _ => types::ReservationStatus::Approved,
+    }))
 }
 
 #[derive(sqlx::FromRow, serde::Serialize)]
 struct ReservationIntermediate {
     pub id: u32,
     pub user: u32,
+    pub borrower: Option<u32>,
     pub created_at: i64,
     #[serde(with = "time::serde::iso8601")]
     pub start_date: OffsetDateTime,
-    #[serde(with = "time::serde::iso8601")]
-    pub end_date: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601::option")]
+    pub end_date: Option<OffsetDateTime>,
+    pub status: types::ReservationStatus,
 }
 
 pub async fn get_reservation(
@@ -230,7 +478,7 @@ pub async fn get_reservation(
 ) -> Result<Option<types::Reservation>, sqlx::Error> {
     let reservation: Option<ReservationIntermediate> = sqlx::query_as(
         "
-        SELECT id, user, created_at, start_date, end_date
+        SELECT id, user, borrower, created_at, start_date, end_date, status
         FROM Reservation
         WHERE id = ?",
     )
@@ -241,16 +489,141 @@ pub async fn get_reservation(
         return Ok(None);
     };
     let user = get_user(pool, reservation.user).await?;
+    let borrower = match reservation.borrower {
+        Some(borrower) => borrowers::get_borrower(pool, borrower).await?,
+        None => None,
+    };
 
     Ok(Some(types::Reservation {
         id: reservation.id,
         user,
+        borrower,
         created_at: reservation.created_at,
         start_date: reservation.start_date,
         end_date: reservation.end_date,
+        status: reservation.status,
     }))
 }
 
+/// Moves a reservation to new dates and/or another copy in one go
+pub async fn edit_reservation(
+    pool: &SqlitePool,
+    reservation: &types::Reservation,
+    copy_id: u32,
+    start_date: OffsetDateTime,
+    end_date: Option<OffsetDateTime>,
+) -> Result<Reserve, sqlx::Error> {
+    // Same locking as in reserve_physical_book. The no-op update takes the write lock before
+    // anything is read, so the reservation is judged as it is now and not as the caller saw it
+    let mut tx = pool.begin().await?;
+    let current: Option<(types::ReservationStatus, OffsetDateTime, Option<OffsetDateTime>)> = sqlx::query_as(
+        "UPDATE Reservation SET id = id WHERE id = ? RETURNING status, start_date, end_date")
+        .bind(reservation.id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some((old_status, old_start_date, old_end_date)) = current else {
+        tx.rollback().await?;
+        return Ok(Reserve::Unavailable);
+    };
+    // A running reservation can still be extended
+    if start_date != old_start_date && start_date.date() < OffsetDateTime::now_utc().date() {
+        tx.rollback().await?;
+        return Ok(Reserve::Unavailable);
+    }
+    let old_copy: u32 = sqlx::query_scalar("SELECT physical_book FROM BookReservationMatch WHERE reservation = ?")
+        .bind(reservation.id)
+        .fetch_one(&mut *tx)
+        .await?;
+    let Some(mut status) = reservation_status(&mut *tx, copy_id, reservation.user.id).await? else {
+        tx.rollback().await?;
+        return Ok(Reserve::Unavailable);
+    };
+    // Giving back part of an approved time needs no new approval, anything else does
+    let within_approved = old_status == types::ReservationStatus::Approved
+        && copy_id == old_copy
+        && start_date >= old_start_date
+        && match (end_date, old_end_date) {
+            (_, None) => true,
+            (Some(end_date), Some(old_end_date)) => end_date <= old_end_date,
+            (None, Some(_)) => false,
+        };
+    if within_approved {
+        status = types::ReservationStatus::Approved;
+    }
+    sqlx::query("UPDATE Reservation SET start_date = ?, end_date = ?, status = ? WHERE id = ?")
+        .bind(start_date)
+        .bind(end_date)
+        .bind(status)
+        .bind(reservation.id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE BookReservationMatch SET physical_book = ? WHERE reservation = ?")
+        .bind(copy_id)
+        .bind(reservation.id)
+        .execute(&mut *tx)
+        .await?;
+    if let Some(violation) = policies::check_reservation(&mut tx, reservation.id, copy_id, start_date, end_date).await? {
+        tx.rollback().await?;
+        return Ok(Reserve::Refused(violation));
+    }
+    if !copy_is_free(&mut tx, copy_id, reservation.user.id, start_date, end_date, reservation.id).await? {
+        tx.rollback().await?;
+        return Ok(Reserve::Unavailable);
+    }
+    tx.commit().await?;
+    Ok(match status {
+        types::ReservationStatus::Pending => Reserve::Requested(reservation.id),
+        _ => Reserve::Reserved(reservation.id),
+    })
+}
+
+/// Reservations waiting for the owner's approval, oldest first
+pub async fn get_reservation_requests(pool: &SqlitePool, owner: u32) -> Result<Vec<BookReservation>, sqlx::Error> {
+    let ids: Vec<u32> = sqlx::query_scalar("
+        SELECT Reservation.id
+        FROM Reservation
+        INNER JOIN BookReservationMatch ON Reservation.id = BookReservationMatch.reservation
+        INNER JOIN PhysicalBook ON BookReservationMatch.physical_book = PhysicalBook.id
+        WHERE PhysicalBook.owner = ? AND Reservation.status = 'pending'
+        ORDER BY Reservation.created_at, Reservation.id")
+        .bind(owner)
+        .fetch_all(pool)
+        .await?;
+    let mut requests = vec![];
+    for id in ids {
+        if let Some(reservation) = get_reservation(pool, id).await? {
+            if let Some(request) = get_book_reservation(pool, reservation).await? {
+                requests.push(request);
+            }
+        }
+    }
+    Ok(requests)
+}
+
+/// Approves a pending reservation, Unavailable when an approved one got the time in the meantime
+pub async fn approve_reservation(pool: &SqlitePool, reservation: &types::Reservation, copy_id: u32) -> Result<Reserve, sqlx::Error> {
+    // Same locking as in reserve_physical_book
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE Reservation SET status = 'approved' WHERE id = ?")
+        .bind(reservation.id)
+        .execute(&mut *tx)
+        .await?;
+    if !copy_is_free(&mut tx, copy_id, reservation.user.id, reservation.start_date, reservation.end_date, reservation.id).await? {
+        tx.rollback().await?;
+        return Ok(Reserve::Unavailable);
+    }
+    tx.commit().await?;
+    Ok(Reserve::Reserved(reservation.id))
+}
+
+pub async fn decline_reservation(pool: &SqlitePool, id: u32) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE Reservation SET status = 'declined' WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
 pub async fn remove_reservation(pool: &SqlitePool, id: u32) -> Result<(), sqlx::Error> {
     sqlx::query(
         "
@@ -266,12 +639,14 @@ pub async fn remove_reservation(pool: &SqlitePool, id: u32) -> Result<(), sqlx::
 pub async fn get_shelves(pool: &SqlitePool) -> Result<Vec<types::Shelf>, sqlx::Error> {
     let shelves: Vec<types::Shelf> = sqlx::query_as(
         "
-        SELECT id, name FROM Shelf
+        SELECT Shelf.id, Shelf.name, Shelf.location, ShelfPath.breadcrumb
+        FROM Shelf
+        INNER JOIN ShelfPath ON Shelf.id = ShelfPath.id
         ",
     )
     .fetch_all(pool)
     .await?;
-    return Ok(shelves);
+    Ok(shelves)
 }
 
 pub async fn get_shelf(
@@ -282,9 +657,10 @@ pub async fn get_shelf(
     if let Some(id) = id {
         let shelf: Option<types::Shelf> = sqlx::query_as(
             "
-            SELECT id, name 
-            FROM Shelf 
-            WHERE id = ?",
+            SELECT Shelf.id, Shelf.name, Shelf.location, ShelfPath.breadcrumb
+            FROM Shelf
+            INNER JOIN ShelfPath ON Shelf.id = ShelfPath.id
+            WHERE Shelf.id = ?",
         )
         .bind(id)
         .fetch_optional(pool)
@@ -294,57 +670,318 @@ pub async fn get_shelf(
     if let Some(name) = name {
         let shelf: Option<types::Shelf> = sqlx::query_as(
             "
-            SELECT id, name 
-            FROM Shelf 
-            WHERE name = ?",
+            SELECT Shelf.id, Shelf.name, Shelf.location, ShelfPath.breadcrumb
+            FROM Shelf
+            INNER JOIN ShelfPath ON Shelf.id = ShelfPath.id
+            WHERE Shelf.name = ?",
         )
         .bind(name)
         .fetch_optional(pool)
         .await?;
         return Ok(shelf);
     }
-    return Ok(None);
+    Ok(None)
 }
 
 pub async fn get_or_create_shelf(
     pool: &SqlitePool,
     name: &str,
 ) -> Result<Option<types::Shelf>, sqlx::Error> {
-    let shelf_id: Option<u32> = sqlx::query_scalar(
+    let mut conn = pool.acquire().await?;
+    let shelf_id = upsert_shelf(&mut conn, name).await?;
+    get_shelf(pool, Some(shelf_id), None).await
+}
+
+/// Id of the shelf with the name, created when there is none yet
+pub(crate) async fn upsert_shelf(conn: &mut SqliteConnection, name: &str) -> Result<u32, sqlx::Error> {
+    sqlx::query_scalar(
         "
         INSERT INTO Shelf (name) VALUES (?)
         ON CONFLICT(name) DO UPDATE SET name=name
         RETURNING id",
     )
     .bind(name)
-    .fetch_optional(pool)
-    .await?;
-    if let Some(id) = shelf_id {
-        return Ok(Some(types::Shelf {
-            id,
-            name: name.to_string(),
-        }));
-    }
-    Ok(None)
+    .fetch_one(conn)
+    .await
+}
+
+fn new_copy_code() -> Result<String, sqlx::Error> {
+    auth::gen_copy_code().ok_or_else(|| sqlx::Error::Io(std::io::Error::other("Could not generate copy code")))
 }
 
 pub async fn create_physical_book(
     pool: &SqlitePool,
     book: u32,
     shelf: u32,
-) -> Result<(), sqlx::Error> {
-    sqlx::query(
+    user: Option<u32>,
+) -> Result<u32, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let id = insert_physical_book(&mut tx, book, shelf, user).await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+pub(crate) async fn insert_physical_book(
+    conn: &mut SqliteConnection,
+    book: u32,
+    shelf: u32,
+    user: Option<u32>,
+) -> Result<u32, sqlx::Error> {
+    // Codes are random, retry in the unlikely case of a collision
+    let mut attempts = 0;
+    loop {
+        let code = new_copy_code()?;
+        let result = sqlx::query_scalar(
+            "
+            INSERT INTO PhysicalBook (book, shelf, code)
+            VALUES (?, ?, ?)
+            RETURNING id",
+        )
+        .bind(book)
+        .bind(shelf)
+        .bind(code)
+        .fetch_one(&mut *conn)
+        .await;
+        match result {
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() && attempts < 5 => attempts += 1,
+            Err(err) => return Err(err),
+            Ok(id) => {
+                history::record_move(conn, id, None, Some(shelf), user, None).await?;
+                return Ok(id);
+            }
+        }
+    }
+}
+
+/// Gives a code to copies created before codes existed
+pub async fn assign_missing_copy_codes(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let copy_ids: Vec<u32> = sqlx::query_scalar("SELECT id FROM PhysicalBook WHERE code IS NULL")
+        .fetch_all(pool)
+        .await?;
+    for copy_id in copy_ids {
+        loop {
+            let code = new_copy_code()?;
+            let result = sqlx::query("UPDATE PhysicalBook SET code = ? WHERE id = ?")
+                .bind(code)
+                .bind(copy_id)
+                .execute(pool)
+                .await;
+            match result {
+                Err(sqlx::Error::Database(err)) if err.is_unique_violation() => continue,
+                result => {
+                    result?;
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Id of the copy with the given id or code, if it exists
+pub async fn resolve_copy(pool: &SqlitePool, copy: &types::CopyRef) -> Result<Option<u32>, sqlx::Error> {
+    match copy {
+        types::CopyRef::Id(id) => {
+            sqlx::query_scalar("SELECT id FROM PhysicalBook WHERE id = ?")
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+        }
+        types::CopyRef::Code(code) => {
+            let Some(code) = auth::normalize_copy_code(code) else {
+                return Ok(None);
+            };
+            sqlx::query_scalar("SELECT id FROM PhysicalBook WHERE code = ?")
+                .bind(code)
+                .fetch_optional(pool)
+                .await
+        }
+    }
+}
+
+pub async fn get_copy_with_book(
+    pool: &SqlitePool,
+    copy_id: u32,
+) -> Result<Option<(types::Book, types::PhysicalBook)>, sqlx::Error> {
+    let Some(uuid) = get_copy_book_uuid(pool, copy_id).await? else {
+        return Ok(None);
+    };
+    let book = get_book(pool, None, Some(uuid)).await?;
+    Ok(get_physical_book(pool, copy_id).await?.map(|copy| (book, copy)))
+}
+
+#[derive(sqlx::FromRow)]
+pub struct LabelCopy {
+    pub copy_id: u32,
+    pub code: String,
+    pub title: String,
+    pub shelf: String,
+}
+
+/// Copies matching every given filter, in shelf and title order
+pub async fn get_label_copies(
+    pool: &SqlitePool,
+    shelf: Option<&str>,
+    book: Option<Uuid>,
+    copy_ids: &[u32],
+    only_unlabeled: bool,
+) -> Result<Vec<LabelCopy>, sqlx::Error> {
+    let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new("
+        SELECT PhysicalBook.id AS copy_id, PhysicalBook.code, Book.title, Shelf.name AS shelf
+        FROM PhysicalBook
+        INNER JOIN Book ON PhysicalBook.book = Book.id
+        INNER JOIN Shelf ON PhysicalBook.shelf = Shelf.id
+        WHERE 1 = 1");
+    if let Some(shelf) = shelf {
+        qb.push(" AND Shelf.name = ").push_bind(shelf);
+    }
+    if let Some(book) = book {
+        qb.push(" AND Book.uuid = ").push_bind(book);
+    }
+    if !copy_ids.is_empty() {
+        qb.push(" AND PhysicalBook.id IN (");
+        let mut sep = qb.separated(", ");
+        for id in copy_ids {
+            sep.push_bind(*id);
+        }
+        sep.push_unseparated(")");
+    }
+    if only_unlabeled {
+        qb.push(" AND PhysicalBook.labeled_at IS NULL");
+    }
+    qb.push(" ORDER BY Shelf.name, Book.title, PhysicalBook.id");
+
+    qb.build_query_as().fetch_all(pool).await
+}
+
+pub async fn mark_copies_labeled(pool: &SqlitePool, copy_ids: &[u32]) -> Result<u64, sqlx::Error> {
+    if copy_ids.is_empty() {
+        return Ok(0);
+    }
+    let now = UtcDateTime::now();
+    let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new("UPDATE PhysicalBook SET labeled_at = ");
+    qb.push_bind(now.unix_timestamp()).push(" WHERE id IN (");
+    let mut sep = qb.separated(", ");
+    for id in copy_ids {
+        sep.push_bind(*id);
+    }
+    sep.push_unseparated(")");
+    let result = qb.build().execute(pool).await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn get_copy_book_uuid(pool: &SqlitePool, copy_id: u32) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar(
         "
-        INSERT INTO PhysicalBook (book, shelf)
-        VALUES (?, ?)",
+        SELECT Book.uuid
+        FROM PhysicalBook
+        INNER JOIN Book ON PhysicalBook.book = Book.id
+        WHERE PhysicalBook.id = ?",
+    )
+    .bind(copy_id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn insert_ebook(
+    pool: &SqlitePool,
+    book: u32,
+    file_name: &str,
+    media_type: &str,
+    size: i64,
+) -> Result<u32, sqlx::Error> {
+    let now = UtcDateTime::now();
+    let ebook_id: u32 = sqlx::query_scalar(
+        "
+        INSERT INTO EbookFile (book, file_name, media_type, size, added_at)
+        VALUES (?, ?, ?, ?, ?)
+        RETURNING id",
     )
     .bind(book)
-    .bind(shelf)
+    .bind(file_name)
+    .bind(media_type)
+    .bind(size)
+    .bind(now.unix_timestamp())
+    .fetch_one(pool)
+    .await?;
+    Ok(ebook_id)
+}
+
+pub async fn get_ebook(pool: &SqlitePool, id: u32) -> Result<Option<types::Ebook>, sqlx::Error> {
+    let ebook: Option<types::Ebook> = sqlx::query_as(
+        "
+        SELECT id, book, file_name, media_type, size, added_at
+        FROM EbookFile
+        WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(ebook)
+}
+
+pub async fn get_book_ebooks(pool: &SqlitePool, book: u32) -> Result<Vec<types::Ebook>, sqlx::Error> {
+    let ebooks: Vec<types::Ebook> = sqlx::query_as(
+        "
+        SELECT id, book, file_name, media_type, size, added_at
+        FROM EbookFile
+        WHERE book = ?
+        ORDER BY id",
+    )
+    .bind(book)
+    .fetch_all(pool)
+    .await?;
+    Ok(ebooks)
+}
+
+pub async fn remove_ebook(pool: &SqlitePool, id: u32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        DELETE FROM EbookFile
+        WHERE id = ?",
+    )
+    .bind(id)
     .execute(pool)
     .await?;
     Ok(())
 }
 
+/// Books that have at least one ebook file, most recently added first.
+/// Optionally limited to books with a physical copy on the given shelf.
+pub async fn get_books_with_ebooks(
+    pool: &SqlitePool,
+    shelf: Option<u32>,
+) -> Result<Vec<types::Book>, sqlx::Error> {
+    let books: Vec<BookIntermediate> = sqlx::query_as(
+        "
+        SELECT 
+            Book.id as id,
+            Book.uuid,
+            Book.isbn,
+            Book.title,
+            Book.authors,
+            Book.genres,
+            Book.publication_year,
+            Book.page_count,
+            Book.language,
+            Book.series,
+            Book.series_index,
+            GROUP_CONCAT(DISTINCT PhysicalBook.id) as copies
+        FROM Book
+        LEFT JOIN PhysicalBook ON Book.id = PhysicalBook.book
+        WHERE EXISTS (SELECT 1 FROM EbookFile WHERE EbookFile.book = Book.id)
+            AND (? IS NULL OR Book.id IN (SELECT book FROM PhysicalBook WHERE shelf = ?))
+        GROUP BY Book.id
+        ORDER BY (SELECT MAX(added_at) FROM EbookFile WHERE EbookFile.book = Book.id) DESC",
+    )
+    .bind(shelf)
+    .bind(shelf)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(books.into_iter().map(|b| b.to_book()).collect())
+}
+
 pub async fn insert_book(pool: &SqlitePool, book: routes::BookForm) -> Result<Option<Uuid>, sqlx::Error> {
     let Some(title) = book.title else {
         return Ok(None);
@@ -354,8 +991,8 @@ pub async fn insert_book(pool: &SqlitePool, book: routes::BookForm) -> Result<Op
     };
     let uuid = Uuid::new_v4();
     sqlx::query("
-        INSERT INTO Book (uuid, isbn, title, authors, genres, publication_year, page_count, language)
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?)",)
+        INSERT INTO Book (uuid, isbn, title, authors, genres, publication_year, page_count, language, series, series_index)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",)
     .bind(uuid)
     .bind(book.isbn.flatten())
     .bind(title)
@@ -363,7 +1000,9 @@ pub async fn insert_book(pool: &SqlitePool, book: routes::BookForm) -> Result<Op
     .bind(book.genres.flatten())
     .bind(book.publication_year.flatten())
     .bind(book.page_count.flatten())
-    .bind(book.language.flatten()).execute(pool).await?;
+    .bind(book.language.flatten())
+    .bind(book.series.flatten())
+    .bind(book.series_index.flatten()).execute(pool).await?;
 
     Ok(Some(uuid))
 }
@@ -379,6 +1018,8 @@ pub async fn edit_book(pool: &SqlitePool, uuid: Uuid, book: routes::BookForm) ->
     apply_update(&mut sep, "publication_year", book.publication_year);
     apply_update(&mut sep, "page_count", book.page_count);
     apply_update(&mut sep, "language", book.language);
+    apply_update(&mut sep, "series", book.series);
+    apply_update(&mut sep, "series_index", book.series_index);
     
     sep.push_unseparated(" WHERE uuid = ").push_bind_unseparated(uuid);
     
@@ -387,16 +1028,40 @@ pub async fn edit_book(pool: &SqlitePool, uuid: Uuid, book: routes::BookForm) ->
     Ok(())
 }
 
-pub async fn delete_book(pool: &SqlitePool, uuid: Uuid) -> Result<(), sqlx::Error> {
+pub async fn delete_book(pool: &SqlitePool, uuid: Uuid, user: Option<u32>) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // The copies go with the book, their location log stays
+    let copy_ids: Vec<u32> = sqlx::query_scalar("
+        SELECT PhysicalBook.id
+        FROM PhysicalBook
+        INNER JOIN Book ON PhysicalBook.book = Book.id
+        WHERE Book.uuid = ?").bind(uuid).fetch_all(&mut *tx).await?;
+    bulk::delete_copies(&mut tx, &copy_ids, user).await?;
     sqlx::query("
         DELETE FROM Book
         WHERE uuid = ?
-        ").bind(uuid).execute(pool).await?;
+        ").bind(uuid).execute(&mut *tx).await?;
+    tx.commit().await?;
     Ok(())
 }
 
+/// `UPDATE table SET ... WHERE id = ?` with whichever columns `set` adds, possibly none
+pub(crate) fn update_builder<'v>(
+    table: &str,
+    id: u32,
+    set: impl FnOnce(&mut Separated<'_, 'v, Sqlite, &'static str>),
+) -> QueryBuilder<'v, Sqlite> {
+    let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(format!("UPDATE {table} SET "));
+    let mut sep = qb.separated(", ");
+    // Keeps the statement valid when nothing is changed
+    sep.push("id = id");
+    set(&mut sep);
+    sep.push_unseparated(" WHERE id = ").push_bind_unseparated(id);
+    qb
+}
+
 // Lifetimes are weird
-fn apply_update<'sep, 'v, T>(
+pub(crate) fn apply_update<'sep, 'v, T>(
     sep: &mut Separated<'sep, 'v, Sqlite, &str>,
     column: &str,
     option: Option<Option<T>>,
@@ -426,6 +1091,8 @@ struct BookIntermediate {
     publication_year: Option<i16>,
     page_count: Option<u16>,
     language: Option<String>,
+    series: Option<String>,
+    series_index: Option<f64>,
     copies: Option<String>
 }
 
@@ -444,6 +1111,8 @@ impl BookIntermediate {
             publication_year: self.publication_year,
             page_count: self.page_count,
             language: self.language.clone(),
+            series: self.series.clone(),
+            series_index: self.series_index,
             copy_ids: match &self.copies {
                 Some(s) => s
                     .split(",")
@@ -467,6 +1136,8 @@ pub async fn get_book(pool: &SqlitePool, isbn: Option<&str>, uuid: Option<Uuid>)
             Book.publication_year,
             Book.page_count,
             Book.language,
+            Book.series,
+            Book.series_index,
             GROUP_CONCAT(DISTINCT PhysicalBook.id) as copies
         FROM Book
         LEFT JOIN PhysicalBook ON Book.id = PhysicalBook.book
@@ -480,19 +1151,28 @@ pub async fn get_book(pool: &SqlitePool, isbn: Option<&str>, uuid: Option<Uuid>)
 pub async fn query_books(
     pool: &SqlitePool,
     search_str: Option<&str>,
+    filter: Option<&types::BookFilter>,
     limit: Option<u32>,
     only_physical: bool,
 ) -> Result<Vec<types::Book>, sqlx::Error> {
-    let sq = format!("
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("
         WITH RankedBooks AS (
             SELECT 
                 *,
                 bm25(BookFts, 0, 8, 4, 2) AS rank
             FROM Book
             INNER JOIN BookFts ON BookFts.book_id = Book.id
-            {}
+            WHERE TRUE");
+    if let Some(expression) = collections::match_expression(search_str, filter) {
+        query.push(" AND BookFts MATCH ").push_bind(expression);
+    }
+    if let Some(filter) = filter {
+        collections::push_filter(&mut query, filter);
+    }
+    query.push("
             ORDER BY rank
-            LIMIT ?
+            LIMIT ").push_bind(limit.unwrap_or(20));
+    query.push(format!("
         )
         SELECT 
             RankedBooks.id,
@@ -504,31 +1184,25 @@ pub async fn query_books(
             RankedBooks.publication_year,
             RankedBooks.page_count,
             RankedBooks.language,
+            RankedBooks.series,
+            RankedBooks.series_index,
             GROUP_CONCAT(DISTINCT PhysicalBook.id) AS copies
         FROM RankedBooks
         {}JOIN PhysicalBook ON PhysicalBook.book = RankedBooks.id
         GROUP BY RankedBooks.id
-        ORDER BY MIN(RankedBooks.rank);
+        ORDER BY MIN(RankedBooks.rank), RankedBooks.title;
         ",
-        match search_str {
-           Some(_) => "WHERE BookFts MATCH ?",
-           None => ""
-        },
         match only_physical {
             true => "",
             false => "LEFT ",
         }
-    );
-    let mut query = sqlx::query_as(&sq);
-    if let Some(search_str) = search_str {
-        query = query.bind(search_str);
-    }
-    let books: Vec<BookIntermediate> = query.bind(limit.unwrap_or(20)).fetch_all(pool).await?;
+    ));
+    let books: Vec<BookIntermediate> = query.build_query_as().fetch_all(pool).await?;
 
-    return Ok(books
+    Ok(books
         .into_iter()
         .map(|b| b.to_book())
-        .collect());
+        .collect())
 }
 
 pub async fn search_suggestions(
@@ -558,23 +1232,30 @@ pub async fn get_user_reservations(
     let user = get_user(pool, user_id).await?;
     let reservations: Vec<ReservationIntermediate> = sqlx::query_as(
         "
-        SELECT id, user, created_at, start_date, end_date
+        SELECT id, user, borrower, created_at, start_date, end_date, status
         FROM Reservation
         WHERE user = ?",
     )
     .bind(user_id)
     .fetch_all(pool)
     .await?;
-    Ok(reservations
-        .iter()
-        .map(|rsv| types::Reservation {
+    let mut user_reservations = vec![];
+    for rsv in reservations {
+        let borrower = match rsv.borrower {
+            Some(borrower) => borrowers::get_borrower(pool, borrower).await?,
+            None => None,
+        };
+        user_reservations.push(types::Reservation {
             id: rsv.id,
             user: user.clone(),
+            borrower,
             created_at: rsv.created_at,
             start_date: rsv.start_date,
             end_date: rsv.end_date,
-        })
-        .collect())
+            status: rsv.status,
+        });
+    }
+    Ok(user_reservations)
 }
 
 pub async fn get_user(pool: &SqlitePool, id: u32) -> Result<types::User, sqlx::Error> {
@@ -590,6 +1271,21 @@ pub async fn get_user(pool: &SqlitePool, id: u32) -> Result<types::User, sqlx::E
     Ok(user)
 }
 
+pub async fn is_admin(pool: &SqlitePool, id: u32) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT is_admin FROM User WHERE id = ?")
+        .bind(id).fetch_one(pool).await
+}
+
+/// Grants or revokes admin rights, false when that would leave no admin at all
+pub async fn set_admin(pool: &SqlitePool, id: u32, admin: bool) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("
+        UPDATE User SET is_admin = ?
+        WHERE id = ? AND (? OR EXISTS (SELECT 1 FROM User WHERE is_admin AND id != ?))")
+        .bind(admin).bind(id).bind(admin).bind(id)
+        .execute(pool).await?;
+    Ok(result.rows_affected() == 1)
+}
+
 pub async fn login_user(
     pool: &SqlitePool,
     username: &str,
@@ -628,8 +1324,8 @@ pub async fn register_user(
     if let Ok(password_hash) = Argon2::default().hash_password(password.as_bytes(), &salt) {
         let user_id: Option<u32> = sqlx::query_scalar(
             "
-            INSERT INTO User (username, password_hash, personal_color)
-            VALUES (?, ?, ?)
+            INSERT INTO User (username, password_hash, personal_color, is_admin)
+            VALUES (?, ?, ?, NOT EXISTS (SELECT 1 FROM User WHERE is_admin))
             ON CONFLICT (username) DO NOTHING
             RETURNING id",
         )
@@ -0,0 +1,154 @@
+use serde::Serialize;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::UtcDateTime;
+
+use crate::types;
+
+#[derive(Serialize)]
+pub struct CopyMove {
+    pub id: u32,
+    /// None once the copy was removed
+    pub copy_id: Option<u32>,
+    pub code: String,
+    pub title: String,
+    /// None when the copy was first added
+    pub from_shelf: Option<types::Shelf>,
+    /// None when the copy was removed
+    pub to_shelf: Option<types::Shelf>,
+    pub user: Option<types::User>,
+    pub moved_at: i64,
+    pub reason: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct CopyMoveRow {
+    id: u32,
+    copy_id: Option<u32>,
+    code: String,
+    title: String,
+    from_shelf_id: Option<u32>,
+    from_shelf: Option<String>,
+    from_shelf_location: Option<u32>,
+    from_shelf_breadcrumb: Option<String>,
+    to_shelf_id: Option<u32>,
+    to_shelf: Option<String>,
+    to_shelf_location: Option<u32>,
+    to_shelf_breadcrumb: Option<String>,
+    user_id: Option<u32>,
+    username: Option<String>,
+    personal_color: Option<String>,
+    moved_at: i64,
+    reason: Option<String>,
+}
+
+impl From<CopyMoveRow> for CopyMove {
+    fn from(row: CopyMoveRow) -> Self {
+        let shelf = |id: Option<u32>, name: Option<String>, location: Option<u32>, breadcrumb: Option<String>| {
+            Some(types::Shelf { id: id?, name: name?, location, breadcrumb: breadcrumb? })
+        };
+        let user = match (row.user_id, row.username, row.personal_color) {
+            (Some(id), Some(username), Some(personal_color)) => Some(types::User { id, username, personal_color }),
+            _ => None,
+        };
+        CopyMove {
+            id: row.id,
+            copy_id: row.copy_id,
+            code: row.code,
+            title: row.title,
+            from_shelf: shelf(row.from_shelf_id, row.from_shelf, row.from_shelf_location, row.from_shelf_breadcrumb),
+            to_shelf: shelf(row.to_shelf_id, row.to_shelf, row.to_shelf_location, row.to_shelf_breadcrumb),
+            user,
+            moved_at: row.moved_at,
+            reason: row.reason,
+        }
+    }
+}
+
+const COPY_MOVE_QUERY: &str = "
+    SELECT
+        CopyMove.id,
+        CopyMove.copy AS copy_id,
+        COALESCE(PhysicalBook.code, CopyMove.code) AS code,
+        COALESCE(Book.title, CopyMove.title) AS title,
+        FromShelf.id AS from_shelf_id,
+        FromShelf.name AS from_shelf,
+        FromShelf.location AS from_shelf_location,
+        FromShelfPath.breadcrumb AS from_shelf_breadcrumb,
+        ToShelf.id AS to_shelf_id,
+        ToShelf.name AS to_shelf,
+        ToShelf.location AS to_shelf_location,
+        ToShelfPath.breadcrumb AS to_shelf_breadcrumb,
+        User.id AS user_id,
+        User.username,
+        User.personal_color,
+        CopyMove.moved_at,
+        CopyMove.reason
+    FROM CopyMove
+    LEFT JOIN PhysicalBook ON CopyMove.copy = PhysicalBook.id
+    LEFT JOIN Book ON PhysicalBook.book = Book.id
+    LEFT JOIN Shelf AS FromShelf ON CopyMove.from_shelf = FromShelf.id
+    LEFT JOIN ShelfPath AS FromShelfPath ON CopyMove.from_shelf = FromShelfPath.id
+    LEFT JOIN Shelf AS ToShelf ON CopyMove.to_shelf = ToShelf.id
+    LEFT JOIN ShelfPath AS ToShelfPath ON CopyMove.to_shelf = ToShelfPath.id
+    LEFT JOIN User ON CopyMove.user = User.id";
+
+/// Appends to the location log, meant to run in the same transaction as the move itself.
+/// The code and title are kept so the entry outlives the copy
+pub async fn record_move(
+    conn: &mut SqliteConnection,
+    copy_id: u32,
+    from_shelf: Option<u32>,
+    to_shelf: Option<u32>,
+    user: Option<u32>,
+    reason: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("
+        INSERT INTO CopyMove (copy, code, title, from_shelf, to_shelf, user, moved_at, reason)
+        SELECT PhysicalBook.id, PhysicalBook.code, Book.title, ?, ?, ?, ?, ?
+        FROM PhysicalBook
+        INNER JOIN Book ON PhysicalBook.book = Book.id
+        WHERE PhysicalBook.id = ?")
+        .bind(from_shelf)
+        .bind(to_shelf)
+        .bind(user)
+        .bind(UtcDateTime::now().unix_timestamp())
+        .bind(reason.map(str::trim).filter(|r| !r.is_empty()))
+        .bind(copy_id)
+        .execute(conn).await?;
+    Ok(())
+}
+
+/// Logs the copy leaving its shelf for good, has to run before the copy is deleted
+pub async fn record_removal(conn: &mut SqliteConnection, copy_id: u32, user: Option<u32>) -> Result<(), sqlx::Error> {
+    let shelf: Option<u32> = sqlx::query_scalar("SELECT shelf FROM PhysicalBook WHERE id = ?")
+        .bind(copy_id).fetch_optional(&mut *conn).await?;
+    if let Some(shelf) = shelf {
+        record_move(conn, copy_id, Some(shelf), None, user, Some("Removed")).await?;
+    }
+    Ok(())
+}
+
+/// Every recorded location of a copy, newest first
+pub async fn get_copy_history(pool: &SqlitePool, copy_id: u32) -> Result<Vec<CopyMove>, sqlx::Error> {
+    let rows: Vec<CopyMoveRow> = sqlx::query_as(&format!("{COPY_MOVE_QUERY}
+        WHERE CopyMove.copy = ?
+        ORDER BY CopyMove.id DESC")).bind(copy_id).fetch_all(pool).await?;
+    Ok(rows.into_iter().map(CopyMove::from).collect())
+}
+
+/// Every recorded location of a removed copy, which is only known by its code, newest first
+pub async fn get_removed_copy_history(pool: &SqlitePool, code: &str) -> Result<Vec<CopyMove>, sqlx::Error> {
+    let rows: Vec<CopyMoveRow> = sqlx::query_as(&format!("{COPY_MOVE_QUERY}
+        WHERE CopyMove.copy IS NULL AND CopyMove.code = ?
+        ORDER BY CopyMove.id DESC")).bind(code).fetch_all(pool).await?;
+    Ok(rows.into_iter().map(CopyMove::from).collect())
+}
+
+/// Copies recently moved onto or off a shelf, newest first
+pub async fn get_shelf_moves(pool: &SqlitePool, shelf_id: u32, limit: u32) -> Result<Vec<CopyMove>, sqlx::Error> {
+    let rows: Vec<CopyMoveRow> = sqlx::query_as(&format!("{COPY_MOVE_QUERY}
+        WHERE CopyMove.from_shelf = ? OR CopyMove.to_shelf = ?
+        ORDER BY CopyMove.id DESC
+        LIMIT ?")).bind(shelf_id).bind(shelf_id).bind(limit).fetch_all(pool).await?;
+    Ok(rows.into_iter().map(CopyMove::from).collect())
+}
@@ -0,0 +1,211 @@
+use serde::Serialize;
+use sqlx::{SqliteExecutor, SqlitePool};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{database::crud, types};
+
+/// How long an offered copy is kept for the next user in line
+pub const CLAIM_WINDOW: Duration = Duration::hours(48);
+pub const DEFAULT_HOLD_DAYS: u32 = 14;
+pub const MAX_HOLD_DAYS: u32 = 365;
+
+#[derive(sqlx::Type, Serialize, Debug, Clone, Copy, PartialEq)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum HoldStatus {
+    Waiting,
+    /// A copy is kept for the user until the offer expires
+    Offered,
+    Claimed,
+    Expired,
+    Cancelled,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct Hold {
+    #[sqlx(rename = "hold_id")]
+    pub id: u32,
+    pub book_uuid: Uuid,
+    pub title: String,
+    #[sqlx(flatten)]
+    pub user: types::User,
+    pub days: u32,
+    pub created_at: i64,
+    pub status: HoldStatus,
+    /// Place in line counting from 1, only while waiting
+    pub position: Option<u32>,
+    pub offered_copy: Option<u32>,
+    pub offered_code: Option<String>,
+    pub offered_at: Option<i64>,
+    pub offer_expires_at: Option<i64>,
+    pub reservation: Option<u32>,
+}
+
+impl Hold {
+    /// From the offer until the copy would be back if it is claimed at the last moment,
+    /// open ended when that is too far ahead to represent
+    pub fn offer_span(&self) -> Option<(OffsetDateTime, Option<OffsetDateTime>)> {
+        let offered_at = OffsetDateTime::from_unix_timestamp(self.offered_at?).ok()?;
+        let expires_at = OffsetDateTime::from_unix_timestamp(self.offer_expires_at?).ok()?;
+        Some((offered_at, expires_at.checked_add(Duration::days(self.days as i64))))
+    }
+}
+
+// Holds are queued by id, which follows the order they were placed in
+const HOLD_QUERY: &str = "
+    SELECT
+        Hold.id AS hold_id,
+        Book.uuid AS book_uuid,
+        Book.title,
+        User.id,
+        User.username,
+        User.personal_color,
+        Hold.days,
+        Hold.created_at,
+        Hold.status,
+        CASE WHEN Hold.status = 'waiting' THEN (
+            SELECT COUNT(*) FROM Hold AS Ahead
+            WHERE Ahead.book = Hold.book AND Ahead.status = 'waiting' AND Ahead.id <= Hold.id
+        ) END AS position,
+        Hold.offered_copy,
+        PhysicalBook.code AS offered_code,
+        Hold.offered_at,
+        Hold.offer_expires_at,
+        Hold.reservation
+    FROM Hold
+    INNER JOIN Book ON Hold.book = Book.id
+    INNER JOIN User ON Hold.user = User.id
+    LEFT JOIN PhysicalBook ON Hold.offered_copy = PhysicalBook.id";
+
+/// None when the user already has a place in line for the book
+pub async fn place_hold(pool: &SqlitePool, book: u32, user: u32, days: u32) -> Result<Option<u32>, sqlx::Error> {
+    let inserted = sqlx::query_scalar("
+        INSERT INTO Hold (book, user, days, created_at)
+        VALUES (?, ?, ?, ?)
+        RETURNING id")
+        .bind(book)
+        .bind(user)
+        .bind(days)
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .fetch_one(pool).await;
+    match inserted {
+        Ok(id) => Ok(Some(id)),
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn get_hold(pool: &SqlitePool, id: u32) -> Result<Option<Hold>, sqlx::Error> {
+    sqlx::query_as(&format!("{HOLD_QUERY} WHERE Hold.id = ?"))
+        .bind(id).fetch_optional(pool).await
+}
+
+/// Newest first
+pub async fn get_user_holds(pool: &SqlitePool, user: u32) -> Result<Vec<Hold>, sqlx::Error> {
+    sqlx::query_as(&format!("{HOLD_QUERY} WHERE Hold.user = ? ORDER BY Hold.id DESC"))
+        .bind(user).fetch_all(pool).await
+}
+
+/// The line for a book, offered holds first
+pub async fn get_book_holds(pool: &SqlitePool, book: u32) -> Result<Vec<Hold>, sqlx::Error> {
+    sqlx::query_as(&format!("{HOLD_QUERY}
+        WHERE Hold.book = ? AND Hold.status IN ('waiting', 'offered')
+        ORDER BY Hold.status = 'waiting', Hold.id"))
+        .bind(book).fetch_all(pool).await
+}
+
+pub async fn set_status(pool: &SqlitePool, id: u32, status: HoldStatus, reservation: Option<u32>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE Hold SET status = ?, reservation = ? WHERE id = ?")
+        .bind(status).bind(reservation).bind(id).execute(pool).await?;
+    Ok(())
+}
+
+/// Puts an offered hold back at its place in line
+pub async fn withdraw_offer(pool: &SqlitePool, id: u32) -> Result<(), sqlx::Error> {
+    sqlx::query("
+        UPDATE Hold
+        SET status = 'waiting', offered_copy = NULL, offered_at = NULL, offer_expires_at = NULL
+        WHERE id = ?").bind(id).execute(pool).await?;
+    Ok(())
+}
+
+/// The running offer on a copy, which keeps it from being reserved by anyone else
+pub async fn get_copy_offer(executor: impl SqliteExecutor<'_>, copy_id: u32) -> Result<Option<Hold>, sqlx::Error> {
+    sqlx::query_as(&format!("{HOLD_QUERY}
+        WHERE Hold.offered_copy = ? AND Hold.status = 'offered' AND Hold.offer_expires_at >= ?"))
+        .bind(copy_id)
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .fetch_optional(executor).await
+}
+
+/// A copy of the book that is on the shelf and free for the whole time the user might keep it
+async fn find_free_copy(pool: &SqlitePool, book: u32, days: u32) -> Result<Option<u32>, sqlx::Error> {
+    let copy_ids: Vec<u32> = sqlx::query_scalar("
+        SELECT id FROM PhysicalBook
+        WHERE book = ?
+            AND id NOT IN (SELECT offered_copy FROM Hold WHERE status = 'offered' AND offered_copy IS NOT NULL)
+            AND id NOT IN (SELECT copy FROM Loan WHERE returned_at IS NULL)
+        ORDER BY id").bind(book).fetch_all(pool).await?;
+
+    let now = OffsetDateTime::now_utc();
+    // The claim can come at the very end of the window
+    let until = now.checked_add(CLAIM_WINDOW).and_then(|at| at.checked_add(Duration::days(days as i64)));
+    for copy_id in copy_ids {
+        let Some((_, copy)) = crud::get_copy_with_book(pool, copy_id).await? else {
+            continue;
+        };
+        if !copy.reservations.iter().any(|r| r.takes_copy() && r.intersects(now, until)) {
+            return Ok(Some(copy_id));
+        }
+    }
+    Ok(None)
+}
+
+/// Expires offers that were not claimed in time and offers free copies to the next in line.
+/// Returns the holds that got an offer
+pub async fn process_holds(pool: &SqlitePool) -> Result<Vec<u32>, sqlx::Error> {
+    let now = OffsetDateTime::now_utc();
+    sqlx::query("UPDATE Hold SET status = 'expired' WHERE status = 'offered' AND offer_expires_at < ?")
+        .bind(now.unix_timestamp()).execute(pool).await?;
+    // The offered copy was removed
+    sqlx::query("
+        UPDATE Hold
+        SET status = 'waiting', offered_at = NULL, offer_expires_at = NULL
+        WHERE status = 'offered' AND offered_copy IS NULL").execute(pool).await?;
+
+    let waiting: Vec<(u32, u32, u32)> = sqlx::query_as("
+        SELECT id, book, days FROM Hold
+        WHERE status = 'waiting'
+        ORDER BY book, id").fetch_all(pool).await?;
+
+    let mut offered = vec![];
+    // Nobody skips ahead of someone who is still waiting for a copy
+    let mut stuck_books = vec![];
+    for (id, book, days) in waiting {
+        if stuck_books.contains(&book) {
+            continue;
+        }
+        let Some(copy_id) = find_free_copy(pool, book, days).await? else {
+            stuck_books.push(book);
+            continue;
+        };
+        let result = sqlx::query("
+            UPDATE Hold
+            SET status = 'offered', offered_copy = ?, offered_at = ?, offer_expires_at = ?
+            WHERE id = ? AND status = 'waiting'")
+            .bind(copy_id)
+            .bind(now.unix_timestamp())
+            .bind((now + CLAIM_WINDOW).unix_timestamp())
+            .bind(id)
+            .execute(pool).await;
+        match result {
+            Ok(result) if result.rows_affected() > 0 => offered.push(id),
+            Ok(_) => {}
+            // Offered by a concurrent run
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => stuck_books.push(book),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(offered)
+}
@@ -0,0 +1,244 @@
+use serde::Serialize;
+use sqlx::{QueryBuilder, Sqlite, SqliteExecutor, SqlitePool};
+use time::{Duration, OffsetDateTime, UtcDateTime};
+
+use crate::{database::holds, types};
+
+#[derive(Serialize)]
+pub struct Loan {
+    pub id: u32,
+    pub copy_id: u32,
+    pub code: String,
+    pub title: String,
+    /// Borrower, or whoever handed the copy out when it went to a borrower without an account
+    pub user: types::User,
+    pub borrower: Option<types::Borrower>,
+    /// The reservation the copy was picked up for, None when lent ad hoc
+    pub reservation: Option<u32>,
+    pub checked_out_at: i64,
+    /// None when lent until it is returned
+    #[serde(with = "time::serde::iso8601::option")]
+    pub due_date: Option<OffsetDateTime>,
+    /// None while the copy is still out
+    pub returned_at: Option<i64>,
+    pub overdue: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct LoanRow {
+    id: u32,
+    copy_id: u32,
+    code: String,
+    title: String,
+    user_id: u32,
+    username: String,
+    personal_color: String,
+    borrower_id: Option<u32>,
+    borrower_name: Option<String>,
+    borrower_phone: Option<String>,
+    borrower_email: Option<String>,
+    borrower_notes: Option<String>,
+    reservation: Option<u32>,
+    checked_out_at: i64,
+    due_date: Option<OffsetDateTime>,
+    returned_at: Option<i64>,
+}
+
+impl From<LoanRow> for Loan {
+    fn from(row: LoanRow) -> Self {
+        let borrower = match (row.borrower_id, row.borrower_name) {
+            (Some(id), Some(name)) => Some(types::Borrower {
+                id,
+                name,
+                phone: row.borrower_phone,
+                email: row.borrower_email,
+                notes: row.borrower_notes,
+            }),
+            _ => None,
+        };
+        Loan {
+            id: row.id,
+            copy_id: row.copy_id,
+            code: row.code,
+            title: row.title,
+            user: types::User { id: row.user_id, username: row.username, personal_color: row.personal_color },
+            borrower,
+            reservation: row.reservation,
+            checked_out_at: row.checked_out_at,
+            due_date: row.due_date,
+            returned_at: row.returned_at,
+            overdue: row.returned_at.is_none() && row.due_date.is_some_and(|due_date| due_date < OffsetDateTime::now_utc()),
+        }
+    }
+}
+
+impl Loan {
+    /// When the copy is out, nobody knows when an overdue copy comes back so it counts as out for another day
+    pub fn busy_span(&self) -> (OffsetDateTime, Option<OffsetDateTime>) {
+        let checked_out_at = OffsetDateTime::from_unix_timestamp(self.checked_out_at).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        let back = self.due_date.map(|due_date| due_date.max(OffsetDateTime::now_utc() + Duration::days(1)));
+        (checked_out_at, back)
+    }
+}
+
+#[derive(Default)]
+pub struct LoanFilter {
+    /// Loans to the user themselves, not those they handed out to borrowers
+    pub user: Option<u32>,
+    pub borrower: Option<u32>,
+    pub copy: Option<u32>,
+    /// Leaves out returned loans
+    pub active: bool,
+}
+
+pub enum CheckOut {
+    Lent(Loan),
+    /// The copy is already out
+    AlreadyLent(Loan),
+    /// Someone else has the copy booked before it would be back
+    Reserved(types::Reservation),
+    /// The copy is kept for someone else in line for the book until they claim it
+    Held(holds::Hold),
+}
+
+const LOAN_QUERY: &str = "
+    SELECT
+        Loan.id,
+        Loan.copy AS copy_id,
+        PhysicalBook.code,
+        Book.title,
+        User.id AS user_id,
+        User.username,
+        User.personal_color,
+        Borrower.id AS borrower_id,
+        Borrower.name AS borrower_name,
+        Borrower.phone AS borrower_phone,
+        Borrower.email AS borrower_email,
+        Borrower.notes AS borrower_notes,
+        Loan.reservation,
+        Loan.checked_out_at,
+        Loan.due_date,
+        Loan.returned_at
+    FROM Loan
+    INNER JOIN PhysicalBook ON Loan.copy = PhysicalBook.id
+    INNER JOIN Book ON PhysicalBook.book = Book.id
+    INNER JOIN User ON Loan.user = User.id
+    LEFT JOIN Borrower ON Loan.borrower = Borrower.id";
+
+pub async fn get_loan(pool: &SqlitePool, id: u32) -> Result<Option<Loan>, sqlx::Error> {
+    let row: Option<LoanRow> = sqlx::query_as(&format!("{LOAN_QUERY} WHERE Loan.id = ?"))
+        .bind(id).fetch_optional(pool).await?;
+    Ok(row.map(Loan::from))
+}
+
+pub async fn get_active_loan(executor: impl SqliteExecutor<'_>, copy_id: u32) -> Result<Option<Loan>, sqlx::Error> {
+    let row: Option<LoanRow> = sqlx::query_as(&format!("{LOAN_QUERY} WHERE Loan.copy = ? AND Loan.returned_at IS NULL"))
+        .bind(copy_id).fetch_optional(executor).await?;
+    Ok(row.map(Loan::from))
+}
+
+/// Newest first
+pub async fn get_loans(pool: &SqlitePool, filter: &LoanFilter) -> Result<Vec<Loan>, sqlx::Error> {
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(LOAN_QUERY);
+    query.push(" WHERE TRUE");
+    if let Some(user) = filter.user {
+        query.push(" AND Loan.user = ").push_bind(user).push(" AND Loan.borrower IS NULL");
+    }
+    if let Some(borrower) = filter.borrower {
+        query.push(" AND Loan.borrower = ").push_bind(borrower);
+    }
+    if let Some(copy) = filter.copy {
+        query.push(" AND Loan.copy = ").push_bind(copy);
+    }
+    if filter.active {
+        query.push(" AND Loan.returned_at IS NULL");
+    }
+    query.push(" ORDER BY Loan.checked_out_at DESC, Loan.id DESC");
+    let rows: Vec<LoanRow> = query.build_query_as().fetch_all(pool).await?;
+    Ok(rows.into_iter().map(Loan::from).collect())
+}
+
+/// Lends a copy until `due_date` or until it is returned, picking up the borrower's running reservation
+/// if none is given. With a borrower, `user` is whoever hands the copy out
+pub async fn check_out(
+    pool: &SqlitePool,
+    copy: &types::PhysicalBook,
+    user: u32,
+    borrower: Option<u32>,
+    due_date: Option<OffsetDateTime>,
+    reservation: Option<u32>,
+) -> Result<CheckOut, sqlx::Error> {
+    if let Some(loan) = get_active_loan(pool, copy.id).await? {
+        return Ok(CheckOut::AlreadyLent(loan));
+    }
+    let now = OffsetDateTime::now_utc();
+    let holds = |r: &&types::Reservation| match borrower {
+        Some(borrower) => r.borrower.as_ref().is_some_and(|b| b.id == borrower),
+        None => r.borrower.is_none() && r.user.id == user,
+    };
+    let reservation = reservation.or_else(|| copy.reservations.iter()
+        .filter(|r| r.takes_copy())
+        .filter(holds)
+        .find(|r| r.is_running(now))
+        .map(|r| r.id));
+    if let Some(conflict) = copy.reservations.iter()
+        .filter(|r| r.takes_copy())
+        .find(|r| !holds(r) && Some(r.id) != reservation && r.intersects(now, due_date)) {
+        return Ok(CheckOut::Reserved(conflict.clone()));
+    }
+
+    // Writing first takes the database's write lock, so a hold offer made for the copy
+    // in the meantime is seen by the check below
+    let mut tx = pool.begin().await?;
+    let inserted: Result<u32, sqlx::Error> = sqlx::query_scalar("
+        INSERT INTO Loan (copy, user, borrower, reservation, checked_out_at, due_date)
+        VALUES (?, ?, ?, ?, ?, ?)
+        RETURNING id")
+        .bind(copy.id)
+        .bind(user)
+        .bind(borrower)
+        .bind(reservation)
+        .bind(UtcDateTime::now().unix_timestamp())
+        .bind(due_date)
+        .fetch_one(&mut *tx).await;
+    let id = match inserted {
+        Ok(id) => id,
+        // Checked out by someone else in the meantime
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            tx.rollback().await?;
+            return match get_active_loan(pool, copy.id).await? {
+                Some(loan) => Ok(CheckOut::AlreadyLent(loan)),
+                None => Err(sqlx::Error::Database(err)),
+            };
+        }
+        Err(err) => return Err(err),
+    };
+    if let Some(offer) = holds::get_copy_offer(&mut *tx, copy.id).await? {
+        if borrower.is_some() || offer.user.id != user {
+            tx.rollback().await?;
+            return Ok(CheckOut::Held(offer));
+        }
+    }
+    tx.commit().await?;
+
+    match get_loan(pool, id).await? {
+        Some(loan) => Ok(CheckOut::Lent(loan)),
+        None => Err(sqlx::Error::RowNotFound),
+    }
+}
+
+/// Closes the copy's running loan, None when it was not lent out
+pub async fn check_in(pool: &SqlitePool, copy_id: u32) -> Result<Option<Loan>, sqlx::Error> {
+    let id: Option<u32> = sqlx::query_scalar("
+        UPDATE Loan
+        SET returned_at = ?
+        WHERE copy = ? AND returned_at IS NULL
+        RETURNING id")
+        .bind(UtcDateTime::now().unix_timestamp())
+        .bind(copy_id)
+        .fetch_optional(pool).await?;
+    match id {
+        Some(id) => get_loan(pool, id).await,
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,206 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{database::crud, routes, types};
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct ShelfCount {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub shelf: types::Shelf,
+    pub copies: u32,
+}
+
+#[derive(Serialize)]
+pub struct LocationNode {
+    #[serde(flatten)]
+    pub location: types::Location,
+    /// Copies on every shelf below this location
+    pub copies: u32,
+    pub children: Vec<LocationNode>,
+    pub shelves: Vec<ShelfCount>,
+}
+
+#[derive(Serialize)]
+pub struct LocationTree {
+    pub locations: Vec<LocationNode>,
+    /// Shelves that have not been placed anywhere yet
+    pub shelves: Vec<ShelfCount>,
+}
+
+const LOCATION_QUERY: &str = "
+    SELECT
+        Location.id,
+        Location.name,
+        Location.kind,
+        Location.parent,
+        LocationPath.breadcrumb,
+        Location.width,
+        Location.depth,
+        Location.height,
+        Location.x,
+        Location.y,
+        Location.rotation,
+        Location.levels,
+        Location.sections
+    FROM Location
+    INNER JOIN LocationPath ON Location.id = LocationPath.id";
+
+pub async fn create_location(
+    pool: &SqlitePool,
+    name: &str,
+    kind: types::LocationKind,
+    parent: Option<u32>,
+) -> Result<u32, sqlx::Error> {
+    sqlx::query_scalar("
+        INSERT INTO Location (name, kind, parent)
+        VALUES (?, ?, ?)
+        RETURNING id").bind(name).bind(kind).bind(parent).fetch_one(pool).await
+}
+
+pub async fn get_location(pool: &SqlitePool, id: u32) -> Result<Option<types::Location>, sqlx::Error> {
+    sqlx::query_as(&format!("{LOCATION_QUERY}
+        WHERE Location.id = ?")).bind(id).fetch_optional(pool).await
+}
+
+/// Whether `node` is `ancestor` itself or somewhere below it
+pub async fn is_descendant(pool: &SqlitePool, ancestor: u32, node: u32) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("
+        WITH RECURSIVE Descendant(id) AS (
+            SELECT ?
+            UNION ALL
+            SELECT Location.id
+            FROM Location
+            INNER JOIN Descendant ON Location.parent = Descendant.id
+        )
+        SELECT EXISTS(SELECT 1 FROM Descendant WHERE id = ?)").bind(ancestor).bind(node).fetch_one(pool).await
+}
+
+pub async fn edit_location(
+    pool: &SqlitePool,
+    id: u32,
+    name: Option<&str>,
+    parent: Option<Option<u32>>,
+    layout: routes::LocationLayoutForm,
+) -> Result<(), sqlx::Error> {
+    let mut qb = crud::update_builder("Location", id, |sep| {
+        crud::apply_update(sep, "name", name.map(Some));
+        crud::apply_update(sep, "parent", parent);
+        crud::apply_update(sep, "width", layout.width);
+        crud::apply_update(sep, "depth", layout.depth);
+        crud::apply_update(sep, "height", layout.height);
+        crud::apply_update(sep, "x", layout.x);
+        crud::apply_update(sep, "y", layout.y);
+        crud::apply_update(sep, "rotation", layout.rotation);
+        crud::apply_update(sep, "levels", layout.levels);
+        crud::apply_update(sep, "sections", layout.sections);
+    });
+    qb.build().execute(pool).await?;
+
+    Ok(())
+}
+
+/// Removes a location, its children and shelves move up to its parent
+pub async fn remove_location(pool: &SqlitePool, id: u32) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let parent: Option<u32> = sqlx::query_scalar("SELECT parent FROM Location WHERE id = ?")
+        .bind(id).fetch_one(&mut *tx).await?;
+    sqlx::query("UPDATE Location SET parent = ? WHERE parent = ?")
+        .bind(parent).bind(id).execute(&mut *tx).await?;
+    sqlx::query("UPDATE Shelf SET location = ? WHERE location = ?")
+        .bind(parent).bind(id).execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM Location WHERE id = ?")
+        .bind(id).execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_shelf_location(
+    pool: &SqlitePool,
+    shelf: u32,
+    location: Option<u32>,
+    level: Option<u32>,
+    section: Option<u32>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE Shelf SET location = ?, level = ?, section = ? WHERE id = ?")
+        .bind(location).bind(level).bind(section).bind(shelf).execute(pool).await?;
+    Ok(())
+}
+
+/// Where a shelf sits inside its bookcase
+pub async fn get_shelf_placement(pool: &SqlitePool, shelf: u32) -> Result<(Option<u32>, Option<u32>), sqlx::Error> {
+    sqlx::query_as("SELECT level, section FROM Shelf WHERE id = ?")
+        .bind(shelf).fetch_one(pool).await
+}
+
+/// The location itself followed by its parents up to the root
+pub async fn get_location_ancestors(pool: &SqlitePool, id: u32) -> Result<Vec<types::Location>, sqlx::Error> {
+    let mut ancestors = vec![];
+    let mut next = Some(id);
+    while let Some(id) = next {
+        let Some(location) = get_location(pool, id).await? else {
+            break;
+        };
+        next = location.parent;
+        ancestors.push(location);
+    }
+    Ok(ancestors)
+}
+
+pub async fn get_child_locations(pool: &SqlitePool, id: u32) -> Result<Vec<types::Location>, sqlx::Error> {
+    sqlx::query_as(&format!("{LOCATION_QUERY}
+        WHERE Location.parent = ?
+        ORDER BY Location.id")).bind(id).fetch_all(pool).await
+}
+
+fn build_nodes(
+    parent: Option<u32>,
+    locations: &mut Vec<types::Location>,
+    shelves: &mut Vec<ShelfCount>,
+) -> Vec<LocationNode> {
+    let (children, rest): (Vec<_>, Vec<_>) = std::mem::take(locations).into_iter()
+        .partition(|location| location.parent == parent);
+    *locations = rest;
+
+    children.into_iter().map(|location| {
+        let (own_shelves, rest): (Vec<_>, Vec<_>) = std::mem::take(shelves).into_iter()
+            .partition(|shelf| shelf.shelf.location == Some(location.id));
+        *shelves = rest;
+        let children = build_nodes(Some(location.id), locations, shelves);
+        let copies = own_shelves.iter().map(|shelf| shelf.copies).sum::<u32>()
+            + children.iter().map(|child| child.copies).sum::<u32>();
+        LocationNode { location, copies, children, shelves: own_shelves }
+    }).collect()
+}
+
+pub async fn get_location_tree(pool: &SqlitePool) -> Result<LocationTree, sqlx::Error> {
+    let mut locations: Vec<types::Location> = sqlx::query_as(&format!("{LOCATION_QUERY}
+        ORDER BY Location.name")).fetch_all(pool).await?;
+
+    let mut shelves: Vec<ShelfCount> = sqlx::query_as("
+        SELECT Shelf.id, Shelf.name, Shelf.location, ShelfPath.breadcrumb, COUNT(PhysicalBook.id) AS copies
+        FROM Shelf
+        INNER JOIN ShelfPath ON Shelf.id = ShelfPath.id
+        LEFT JOIN PhysicalBook ON PhysicalBook.shelf = Shelf.id
+        GROUP BY Shelf.id
+        ORDER BY Shelf.name").fetch_all(pool).await?;
+
+    let nodes = build_nodes(None, &mut locations, &mut shelves);
+    Ok(LocationTree { locations: nodes, shelves })
+}
+
+/// Subtree below a single location
+pub async fn get_location_node(pool: &SqlitePool, id: u32) -> Result<Option<LocationNode>, sqlx::Error> {
+    fn find(nodes: Vec<LocationNode>, id: u32) -> Option<LocationNode> {
+        for node in nodes {
+            if node.location.id == id {
+                return Some(node);
+            }
+            if let Some(node) = find(node.children, id) {
+                return Some(node);
+            }
+        }
+        None
+    }
+    Ok(find(get_location_tree(pool).await?.locations, id))
+}
@@ -1,5 +1,19 @@
+pub mod availability;
+pub mod bulk;
+pub mod borrowers;
+pub mod calendar;
+pub mod capacity;
+pub mod collections;
 pub mod crud;
+pub mod history;
+pub mod holds;
+pub mod loans;
+pub mod locations;
+pub mod policies;
+pub mod reminders;
 pub mod search;
+pub mod shelves;
+pub mod stocktake;
 
 use std::{fs, path::Path, str::FromStr};
 use sqlx::{sqlite::SqliteConnectOptions, Pool, Sqlite, SqlitePool};
@@ -17,5 +31,7 @@ pub async fn init_database() -> Result<Pool<Sqlite>, sqlx::Error> {
 
     sqlx::migrate!("./migrations").run(&pool).await?;
 
-    return Ok(pool);
+    crud::assign_missing_copy_codes(&pool).await?;
+
+    Ok(pool)
 }
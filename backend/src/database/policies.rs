@@ -0,0 +1,208 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime};
+
+use crate::database::crud;
+
+/// Longest a rule can allow for loans or booking ahead, about ten years
+pub const MAX_LIMIT_DAYS: u32 = 3650;
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct LendingPolicy {
+    pub id: u32,
+    /// Only one of role, shelf and copy is set, none for the rule that applies to everyone
+    pub role: Option<String>,
+    pub shelf: Option<u32>,
+    pub copy: Option<u32>,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub limits: Limits,
+}
+
+/// What a rule allows, None leaves it to a less specific rule
+#[derive(sqlx::FromRow, Serialize, Deserialize, Default, Clone)]
+pub struct Limits {
+    pub max_loan_days: Option<u32>,
+    /// Reservations one person can have that have not ended yet
+    pub max_reservations: Option<u32>,
+    pub max_days_ahead: Option<u32>,
+    /// Copies that stay in the house and cannot be reserved
+    pub reference_only: Option<bool>,
+}
+
+impl Limits {
+    fn or(self, fallback: Limits) -> Limits {
+        Limits {
+            max_loan_days: self.max_loan_days.or(fallback.max_loan_days),
+            max_reservations: self.max_reservations.or(fallback.max_reservations),
+            max_days_ahead: self.max_days_ahead.or(fallback.max_days_ahead),
+            reference_only: self.reference_only.or(fallback.reference_only),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Violation {
+    ReferenceOnly,
+    TooLong(u32),
+    TooFarAhead(u32),
+    TooManyReservations(u32),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::ReferenceOnly => write!(f, "This copy is for reference only and cannot be reserved"),
+            Violation::TooLong(days) => write!(f, "This copy can be reserved for at most {days} days"),
+            Violation::TooFarAhead(days) => write!(f, "Reservations can start at most {days} days ahead"),
+            Violation::TooManyReservations(max) => write!(f, "No more than {max} reservations can be held at once"),
+        }
+    }
+}
+
+const POLICY_QUERY: &str = "
+    SELECT id, role, shelf, copy, max_loan_days, max_reservations, max_days_ahead, reference_only
+    FROM LendingPolicy";
+
+/// None when there already is a rule for the scope
+pub async fn create_policy(
+    pool: &SqlitePool,
+    role: Option<&str>,
+    shelf: Option<u32>,
+    copy: Option<u32>,
+    limits: &Limits,
+) -> Result<Option<u32>, sqlx::Error> {
+    let inserted = sqlx::query_scalar("
+        INSERT INTO LendingPolicy (role, shelf, copy, max_loan_days, max_reservations, max_days_ahead, reference_only)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        RETURNING id")
+        .bind(role)
+        .bind(shelf)
+        .bind(copy)
+        .bind(limits.max_loan_days)
+        .bind(limits.max_reservations)
+        .bind(limits.max_days_ahead)
+        .bind(limits.reference_only)
+        .fetch_one(pool).await;
+    match inserted {
+        Ok(id) => Ok(Some(id)),
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn get_policy(pool: &SqlitePool, id: u32) -> Result<Option<LendingPolicy>, sqlx::Error> {
+    sqlx::query_as(&format!("{POLICY_QUERY} WHERE id = ?"))
+        .bind(id).fetch_optional(pool).await
+}
+
+/// The rule for everyone first, then by role, shelf and copy
+pub async fn get_policies(pool: &SqlitePool) -> Result<Vec<LendingPolicy>, sqlx::Error> {
+    sqlx::query_as(&format!("{POLICY_QUERY} ORDER BY copy IS NOT NULL, shelf IS NOT NULL, role IS NOT NULL, role, shelf, copy"))
+        .fetch_all(pool).await
+}
+
+pub async fn edit_policy(
+    pool: &SqlitePool,
+    id: u32,
+    max_loan_days: Option<Option<u32>>,
+    max_reservations: Option<Option<u32>>,
+    max_days_ahead: Option<Option<u32>>,
+    reference_only: Option<Option<bool>>,
+) -> Result<(), sqlx::Error> {
+    let mut qb = crud::update_builder("LendingPolicy", id, |sep| {
+        crud::apply_update(sep, "max_loan_days", max_loan_days);
+        crud::apply_update(sep, "max_reservations", max_reservations);
+        crud::apply_update(sep, "max_days_ahead", max_days_ahead);
+        crud::apply_update(sep, "reference_only", reference_only);
+    });
+    qb.build().execute(pool).await?;
+    Ok(())
+}
+
+pub async fn remove_policy(pool: &SqlitePool, id: u32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM LendingPolicy WHERE id = ?")
+        .bind(id).execute(pool).await?;
+    Ok(())
+}
+
+pub async fn set_user_role(pool: &SqlitePool, user: u32, role: Option<&str>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE User SET role = ? WHERE id = ?")
+        .bind(role).bind(user).execute(pool).await?;
+    Ok(())
+}
+
+/// What applies to a copy for someone with the role, the most specific rule winning for each limit
+pub async fn get_limits(conn: &mut SqliteConnection, role: Option<&str>, copy: u32) -> Result<Limits, sqlx::Error> {
+    let rules: Vec<LendingPolicy> = sqlx::query_as(&format!("{POLICY_QUERY}
+        WHERE (role IS NULL AND shelf IS NULL AND copy IS NULL)
+            OR role = ?
+            OR shelf = (SELECT shelf FROM PhysicalBook WHERE id = ?)
+            OR copy = ?
+        ORDER BY copy IS NULL, shelf IS NULL, role IS NULL"))
+        .bind(role)
+        .bind(copy)
+        .bind(copy)
+        .fetch_all(&mut *conn).await?;
+    Ok(rules.into_iter().fold(Limits::default(), |limits, rule| limits.or(rule.limits)))
+}
+
+/// What applies to a copy for the user, or for the borrower when the reservation is for one
+pub async fn get_holder_limits(pool: &SqlitePool, user: u32, borrower: Option<u32>, copy: u32) -> Result<Limits, sqlx::Error> {
+    let role: Option<String> = match borrower {
+        Some(_) => None,
+        None => sqlx::query_scalar("SELECT role FROM User WHERE id = ?").bind(user).fetch_one(pool).await?,
+    };
+    let mut conn = pool.acquire().await?;
+    get_limits(&mut conn, role.as_deref(), copy).await
+}
+
+/// Why the reservation, already written on the connection, is not allowed for its copy and holder
+pub(crate) async fn check_reservation(
+    conn: &mut SqliteConnection,
+    id: u32,
+    copy: u32,
+    start_date: OffsetDateTime,
+    end_date: Option<OffsetDateTime>,
+) -> Result<Option<Violation>, sqlx::Error> {
+    let (user, borrower, role): (u32, Option<u32>, Option<String>) = sqlx::query_as("
+        SELECT Reservation.user, Reservation.borrower, User.role
+        FROM Reservation
+        INNER JOIN User ON Reservation.user = User.id
+        WHERE Reservation.id = ?")
+        .bind(id).fetch_one(&mut *conn).await?;
+    // Borrowers without an account have no role
+    let role = role.filter(|_| borrower.is_none());
+    let limits = get_limits(conn, role.as_deref(), copy).await?;
+
+    if limits.reference_only == Some(true) {
+        return Ok(Some(Violation::ReferenceOnly));
+    }
+    if let Some(days) = limits.max_loan_days {
+        // Keeping a copy until it is returned is longer than any limit
+        if end_date.is_none_or(|end_date| end_date - start_date > Duration::days(days as i64)) {
+            return Ok(Some(Violation::TooLong(days)));
+        }
+    }
+    let now = OffsetDateTime::now_utc();
+    if let Some(days) = limits.max_days_ahead {
+        if now.checked_add(Duration::days(days as i64)).is_some_and(|latest| start_date > latest) {
+            return Ok(Some(Violation::TooFarAhead(days)));
+        }
+    }
+    if let Some(max) = limits.max_reservations {
+        let end_dates: Vec<Option<OffsetDateTime>> = match borrower {
+            // Requests still waiting for approval count, declined ones do not
+            Some(borrower) => sqlx::query_scalar("SELECT end_date FROM Reservation WHERE id != ? AND borrower = ? AND status != 'declined'")
+                .bind(id).bind(borrower).fetch_all(&mut *conn).await?,
+            None => sqlx::query_scalar("SELECT end_date FROM Reservation WHERE id != ? AND user = ? AND borrower IS NULL AND status != 'declined'")
+                .bind(id).bind(user).fetch_all(&mut *conn).await?,
+        };
+        if end_dates.into_iter().filter(|end_date| end_date.is_none_or(|end_date| end_date >= now)).count() as u32 >= max {
+            return Ok(Some(Violation::TooManyReservations(max)));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,215 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use time::{Duration, OffsetDateTime};
+
+use crate::database::crud;
+
+/// How long before it starts a reservation is reminded of
+pub const STARTING_NOTICE: Duration = Duration::days(1);
+/// How long before the due date a loan is reminded of
+pub const DUE_NOTICE: Duration = Duration::days(2);
+
+#[derive(sqlx::Type, Serialize, Debug, Clone, Copy, PartialEq)]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ReminderKind {
+    ReservationStarting,
+    DueSoon,
+    Overdue,
+    /// A copy is kept for the user's hold until the offer expires
+    HoldAvailable,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct ReminderSettings {
+    pub email: Option<String>,
+    pub email_reminders: bool,
+}
+
+/// A reminder that is due to be sent and has not been yet
+pub struct Reminder {
+    pub kind: ReminderKind,
+    /// Id of the reservation, loan or hold
+    pub subject: u32,
+    pub user: u32,
+    pub username: String,
+    pub email: String,
+    pub title: String,
+    pub code: String,
+    pub shelf: String,
+    /// Who the copy is for when the user handles it for a borrower
+    pub borrower: Option<String>,
+    /// When the reservation starts, the loan is due or the offer expires
+    pub date: OffsetDateTime,
+}
+
+#[derive(sqlx::FromRow)]
+struct ReminderRow {
+    subject: u32,
+    user: u32,
+    username: String,
+    email: String,
+    title: String,
+    code: String,
+    shelf: String,
+    borrower: Option<String>,
+    date: OffsetDateTime,
+}
+
+impl ReminderRow {
+    fn into_reminder(self, kind: ReminderKind) -> Reminder {
+        Reminder {
+            kind,
+            subject: self.subject,
+            user: self.user,
+            username: self.username,
+            email: self.email,
+            title: self.title,
+            code: self.code,
+            shelf: self.shelf,
+            borrower: self.borrower,
+            date: self.date,
+        }
+    }
+}
+
+const RESERVATION_REMINDER_QUERY: &str = "
+    SELECT
+        Reservation.id AS subject,
+        User.id AS user,
+        User.username,
+        User.email,
+        Book.title,
+        PhysicalBook.code,
+        ShelfPath.breadcrumb AS shelf,
+        Borrower.name AS borrower,
+        Reservation.start_date AS date
+    FROM Reservation
+    INNER JOIN BookReservationMatch ON Reservation.id = BookReservationMatch.reservation
+    INNER JOIN PhysicalBook ON BookReservationMatch.physical_book = PhysicalBook.id
+    INNER JOIN Book ON PhysicalBook.book = Book.id
+    INNER JOIN ShelfPath ON PhysicalBook.shelf = ShelfPath.id
+    INNER JOIN User ON Reservation.user = User.id
+    LEFT JOIN Borrower ON Reservation.borrower = Borrower.id
+    WHERE User.email_reminders AND User.email IS NOT NULL AND Reservation.status = 'approved'
+        AND unixepoch(Reservation.start_date) > ? AND unixepoch(Reservation.start_date) <= ?
+        AND NOT EXISTS (SELECT 1 FROM SentReminder WHERE kind = 'reservation_starting' AND subject = Reservation.id)";
+
+const LOAN_REMINDER_QUERY: &str = "
+    SELECT
+        Loan.id AS subject,
+        User.id AS user,
+        User.username,
+        User.email,
+        Book.title,
+        PhysicalBook.code,
+        ShelfPath.breadcrumb AS shelf,
+        Borrower.name AS borrower,
+        Loan.due_date AS date
+    FROM Loan
+    INNER JOIN PhysicalBook ON Loan.copy = PhysicalBook.id
+    INNER JOIN Book ON PhysicalBook.book = Book.id
+    INNER JOIN ShelfPath ON PhysicalBook.shelf = ShelfPath.id
+    INNER JOIN User ON Loan.user = User.id
+    LEFT JOIN Borrower ON Loan.borrower = Borrower.id
+    WHERE User.email_reminders AND User.email IS NOT NULL
+        AND Loan.returned_at IS NULL AND Loan.due_date IS NOT NULL";
+
+#[derive(sqlx::FromRow)]
+struct HoldReminderRow {
+    subject: u32,
+    user: u32,
+    username: String,
+    email: String,
+    title: String,
+    code: String,
+    shelf: String,
+    offer_expires_at: i64,
+}
+
+pub async fn get_reminder_settings(pool: &SqlitePool, user: u32) -> Result<ReminderSettings, sqlx::Error> {
+    sqlx::query_as("SELECT email, email_reminders FROM User WHERE id = ?")
+        .bind(user).fetch_one(pool).await
+}
+
+pub async fn edit_reminder_settings(
+    pool: &SqlitePool,
+    user: u32,
+    email: Option<Option<String>>,
+    email_reminders: Option<bool>,
+) -> Result<(), sqlx::Error> {
+    let mut qb = crud::update_builder("User", user, |sep| {
+        crud::apply_update(sep, "email", email);
+        crud::apply_update(sep, "email_reminders", email_reminders.map(Some));
+    });
+    qb.build().execute(pool).await?;
+    Ok(())
+}
+
+/// Reminders for users who opted in that are due at `now` and were not sent yet
+pub async fn get_pending_reminders(pool: &SqlitePool, now: OffsetDateTime) -> Result<Vec<Reminder>, sqlx::Error> {
+    let mut reminders = vec![];
+
+    // Dates are compared in whole seconds since the epoch, whatever offset they were stored with
+    let starting: Vec<ReminderRow> = sqlx::query_as(RESERVATION_REMINDER_QUERY)
+        .bind(now.unix_timestamp())
+        .bind((now + STARTING_NOTICE).unix_timestamp())
+        .fetch_all(pool).await?;
+    reminders.extend(starting.into_iter().map(|row| row.into_reminder(ReminderKind::ReservationStarting)));
+
+    for (kind, after, until) in [
+        (ReminderKind::DueSoon, Some(now), now + DUE_NOTICE),
+        (ReminderKind::Overdue, None, now),
+    ] {
+        let loans: Vec<ReminderRow> = sqlx::query_as(&format!("{LOAN_REMINDER_QUERY}
+            AND (? IS NULL OR unixepoch(Loan.due_date) > ?) AND unixepoch(Loan.due_date) <= ?
+            AND NOT EXISTS (SELECT 1 FROM SentReminder WHERE kind = ? AND subject = Loan.id)"))
+            .bind(after.map(|after| after.unix_timestamp()))
+            .bind(after.map(|after| after.unix_timestamp()))
+            .bind(until.unix_timestamp())
+            .bind(kind)
+            .fetch_all(pool).await?;
+        reminders.extend(loans.into_iter().map(|row| row.into_reminder(kind)));
+    }
+
+    let holds: Vec<HoldReminderRow> = sqlx::query_as("
+        SELECT
+            Hold.id AS subject,
+            User.id AS user,
+            User.username,
+            User.email,
+            Book.title,
+            PhysicalBook.code,
+            ShelfPath.breadcrumb AS shelf,
+            Hold.offer_expires_at
+        FROM Hold
+        INNER JOIN PhysicalBook ON Hold.offered_copy = PhysicalBook.id
+        INNER JOIN Book ON Hold.book = Book.id
+        INNER JOIN ShelfPath ON PhysicalBook.shelf = ShelfPath.id
+        INNER JOIN User ON Hold.user = User.id
+        WHERE Hold.status = 'offered' AND Hold.offer_expires_at > ?
+            AND User.email_reminders AND User.email IS NOT NULL
+            AND NOT EXISTS (SELECT 1 FROM SentReminder WHERE kind = 'hold_available' AND subject = Hold.id)")
+        .bind(now.unix_timestamp()).fetch_all(pool).await?;
+    reminders.extend(holds.into_iter().map(|row| Reminder {
+        kind: ReminderKind::HoldAvailable,
+        subject: row.subject,
+        user: row.user,
+        username: row.username,
+        email: row.email,
+        title: row.title,
+        code: row.code,
+        shelf: row.shelf,
+        borrower: None,
+        date: OffsetDateTime::from_unix_timestamp(row.offer_expires_at).unwrap_or(OffsetDateTime::UNIX_EPOCH),
+    }));
+
+    Ok(reminders)
+}
+
+pub async fn mark_sent(pool: &SqlitePool, kind: ReminderKind, subject: u32, user: u32) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT OR IGNORE INTO SentReminder (kind, subject, user, sent_at) VALUES (?, ?, ?, ?)")
+        .bind(kind).bind(subject).bind(user).bind(OffsetDateTime::now_utc().unix_timestamp())
+        .execute(pool).await?;
+    Ok(())
+}
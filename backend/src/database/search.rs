@@ -78,15 +78,15 @@ pub async fn get_spelling_candidates(pool: &SqlitePool, string: &str, max_candid
     for word in words {
         let candidates: Vec<Candidate> = sqlx::query_as("
             SELECT word, score FROM BookSpellfix WHERE word MATCH ? AND top=?
-            ").bind(format!("{}", word)).bind(max_candidates.min(20)).fetch_all(&mut *tx).await?;
+            ").bind(word.to_string()).bind(max_candidates.min(20)).fetch_all(&mut *tx).await?;
         //                     ^ Add * for prefix search
-        if candidates.len() > 0 {
+        if !candidates.is_empty() {
             corrected_words.push(candidates);
         }
     }
     tx.commit().await?;
 
-    if corrected_words.len() > 0 {
+    if !corrected_words.is_empty() {
         return Ok(Some(SpellfixCandidates::new(corrected_words)))
     }
     Ok(None)
@@ -106,8 +106,8 @@ impl Ord for Candidate {
 
 impl PartialOrd for Candidate {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.score.partial_cmp(&other.score) 
-    } 
+        Some(self.cmp(other))
+    }
 }
 
 impl Eq for Candidate {}
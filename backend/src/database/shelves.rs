@@ -0,0 +1,191 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+
+use crate::{database::{bulk, crud}, types};
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct ShelfSummary {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub shelf: types::Shelf,
+    pub description: Option<String>,
+    pub sort_rule: Option<types::SortRule>,
+    pub copies: u32,
+}
+
+#[derive(Serialize)]
+pub struct ShelfCopy {
+    pub book: types::Book,
+    pub copy: types::PhysicalBook,
+    /// Reserved by someone today
+    pub reserved: bool,
+    pub next_reservation: Option<types::Reservation>,
+}
+
+#[derive(Serialize)]
+pub struct ShelfDetail {
+    #[serde(flatten)]
+    pub shelf: ShelfSummary,
+    pub copies: Vec<ShelfCopy>,
+}
+
+const SHELF_SUMMARY_QUERY: &str = "
+    SELECT Shelf.id, Shelf.name, Shelf.location, ShelfPath.breadcrumb, Shelf.description, Shelf.sort_rule, COUNT(PhysicalBook.id) AS copies
+    FROM Shelf
+    INNER JOIN ShelfPath ON Shelf.id = ShelfPath.id
+    LEFT JOIN PhysicalBook ON PhysicalBook.shelf = Shelf.id";
+
+pub async fn get_shelf_summaries(pool: &SqlitePool) -> Result<Vec<ShelfSummary>, sqlx::Error> {
+    sqlx::query_as(&format!("{SHELF_SUMMARY_QUERY}
+        GROUP BY Shelf.id
+        ORDER BY Shelf.name")).fetch_all(pool).await
+}
+
+pub async fn get_shelf_detail(pool: &SqlitePool, id: u32) -> Result<Option<ShelfDetail>, sqlx::Error> {
+    let summary: Option<ShelfSummary> = sqlx::query_as(&format!("{SHELF_SUMMARY_QUERY}
+        WHERE Shelf.id = ?
+        GROUP BY Shelf.id")).bind(id).fetch_optional(pool).await?;
+    let Some(summary) = summary else {
+        return Ok(None);
+    };
+
+    let copy_ids = bulk::get_shelf_copy_ids(pool, id).await?;
+    let now = OffsetDateTime::now_utc();
+    let mut copies = vec![];
+    for copy_id in copy_ids {
+        let Some((book, copy)) = crud::get_copy_with_book(pool, copy_id).await? else {
+            continue;
+        };
+        let reserved = copy.reservations.iter().any(|r| r.takes_copy() && r.is_running(now));
+        let next_reservation = copy.reservations.iter()
+            .filter(|r| r.takes_copy() && r.start_date > now)
+            .min_by_key(|r| r.start_date)
+            .cloned();
+        copies.push(ShelfCopy { book, copy, reserved, next_reservation });
+    }
+    copies.sort_by(|a, b| a.book.title.cmp(&b.book.title));
+
+    Ok(Some(ShelfDetail { shelf: summary, copies }))
+}
+
+pub async fn create_shelf(pool: &SqlitePool, name: &str, description: Option<&str>) -> Result<u32, sqlx::Error> {
+    sqlx::query_scalar("
+        INSERT INTO Shelf (name, description)
+        VALUES (?, ?)
+        RETURNING id").bind(name).bind(description).fetch_one(pool).await
+}
+
+pub async fn edit_shelf(
+    pool: &SqlitePool,
+    id: u32,
+    name: Option<&str>,
+    description: Option<Option<String>>,
+    sort_rule: Option<Option<types::SortRule>>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if let Some(name) = name {
+        sqlx::query("UPDATE Shelf SET name = ? WHERE id = ?")
+            .bind(name).bind(id).execute(&mut *tx).await?;
+    }
+    if let Some(description) = description {
+        sqlx::query("UPDATE Shelf SET description = ? WHERE id = ?")
+            .bind(description).bind(id).execute(&mut *tx).await?;
+    }
+    if let Some(sort_rule) = sort_rule {
+        sqlx::query("UPDATE Shelf SET sort_rule = ? WHERE id = ?")
+            .bind(sort_rule).bind(id).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Moves every copy from the given shelves onto `into` and removes the emptied shelves
+pub async fn merge_shelves(pool: &SqlitePool, from: &[types::Shelf], into: &types::Shelf, user: Option<u32>) -> Result<u32, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut moved = 0;
+    for shelf in from {
+        let copy_ids: Vec<u32> = sqlx::query_scalar("SELECT id FROM PhysicalBook WHERE shelf = ?")
+            .bind(shelf.id).fetch_all(&mut *tx).await?;
+        let reason = format!("Merged shelf {} into {}", shelf.name, into.name);
+        for copy_id in copy_ids {
+            crud::move_copy(&mut tx, copy_id, into.id, user, Some(&reason)).await?;
+            moved += 1;
+        }
+        sqlx::query("DELETE FROM Shelf WHERE id = ?")
+            .bind(shelf.id).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(moved)
+}
+
+/// Removes a shelf, its copies either move to `destination` or are removed along with their reservations
+pub async fn remove_shelf(pool: &SqlitePool, shelf: &types::Shelf, destination: Option<&types::Shelf>, user: Option<u32>) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let copy_ids: Vec<u32> = sqlx::query_scalar("SELECT id FROM PhysicalBook WHERE shelf = ?")
+        .bind(shelf.id).fetch_all(&mut *tx).await?;
+    match destination {
+        Some(destination) => {
+            let reason = format!("Shelf {} was removed", shelf.name);
+            for copy_id in copy_ids {
+                crud::move_copy(&mut tx, copy_id, destination.id, user, Some(&reason)).await?;
+            }
+        }
+        None => bulk::delete_copies(&mut tx, &copy_ids, user).await?,
+    }
+    sqlx::query("DELETE FROM Shelf WHERE id = ?")
+        .bind(shelf.id).execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[derive(sqlx::FromRow, Clone)]
+pub struct ShelvedCopy {
+    pub copy_id: u32,
+    pub code: String,
+    pub title: String,
+    pub authors: String,
+    pub series: Option<String>,
+    pub series_index: Option<f64>,
+    pub publication_year: Option<i16>,
+    pub color: Option<String>,
+    pub position: Option<u32>,
+}
+
+/// How the copies on the shelf are meant to be ordered, manually when no rule was set
+pub async fn get_sort_rule(pool: &SqlitePool, shelf: u32) -> Result<types::SortRule, sqlx::Error> {
+    let rule: Option<types::SortRule> = sqlx::query_scalar("SELECT sort_rule FROM Shelf WHERE id = ?")
+        .bind(shelf).fetch_one(pool).await?;
+    Ok(rule.unwrap_or_default())
+}
+
+pub async fn get_shelved_copies(pool: &SqlitePool, shelf: u32) -> Result<Vec<ShelvedCopy>, sqlx::Error> {
+    sqlx::query_as("
+        SELECT
+            PhysicalBook.id AS copy_id,
+            PhysicalBook.code,
+            Book.title,
+            Book.authors,
+            Book.series,
+            Book.series_index,
+            Book.publication_year,
+            PhysicalBook.color,
+            PhysicalBook.position
+        FROM PhysicalBook
+        INNER JOIN Book ON PhysicalBook.book = Book.id
+        WHERE PhysicalBook.shelf = ?
+        ORDER BY PhysicalBook.position IS NULL, PhysicalBook.position, PhysicalBook.id").bind(shelf).fetch_all(pool).await
+}
+
+/// Numbers the given copies from the left, every other copy on the shelf loses its position
+pub async fn set_positions(pool: &SqlitePool, shelf: u32, copy_ids: &[u32]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE PhysicalBook SET position = NULL WHERE shelf = ?")
+        .bind(shelf).execute(&mut *tx).await?;
+    for (index, copy_id) in copy_ids.iter().enumerate() {
+        sqlx::query("UPDATE PhysicalBook SET position = ? WHERE id = ? AND shelf = ?")
+            .bind(index as u32 + 1).bind(copy_id).bind(shelf).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
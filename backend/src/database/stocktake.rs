@@ -0,0 +1,297 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use time::UtcDateTime;
+
+use crate::{database::crud, types};
+
+#[derive(Serialize)]
+pub struct Stocktake {
+    pub id: u32,
+    pub user: types::User,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub shelves: Vec<types::Shelf>,
+}
+
+#[derive(sqlx::FromRow, Serialize, Clone)]
+pub struct StocktakeCopy {
+    pub copy_id: u32,
+    pub code: String,
+    pub title: String,
+    #[serde(skip)]
+    pub shelf_id: u32,
+    pub shelf: String,
+}
+
+#[derive(Serialize)]
+pub struct MisplacedCopy {
+    #[serde(flatten)]
+    pub copy: StocktakeCopy,
+    pub found_on: String,
+    #[serde(skip)]
+    pub found_on_id: u32,
+}
+
+#[derive(Serialize)]
+pub struct StocktakeReport {
+    #[serde(flatten)]
+    pub stocktake: Stocktake,
+    pub found: Vec<StocktakeCopy>,
+    /// Expected on one of the shelves but never scanned
+    pub missing: Vec<StocktakeCopy>,
+    /// Scanned on another shelf than the one they belong to
+    pub misplaced: Vec<MisplacedCopy>,
+    /// Scanned codes that do not match any copy
+    pub unknown: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct StocktakeSummary {
+    #[serde(flatten)]
+    pub stocktake: Stocktake,
+    pub found: usize,
+    pub missing: usize,
+    pub misplaced: usize,
+    pub unknown: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanStatus {
+    Found,
+    Misplaced,
+    Unknown,
+}
+
+#[derive(Serialize)]
+pub struct ScanResult {
+    pub scanned: String,
+    pub status: ScanStatus,
+    pub copy: Option<StocktakeCopy>,
+}
+
+pub async fn start_stocktake(pool: &SqlitePool, user_id: u32, shelf_ids: &[u32]) -> Result<u32, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let now = UtcDateTime::now();
+
+    let stocktake_id: u32 = sqlx::query_scalar("
+        INSERT INTO Stocktake (user, started_at)
+        VALUES (?, ?)
+        RETURNING id").bind(user_id).bind(now.unix_timestamp()).fetch_one(&mut *tx).await?;
+
+    for shelf_id in shelf_ids {
+        sqlx::query("
+            INSERT INTO StocktakeShelf (stocktake, shelf)
+            VALUES (?, ?)
+            ON CONFLICT DO NOTHING").bind(stocktake_id).bind(shelf_id).execute(&mut *tx).await?;
+    }
+
+    sqlx::query("
+        INSERT INTO StocktakeExpected (stocktake, copy, shelf)
+        SELECT StocktakeShelf.stocktake, PhysicalBook.id, PhysicalBook.shelf
+        FROM PhysicalBook
+        INNER JOIN StocktakeShelf ON StocktakeShelf.shelf = PhysicalBook.shelf
+        WHERE StocktakeShelf.stocktake = ?").bind(stocktake_id).execute(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(stocktake_id)
+}
+
+pub async fn get_stocktake(pool: &SqlitePool, id: u32) -> Result<Option<Stocktake>, sqlx::Error> {
+    let stocktake: Option<(u32, u32, i64, Option<i64>)> = sqlx::query_as("
+        SELECT id, user, started_at, finished_at
+        FROM Stocktake
+        WHERE id = ?").bind(id).fetch_optional(pool).await?;
+    let Some((id, user, started_at, finished_at)) = stocktake else {
+        return Ok(None);
+    };
+
+    let shelves: Vec<types::Shelf> = sqlx::query_as("
+        SELECT Shelf.id, Shelf.name, Shelf.location, ShelfPath.breadcrumb
+        FROM StocktakeShelf
+        INNER JOIN Shelf ON StocktakeShelf.shelf = Shelf.id
+        INNER JOIN ShelfPath ON Shelf.id = ShelfPath.id
+        WHERE StocktakeShelf.stocktake = ?
+        ORDER BY Shelf.name").bind(id).fetch_all(pool).await?;
+
+    Ok(Some(Stocktake {
+        id,
+        user: crud::get_user(pool, user).await?,
+        started_at,
+        finished_at,
+        shelves,
+    }))
+}
+
+pub async fn get_stocktakes(pool: &SqlitePool) -> Result<Vec<StocktakeSummary>, sqlx::Error> {
+    let ids: Vec<u32> = sqlx::query_scalar("SELECT id FROM Stocktake ORDER BY started_at DESC")
+        .fetch_all(pool).await?;
+
+    let mut summaries = vec![];
+    for id in ids {
+        if let Some(report) = get_stocktake_report(pool, id).await? {
+            summaries.push(StocktakeSummary {
+                found: report.found.len(),
+                missing: report.missing.len(),
+                misplaced: report.misplaced.len(),
+                unknown: report.unknown.len(),
+                stocktake: report.stocktake,
+            });
+        }
+    }
+    Ok(summaries)
+}
+
+/// Finishes the stocktake and moves the misplaced copies in `moves` to the shelf they were found on.
+/// Returns false without moving anything when the stocktake was already finished
+pub async fn finish_stocktake(
+    pool: &SqlitePool,
+    id: u32,
+    moves: &[MisplacedCopy],
+    user: Option<u32>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let now = UtcDateTime::now();
+    let finished = sqlx::query("
+        UPDATE Stocktake
+        SET finished_at = ?
+        WHERE id = ? AND finished_at IS NULL").bind(now.unix_timestamp()).bind(id).execute(&mut *tx).await?;
+    if finished.rows_affected() == 0 {
+        return Ok(false);
+    }
+    let reason = format!("Found during stocktake {id}");
+    for misplaced in moves {
+        crud::move_copy(&mut tx, misplaced.copy.copy_id, misplaced.found_on_id, user, Some(&reason)).await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+async fn get_copy(pool: &SqlitePool, copy_id: u32) -> Result<Option<StocktakeCopy>, sqlx::Error> {
+    sqlx::query_as("
+        SELECT PhysicalBook.id AS copy_id, PhysicalBook.code, Book.title, Shelf.id AS shelf_id, Shelf.name AS shelf
+        FROM PhysicalBook
+        INNER JOIN Book ON PhysicalBook.book = Book.id
+        INNER JOIN Shelf ON PhysicalBook.shelf = Shelf.id
+        WHERE PhysicalBook.id = ?").bind(copy_id).fetch_optional(pool).await
+}
+
+async fn get_expected_copies(pool: &SqlitePool, stocktake_id: u32) -> Result<Vec<StocktakeCopy>, sqlx::Error> {
+    sqlx::query_as("
+        SELECT PhysicalBook.id AS copy_id, PhysicalBook.code, Book.title, Shelf.id AS shelf_id, Shelf.name AS shelf
+        FROM StocktakeExpected
+        INNER JOIN PhysicalBook ON StocktakeExpected.copy = PhysicalBook.id
+        INNER JOIN Book ON PhysicalBook.book = Book.id
+        INNER JOIN Shelf ON StocktakeExpected.shelf = Shelf.id
+        WHERE StocktakeExpected.stocktake = ?
+        ORDER BY Shelf.name, Book.title").bind(stocktake_id).fetch_all(pool).await
+}
+
+pub async fn record_scans(
+    pool: &SqlitePool,
+    stocktake_id: u32,
+    shelf: &types::Shelf,
+    scans: &[(String, Option<u32>)],
+) -> Result<Vec<ScanResult>, sqlx::Error> {
+    let now = UtcDateTime::now();
+
+    let mut results = vec![];
+    for (scanned, copy_id) in scans {
+        let copy = match copy_id {
+            Some(copy_id) => get_copy(pool, *copy_id).await?,
+            None => None,
+        };
+        sqlx::query("
+            INSERT INTO StocktakeScan (stocktake, scanned, copy, shelf, copy_shelf, scanned_at)
+            VALUES (?, ?, ?, ?, ?, ?)")
+            .bind(stocktake_id)
+            .bind(scanned)
+            .bind(copy.as_ref().map(|c| c.copy_id))
+            .bind(shelf.id)
+            .bind(copy.as_ref().map(|c| c.shelf_id))
+            .bind(now.unix_timestamp())
+            .execute(pool).await?;
+
+        let status = match &copy {
+            Some(copy) if copy.shelf_id == shelf.id => ScanStatus::Found,
+            Some(_) => ScanStatus::Misplaced,
+            None => ScanStatus::Unknown,
+        };
+        results.push(ScanResult { scanned: scanned.clone(), status, copy });
+    }
+    Ok(results)
+}
+
+#[derive(sqlx::FromRow)]
+struct ScanRow {
+    scanned: String,
+    copy_id: Option<u32>,
+    code: Option<String>,
+    title: Option<String>,
+    copy_shelf_id: Option<u32>,
+    copy_shelf: Option<String>,
+    shelf_id: u32,
+    shelf: String,
+}
+
+pub async fn get_stocktake_report(pool: &SqlitePool, id: u32) -> Result<Option<StocktakeReport>, sqlx::Error> {
+    let Some(stocktake) = get_stocktake(pool, id).await? else {
+        return Ok(None);
+    };
+    let expected = get_expected_copies(pool, id).await?;
+
+    let scans: Vec<ScanRow> = sqlx::query_as("
+        SELECT 
+            StocktakeScan.scanned,
+            StocktakeScan.copy AS copy_id,
+            PhysicalBook.code,
+            Book.title,
+            CopyShelf.id AS copy_shelf_id,
+            CopyShelf.name AS copy_shelf,
+            Shelf.id AS shelf_id,
+            Shelf.name AS shelf
+        FROM StocktakeScan
+        INNER JOIN Shelf ON StocktakeScan.shelf = Shelf.id
+        LEFT JOIN Shelf AS CopyShelf ON StocktakeScan.copy_shelf = CopyShelf.id
+        LEFT JOIN PhysicalBook ON StocktakeScan.copy = PhysicalBook.id
+        LEFT JOIN Book ON PhysicalBook.book = Book.id
+        WHERE StocktakeScan.stocktake = ?
+        ORDER BY StocktakeScan.id DESC").bind(id).fetch_all(pool).await?;
+
+    let mut found = vec![];
+    let mut misplaced = vec![];
+    let mut unknown = vec![];
+    let mut seen = vec![];
+    // Newest scan first, so a copy that was moved during the stocktake counts where it was last seen
+    for scan in scans {
+        let (Some(copy_id), Some(code), Some(title), Some(copy_shelf_id), Some(copy_shelf)) =
+            (scan.copy_id, scan.code, scan.title, scan.copy_shelf_id, scan.copy_shelf) else {
+            if scan.copy_id.is_none() && !unknown.contains(&scan.scanned) {
+                unknown.push(scan.scanned);
+            }
+            continue;
+        };
+        if seen.contains(&copy_id) {
+            continue;
+        }
+        seen.push(copy_id);
+        let copy = StocktakeCopy { copy_id, code, title, shelf_id: copy_shelf_id, shelf: copy_shelf };
+        if copy.shelf_id == scan.shelf_id {
+            found.push(copy);
+        } else {
+            misplaced.push(MisplacedCopy { copy, found_on: scan.shelf, found_on_id: scan.shelf_id });
+        }
+    }
+
+    let missing = expected.into_iter()
+        .filter(|c| !seen.contains(&c.copy_id))
+        .collect();
+
+    Ok(Some(StocktakeReport {
+        stocktake,
+        found,
+        missing,
+        misplaced,
+        unknown,
+    }))
+}
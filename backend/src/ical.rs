@@ -0,0 +1,138 @@
+// iCalendar feed (RFC 5545) of reservations and due dates for calendar apps, reached through a secret URL
+use actix_web::{get, web::{self, Data}, HttpResponse, Responder, Result};
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::{auth::{self, FeedScope}, database::{calendar, crud}, types, AppState};
+
+const CALENDAR_TYPE: &str = "text/calendar; charset=utf-8";
+/// Content lines longer than this many octets have to be folded
+const LINE_LIMIT: usize = 75;
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn format_date_time(date_time: OffsetDateTime) -> String {
+    let utc = date_time.to_offset(UtcOffset::UTC);
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        utc.year(), utc.month() as u8, utc.day(), utc.hour(), utc.minute(), utc.second())
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    format_date_time(OffsetDateTime::from_unix_timestamp(timestamp).unwrap_or(OffsetDateTime::UNIX_EPOCH))
+}
+
+/// Appends the content line, folded so that no line is longer than the limit without splitting a character
+fn push_line(ics: &mut String, line: &str) {
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > LINE_LIMIT {
+            ics.push_str("\r\n ");
+            // The leading space counts towards the continuation line
+            length = 1;
+        }
+        ics.push(c);
+        length += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+/// Host part of the public URL, keeps the event UIDs unique across libraries
+fn uid_domain(public_url: &str) -> &str {
+    let without_scheme = public_url.split_once("://").map_or(public_url, |(_, rest)| rest);
+    without_scheme.split('/').next().unwrap_or(without_scheme)
+}
+
+fn reservation_event(ics: &mut String, domain: &str, stamp: &str, entry: &calendar::ReservationEntry) {
+    push_line(ics, "BEGIN:VEVENT");
+    push_line(ics, &format!("UID:reservation-{}@{domain}", entry.id));
+    push_line(ics, &format!("DTSTAMP:{stamp}"));
+    push_line(ics, &format!("CREATED:{}", format_timestamp(entry.created_at)));
+    push_line(ics, &format!("DTSTART:{}", format_date_time(entry.start_date)));
+    let mut description = format!("Copy {} on {}", entry.code, entry.shelf);
+    match entry.end_date {
+        Some(end_date) => push_line(ics, &format!("DTEND:{}", format_date_time(end_date))),
+        // Without an end the event only marks when the copy is picked up
+        None => description += "\nKept until it is returned",
+    }
+    // Requests still waiting for the owner show up as tentative
+    let (status, summary) = match entry.status {
+        types::ReservationStatus::Pending => ("TENTATIVE", "Requested"),
+        _ => ("CONFIRMED", "Reserved"),
+    };
+    push_line(ics, &format!("STATUS:{status}"));
+    push_line(ics, &format!("SUMMARY:{}", escape(&format!("{summary}: {} ({})", entry.title, entry.holder))));
+    push_line(ics, &format!("LOCATION:{}", escape(&entry.shelf)));
+    push_line(ics, &format!("DESCRIPTION:{}", escape(&description)));
+    push_line(ics, "END:VEVENT");
+}
+
+fn due_event(ics: &mut String, domain: &str, stamp: &str, entry: &calendar::DueEntry) {
+    push_line(ics, "BEGIN:VEVENT");
+    push_line(ics, &format!("UID:loan-{}-due@{domain}", entry.loan_id));
+    push_line(ics, &format!("DTSTAMP:{stamp}"));
+    push_line(ics, &format!("CREATED:{}", format_timestamp(entry.checked_out_at)));
+    push_line(ics, &format!("DTSTART:{}", format_date_time(entry.due_date)));
+    push_line(ics, &format!("SUMMARY:{}", escape(&format!("Due: {} ({})", entry.title, entry.holder))));
+    push_line(ics, &format!("LOCATION:{}", escape(&entry.shelf)));
+    push_line(ics, &format!("DESCRIPTION:{}", escape(&format!("Copy {} goes back to {}", entry.code, entry.shelf))));
+    push_line(ics, "END:VEVENT");
+}
+
+fn calendar(
+    public_url: &str,
+    name: &str,
+    reservations: &[calendar::ReservationEntry],
+    due_dates: &[calendar::DueEntry],
+) -> String {
+    let domain = uid_domain(public_url);
+    let stamp = format_date_time(OffsetDateTime::now_utc());
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//Home Library//Reservations//EN");
+    push_line(&mut ics, "CALSCALE:GREGORIAN");
+    push_line(&mut ics, "METHOD:PUBLISH");
+    push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape(name)));
+    for entry in reservations {
+        reservation_event(&mut ics, domain, &stamp, entry);
+    }
+    for entry in due_dates {
+        due_event(&mut ics, domain, &stamp, entry);
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// Needs no session, the token in the URL is the credential until the feed is revoked
+#[get("/calendar/{token}.ics")]
+pub async fn calendar_feed(state: Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let token = path.into_inner().0;
+    let feed = match auth::validate_calendar_feed(&state.db, &token).await {
+        Ok(Some(feed)) => feed,
+        // Tells nothing about whether the feed ever existed
+        Ok(None) => return Err(actix_web::error::ErrorNotFound("Could not find calendar feed")),
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    };
+    let user = match feed.scope {
+        FeedScope::Mine => Some(feed.user),
+        // Feeds of everything stop working for anyone who is no longer an admin
+        FeedScope::All => match crud::is_admin(&state.db, feed.user).await {
+            Ok(true) => None,
+            Ok(false) => return Err(actix_web::error::ErrorNotFound("Could not find calendar feed")),
+            Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+        },
+    };
+    let reservations = calendar::get_reservation_entries(&state.db, user).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let due_dates = calendar::get_due_entries(&state.db, user).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(CALENDAR_TYPE)
+        .body(calendar(&state.public_url, &feed.name, &reservations, &due_dates)))
+}
@@ -0,0 +1,588 @@
+// Printable label sheets with a scannable code per physical copy
+use qrcode::{Color, QrCode};
+
+/// Everything printed on a single label
+pub struct Label {
+    pub code: String,
+    pub title: String,
+    pub shelf: String,
+    /// Encoded in the QR code or barcode
+    pub url: String,
+}
+
+#[derive(Clone, Copy, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeKind {
+    Qr,
+    Code128,
+}
+
+/// Label sheet geometry, all measurements in millimetres
+#[derive(Clone, Copy)]
+pub struct SheetLayout {
+    pub page_width: f64,
+    pub page_height: f64,
+    pub columns: u32,
+    pub rows: u32,
+    pub label_width: f64,
+    pub label_height: f64,
+    pub margin_top: f64,
+    pub margin_left: f64,
+    /// Distance between the left edges of two neighbouring labels
+    pub horizontal_pitch: f64,
+    /// Distance between the top edges of two neighbouring labels
+    pub vertical_pitch: f64,
+}
+
+impl SheetLayout {
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            // A4, 3 x 7 labels of 63.5 x 38.1 mm
+            "avery-l7160" => Some(Self {
+                page_width: 210.0, page_height: 297.0, columns: 3, rows: 7,
+                label_width: 63.5, label_height: 38.1, margin_top: 15.15, margin_left: 7.25,
+                horizontal_pitch: 66.04, vertical_pitch: 38.1,
+            }),
+            // A4, 3 x 8 labels of 63.5 x 33.9 mm
+            "avery-l7159" => Some(Self {
+                page_width: 210.0, page_height: 297.0, columns: 3, rows: 8,
+                label_width: 63.5, label_height: 33.9, margin_top: 12.9, margin_left: 6.5,
+                horizontal_pitch: 66.0, vertical_pitch: 33.9,
+            }),
+            // A4, 5 x 13 labels of 38.1 x 21.2 mm
+            "avery-l7651" => Some(Self {
+                page_width: 210.0, page_height: 297.0, columns: 5, rows: 13,
+                label_width: 38.1, label_height: 21.2, margin_top: 10.7, margin_left: 4.75,
+                horizontal_pitch: 40.6, vertical_pitch: 21.2,
+            }),
+            // US Letter, 3 x 10 labels of 66.7 x 25.4 mm
+            "avery-5160" => Some(Self {
+                page_width: 215.9, page_height: 279.4, columns: 3, rows: 10,
+                label_width: 66.675, label_height: 25.4, margin_top: 12.7, margin_left: 4.7625,
+                horizontal_pitch: 69.85, vertical_pitch: 25.4,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Only meaningful for a valid layout, larger ones saturate instead of overflowing
+    pub fn labels_per_page(&self) -> usize {
+        self.columns.checked_mul(self.rows).map_or(usize::MAX, |count| count as usize)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let measurements = [
+            self.page_width, self.page_height, self.label_width, self.label_height,
+            self.margin_top, self.margin_left, self.horizontal_pitch, self.vertical_pitch,
+        ];
+        measurements.iter().all(|m| m.is_finite() && *m >= 0.0)
+            && self.columns > 0 && self.rows > 0 && self.label_width > 0.0 && self.label_height > 0.0
+            // Labels on a sheet never overlap
+            && self.horizontal_pitch >= self.label_width && self.vertical_pitch >= self.label_height
+            && self.columns.checked_mul(self.rows).is_some_and(|count| count <= MAX_LABELS_PER_PAGE)
+            && self.margin_left + self.horizontal_pitch * (self.columns - 1) as f64 + self.label_width <= self.page_width
+            && self.margin_top + self.vertical_pitch * (self.rows - 1) as f64 + self.label_height <= self.page_height
+    }
+}
+
+/// More labels than any real sheet has, keeps a page cheap to render
+const MAX_LABELS_PER_PAGE: u32 = 1000;
+
+pub struct SheetOptions {
+    pub layout: SheetLayout,
+    pub code: CodeKind,
+    /// Number of label positions to leave empty at the start of the first sheet
+    pub skip: usize,
+    /// Draws the label borders, useful when aligning the printer
+    pub outline: bool,
+}
+
+// Widths of the alternating bars and spaces of every Code 128 symbol
+const CODE128_PATTERNS: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: usize = 106;
+
+/// Encodes printable ASCII with code set B, returning the bar and space widths in modules
+pub fn code128_widths(data: &str) -> Option<Vec<u8>> {
+    let mut symbols = vec![CODE128_START_B];
+    for c in data.chars() {
+        if !(' '..='~').contains(&c) {
+            return None;
+        }
+        symbols.push(c as usize - 32);
+    }
+    let checksum = symbols.iter().enumerate()
+        .map(|(i, s)| s * i.max(1))
+        .sum::<usize>() % 103;
+    symbols.push(checksum);
+    symbols.push(CODE128_STOP);
+
+    Some(symbols.iter()
+        .flat_map(|s| CODE128_PATTERNS[*s].bytes().map(|b| b - b'0'))
+        .collect())
+}
+
+/// Drawing surface with the origin in the top left corner, measurements in millimetres
+trait Canvas {
+    fn rect(&mut self, x: f64, y: f64, width: f64, height: f64);
+    fn outline(&mut self, x: f64, y: f64, width: f64, height: f64);
+    /// `y` is the text baseline
+    fn text(&mut self, x: f64, y: f64, size: f64, text: &str);
+}
+
+// Rough average glyph width of Helvetica relative to the font size
+const AVERAGE_GLYPH_WIDTH: f64 = 0.52;
+const PADDING: f64 = 2.0;
+
+fn fit_text(text: &str, size: f64, width: f64) -> String {
+    let max_chars = (width / (size * AVERAGE_GLYPH_WIDTH)).floor().max(1.0) as usize;
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut fitted: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    fitted = fitted.trim_end().to_string();
+    fitted.push('…');
+    fitted
+}
+
+/// Splits the text in at most `max_lines` lines, the last one is shortened if needed
+fn wrap_text(text: &str, size: f64, width: f64, max_lines: usize) -> Vec<String> {
+    let max_chars = (width / (size * AVERAGE_GLYPH_WIDTH)).floor().max(1.0) as usize;
+    let mut lines: Vec<String> = vec![];
+    let mut current = String::new();
+    let mut words = text.split_whitespace();
+    for word in words.by_ref() {
+        let candidate = if current.is_empty() { word.to_string() } else { format!("{current} {word}") };
+        if candidate.chars().count() <= max_chars || current.is_empty() {
+            current = candidate;
+            continue;
+        }
+        if lines.len() + 1 == max_lines {
+            current = candidate;
+            break;
+        }
+        lines.push(current);
+        current = word.to_string();
+    }
+    // Anything that did not fit goes to the last line, which is shortened with an ellipsis
+    let rest: Vec<&str> = words.collect();
+    if !rest.is_empty() {
+        current = format!("{current} {}", rest.join(" "));
+    }
+    if !current.is_empty() {
+        lines.push(fit_text(&current, size, width));
+    }
+    lines
+}
+
+fn draw_qr(canvas: &mut impl Canvas, data: &str, x: f64, y: f64, size: f64) {
+    let Ok(code) = QrCode::new(data.as_bytes()) else {
+        return;
+    };
+    let width = code.width();
+    let modules: Vec<bool> = code.to_colors().into_iter().map(|c| c == Color::Dark).collect();
+    // Leave a quiet zone of one module on every side
+    let module = size / (width + 2) as f64;
+    for row in 0..width {
+        let mut col = 0;
+        while col < width {
+            if !modules[row * width + col] {
+                col += 1;
+                continue;
+            }
+            // Merge horizontal runs into one rectangle to keep the output small
+            let start = col;
+            while col < width && modules[row * width + col] {
+                col += 1;
+            }
+            canvas.rect(
+                x + (start + 1) as f64 * module,
+                y + (row + 1) as f64 * module,
+                (col - start) as f64 * module,
+                module,
+            );
+        }
+    }
+}
+
+fn draw_code128(canvas: &mut impl Canvas, widths: &[u8], x: f64, y: f64, width: f64, height: f64) {
+    // Quiet zone of ten modules on both sides
+    let total_modules = widths.iter().map(|w| *w as u32).sum::<u32>() + 20;
+    let module = width / total_modules as f64;
+    let mut position = x + 10.0 * module;
+    for (i, w) in widths.iter().enumerate() {
+        let bar_width = *w as f64 * module;
+        if i % 2 == 0 {
+            canvas.rect(position, y, bar_width, height);
+        }
+        position += bar_width;
+    }
+}
+
+fn draw_label(canvas: &mut impl Canvas, label: &Label, options: &SheetOptions, x: f64, y: f64) {
+    let layout = &options.layout;
+    if options.outline {
+        canvas.outline(x, y, layout.label_width, layout.label_height);
+    }
+    let inner_height = layout.label_height - 2.0 * PADDING;
+    let copy_line = label.code.as_str();
+
+    match options.code {
+        CodeKind::Qr => {
+            draw_qr(canvas, &label.url, x + PADDING, y + PADDING, inner_height);
+            let text_x = x + 2.0 * PADDING + inner_height;
+            let text_width = layout.label_width - inner_height - 3.0 * PADDING;
+            let title_size = (inner_height / 5.0).min(3.5);
+            let small_size = title_size * 0.8;
+            let mut baseline = y + PADDING + title_size;
+            for line in wrap_text(&label.title, title_size, text_width, 2) {
+                canvas.text(text_x, baseline, title_size, &line);
+                baseline += title_size * 1.2;
+            }
+            baseline += small_size * 0.3;
+            canvas.text(text_x, baseline, small_size, &fit_text(&label.shelf, small_size, text_width));
+            canvas.text(text_x, y + layout.label_height - PADDING, small_size, copy_line);
+        }
+        CodeKind::Code128 => {
+            let text_width = layout.label_width - 2.0 * PADDING;
+            let text_size = (inner_height / 6.0).min(3.0);
+            let bar_height = inner_height - 2.4 * text_size;
+            if let Some(widths) = code128_widths(&label.url) {
+                draw_code128(canvas, &widths, x + PADDING, y + PADDING, text_width, bar_height);
+            }
+            let baseline = y + PADDING + bar_height + text_size * 1.1;
+            canvas.text(x + PADDING, baseline, text_size, &fit_text(&label.title, text_size, text_width));
+            let info = format!("{copy_line} · {}", label.shelf);
+            canvas.text(x + PADDING, baseline + text_size * 1.2, text_size * 0.85, &fit_text(&info, text_size * 0.85, text_width));
+        }
+    }
+}
+
+fn draw_page(canvas: &mut impl Canvas, labels: &[Option<&Label>], options: &SheetOptions) {
+    let layout = &options.layout;
+    for (i, label) in labels.iter().enumerate() {
+        let Some(label) = label else {
+            continue;
+        };
+        let col = i as u32 % layout.columns;
+        let row = i as u32 / layout.columns;
+        let x = layout.margin_left + col as f64 * layout.horizontal_pitch;
+        let y = layout.margin_top + row as f64 * layout.vertical_pitch;
+        draw_label(canvas, label, options, x, y);
+    }
+}
+
+/// Label positions per page, `None` marks the skipped positions on the first sheet.
+/// At most the whole first sheet but one position is skipped
+fn paginate<'a>(labels: &'a [Label], options: &SheetOptions) -> Vec<Vec<Option<&'a Label>>> {
+    let per_page = options.layout.labels_per_page().max(1);
+    let slots: Vec<Option<&Label>> = std::iter::repeat_n(None, options.skip.min(per_page - 1))
+        .chain(labels.iter().map(Some))
+        .collect();
+    slots.chunks(per_page)
+        .map(|page| page.to_vec())
+        .collect()
+}
+
+pub fn page_count(labels: &[Label], options: &SheetOptions) -> usize {
+    paginate(labels, options).len().max(1)
+}
+
+struct SvgCanvas {
+    body: String,
+}
+
+pub(crate) fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Canvas for SvgCanvas {
+    fn rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.body += &format!("<rect x=\"{x:.3}\" y=\"{y:.3}\" width=\"{width:.3}\" height=\"{height:.3}\"/>\n");
+    }
+
+    fn outline(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.body += &format!("<rect x=\"{x:.3}\" y=\"{y:.3}\" width=\"{width:.3}\" height=\"{height:.3}\" rx=\"1.5\" fill=\"none\" stroke=\"#bbb\" stroke-width=\"0.2\"/>\n");
+    }
+
+    fn text(&mut self, x: f64, y: f64, size: f64, text: &str) {
+        self.body += &format!("<text x=\"{x:.3}\" y=\"{y:.3}\" font-size=\"{size:.2}\">{}</text>\n", escape_xml(text));
+    }
+}
+
+/// Renders one page (starting at 1) of the label sheet as SVG
+pub fn render_svg(labels: &[Label], options: &SheetOptions, page: usize) -> Option<String> {
+    let pages = paginate(labels, options);
+    let page_labels = match pages.get(page.checked_sub(1)?) {
+        Some(page_labels) => page_labels.clone(),
+        None if page == 1 => vec![],
+        None => return None,
+    };
+    let mut canvas = SvgCanvas { body: String::new() };
+    draw_page(&mut canvas, &page_labels, options);
+    let layout = &options.layout;
+    Some(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}mm\" height=\"{h}mm\" viewBox=\"0 0 {w} {h}\">
+<g fill=\"#000\" font-family=\"Helvetica, Arial, sans-serif\">
+{}</g>
+</svg>
+",
+        canvas.body,
+        w = layout.page_width,
+        h = layout.page_height,
+    ))
+}
+
+struct PdfCanvas {
+    page_height: f64,
+    content: Vec<u8>,
+}
+
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+
+/// Encodes text for the standard WinAnsi encoded Helvetica font, characters outside it become '?'
+fn pdf_string(text: &str) -> Vec<u8> {
+    let mut bytes = vec![b'('];
+    for c in text.chars() {
+        let byte = match c {
+            '…' => 0x85,
+            '·' => 0xB7,
+            c if (c as u32) < 0x80 || (0xA0..=0xFF).contains(&(c as u32)) => c as u32 as u8,
+            _ => b'?',
+        };
+        if matches!(byte, b'(' | b')' | b'\\') {
+            bytes.push(b'\\');
+        }
+        bytes.push(byte);
+    }
+    bytes.push(b')');
+    bytes
+}
+
+impl Canvas for PdfCanvas {
+    fn rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        let bottom = self.page_height - y - height;
+        self.content.extend(format!(
+            "{:.3} {:.3} {:.3} {:.3} re f\n",
+            x * POINTS_PER_MM, bottom * POINTS_PER_MM, width * POINTS_PER_MM, height * POINTS_PER_MM
+        ).bytes());
+    }
+
+    fn outline(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        let bottom = self.page_height - y - height;
+        self.content.extend(format!(
+            "q 0.73 G 0.5 w {:.3} {:.3} {:.3} {:.3} re S Q\n",
+            x * POINTS_PER_MM, bottom * POINTS_PER_MM, width * POINTS_PER_MM, height * POINTS_PER_MM
+        ).bytes());
+    }
+
+    fn text(&mut self, x: f64, y: f64, size: f64, text: &str) {
+        self.content.extend(format!(
+            "BT /F1 {:.2} Tf {:.3} {:.3} Td ",
+            size * POINTS_PER_MM, x * POINTS_PER_MM, (self.page_height - y) * POINTS_PER_MM
+        ).bytes());
+        self.content.extend(pdf_string(text));
+        self.content.extend(b" Tj ET\n");
+    }
+}
+
+/// Renders every page of the label sheet into a single PDF document
+pub fn render_pdf(labels: &[Label], options: &SheetOptions) -> Vec<u8> {
+    let layout = &options.layout;
+    let mut pages = paginate(labels, options);
+    if pages.is_empty() {
+        pages.push(vec![]);
+    }
+
+    // Objects: 1 catalog, 2 page tree, 3 font, then a page and its content stream per page
+    let mut objects: Vec<Vec<u8>> = vec![];
+    let page_ids: Vec<usize> = (0..pages.len()).map(|i| 4 + i * 2).collect();
+    objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+    objects.push(format!(
+        "<< /Type /Pages /Kids [{}] /Count {} >>",
+        page_ids.iter().map(|id| format!("{id} 0 R")).collect::<Vec<_>>().join(" "),
+        pages.len()
+    ).into_bytes());
+    objects.push(b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_vec());
+
+    for (page, page_id) in pages.iter().zip(&page_ids) {
+        let mut canvas = PdfCanvas { page_height: layout.page_height, content: vec![] };
+        draw_page(&mut canvas, page, options);
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.3} {:.3}] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            layout.page_width * POINTS_PER_MM, layout.page_height * POINTS_PER_MM, page_id + 1
+        ).into_bytes());
+        let mut stream = format!("<< /Length {} >>\nstream\n", canvas.content.len()).into_bytes();
+        stream.extend(canvas.content);
+        stream.extend(b"\nendstream");
+        objects.push(stream);
+    }
+
+    let mut pdf = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend(format!("{} 0 obj\n", i + 1).bytes());
+        pdf.extend(object);
+        pdf.extend(b"\nendobj\n");
+    }
+    let xref_offset = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).bytes());
+    for offset in offsets {
+        pdf.extend(format!("{offset:010} 00000 n \n").bytes());
+    }
+    pdf.extend(format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n",
+        objects.len() + 1
+    ).bytes());
+    pdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(code: &str) -> Label {
+        Label {
+            code: code.to_string(),
+            title: "Dune".to_string(),
+            shelf: "Hall".to_string(),
+            url: format!("https://library.example/c/{code}"),
+        }
+    }
+
+    fn options(skip: usize) -> SheetOptions {
+        SheetOptions {
+            layout: SheetLayout::preset("avery-l7160").unwrap(),
+            code: CodeKind::Qr,
+            skip,
+            outline: false,
+        }
+    }
+
+    #[test]
+    fn code128_encodes_start_checksum_and_stop() {
+        // 'A' is symbol 33, the checksum is (104 + 33) % 103 = 34
+        let widths = code128_widths("A").unwrap();
+        let expected: Vec<u8> = "2112141113231311232331112".bytes().map(|b| b - b'0').collect();
+        assert_eq!(widths, expected);
+    }
+
+    #[test]
+    fn code128_symbols_have_fixed_widths() {
+        let data = "https://library.example/c/abc-def";
+        let widths = code128_widths(data).unwrap();
+        // Start, data, checksum and stop, every symbol but the stop is 11 modules wide
+        let modules: u32 = widths.iter().map(|w| *w as u32).sum();
+        assert_eq!(modules, 11 * (data.len() as u32 + 2) + 13);
+        assert_eq!(widths.len(), 6 * (data.len() + 2) + 7);
+    }
+
+    #[test]
+    fn code128_rejects_characters_outside_code_set_b() {
+        assert!(code128_widths("abc\\n").is_some());
+        assert!(code128_widths("tab\t").is_none());
+        assert!(code128_widths("café").is_none());
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        for preset in ["avery-l7160", "avery-l7159", "avery-l7651", "avery-5160"] {
+            assert!(SheetLayout::preset(preset).unwrap().is_valid(), "{preset}");
+        }
+    }
+
+    #[test]
+    fn oversized_and_degenerate_layouts_are_invalid() {
+        let preset = SheetLayout::preset("avery-l7160").unwrap();
+        let huge = SheetLayout { columns: 65536, rows: 65536, horizontal_pitch: 0.0, vertical_pitch: 0.0, ..preset };
+        assert!(!huge.is_valid());
+        assert_eq!(huge.labels_per_page(), usize::MAX);
+        let negative = SheetLayout { horizontal_pitch: -10.0, ..preset };
+        assert!(!negative.is_valid());
+        let overlapping = SheetLayout { vertical_pitch: preset.label_height / 2.0, ..preset };
+        assert!(!overlapping.is_valid());
+        let unbounded = SheetLayout { page_width: f64::INFINITY, ..preset };
+        assert!(!unbounded.is_valid());
+        let nan = SheetLayout { margin_top: f64::NAN, ..preset };
+        assert!(!nan.is_valid());
+    }
+
+    #[test]
+    fn skipped_positions_stay_on_the_first_sheet() {
+        let labels: Vec<Label> = (0..3).map(|i| label(&format!("abc-de{i}"))).collect();
+        let pages = paginate(&labels, &options(2));
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].iter().filter(|slot| slot.is_none()).count(), 2);
+
+        // A skip beyond the sheet leaves only its last position for the first label
+        let pages = paginate(&labels, &options(usize::MAX));
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].len(), 21);
+        assert!(pages[0][20].is_some());
+        assert_eq!(pages[1].len(), 2);
+    }
+
+    #[test]
+    fn pdf_strings_are_escaped_and_encoded() {
+        assert_eq!(pdf_string("a(b)c\\"), b"(a\\(b\\)c\\\\)".to_vec());
+        assert_eq!(pdf_string("Dune…·é"), b"(Dune\x85\xB7\xE9)".to_vec());
+        assert_eq!(pdf_string("日本"), b"(??)".to_vec());
+    }
+
+    #[test]
+    fn pdf_cross_reference_table_points_at_objects() {
+        let labels: Vec<Label> = (0..30).map(|i| label(&format!("abc-d{i:02}"))).collect();
+        let pdf = render_pdf(&labels, &options(0));
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(pdf.starts_with(b"%PDF-1.4\n"));
+        assert!(text.ends_with("%%EOF\n"));
+        // Catalog, page tree, font and a page with its content per sheet
+        assert!(text.contains("/Count 2"));
+
+        let startxref: usize = text.rsplit("startxref\n").next().unwrap()
+            .lines().next().unwrap().parse().unwrap();
+        let xref = std::str::from_utf8(&pdf[startxref..]).unwrap();
+        assert!(xref.starts_with("xref\n0 8\n"));
+        let entries = xref.lines().skip(3).take(7);
+        for (i, entry) in entries.enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            let header = format!("{} 0 obj\n", i + 1);
+            assert!(pdf[offset..].starts_with(header.as_bytes()), "object {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn pdf_stream_lengths_match_their_content() {
+        let pdf = render_pdf(&[label("abc-def")], &options(0));
+        let text = String::from_utf8_lossy(&pdf);
+        let (_, rest) = text.split_once("/Length ").unwrap();
+        let (length, rest) = rest.split_once(" >>\nstream\n").unwrap();
+        let (content, _) = rest.split_once("\nendstream").unwrap();
+        assert_eq!(length.parse::<usize>().unwrap(), content.len());
+    }
+
+    #[test]
+    fn empty_sheet_still_has_a_page() {
+        assert_eq!(page_count(&[], &options(0)), 1);
+        assert!(render_svg(&[], &options(0), 1).is_some());
+        assert!(render_svg(&[], &options(0), 2).is_none());
+        assert!(String::from_utf8_lossy(&render_pdf(&[], &options(0))).contains("/Count 1"));
+    }
+}
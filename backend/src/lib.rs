@@ -2,9 +2,19 @@ pub mod database;
 pub mod routes;
 pub mod types;
 pub mod auth;
+pub mod opds;
+pub mod ical;
+pub mod mailer;
+pub mod labels;
+pub mod map;
+pub mod reshelve;
 
 use sqlx::{Pool, Sqlite};
 
 pub struct AppState {
     pub db: Pool<Sqlite>,
+    /// Externally reachable URL of the backend, used for absolute links in feeds
+    pub public_url: String,
+    /// URL of the web frontend, where scanned labels are redirected
+    pub frontend_url: String,
 }
@@ -0,0 +1,168 @@
+// Reminder emails sent through an SMTP relay, configured with the SMTP_* environment variables
+use std::env;
+
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use sqlx::SqlitePool;
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::database::reminders::{self, Reminder, ReminderKind};
+
+/// How the connection to the relay is secured
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SmtpSecurity {
+    /// Plain text, only for a relay or test sink on the same machine
+    None,
+    StartTls,
+    /// Implicit TLS, usually on port 465
+    Tls,
+}
+
+#[derive(Clone, Debug)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+}
+
+/// Empty variables count as unset, docker compose passes them on that way
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+impl SmtpConfig {
+    /// None when SMTP_HOST is not set, which leaves reminders off
+    pub fn from_env() -> Option<SmtpConfig> {
+        let host = var("SMTP_HOST")?;
+        let security = match var("SMTP_SECURITY").unwrap_or_default().to_lowercase().as_str() {
+            "none" => SmtpSecurity::None,
+            "tls" => SmtpSecurity::Tls,
+            _ => SmtpSecurity::StartTls,
+        };
+        Some(SmtpConfig {
+            host,
+            port: var("SMTP_PORT").and_then(|port| port.parse().ok()),
+            security,
+            username: var("SMTP_USERNAME"),
+            password: var("SMTP_PASSWORD"),
+            from: var("SMTP_FROM").unwrap_or_else(|| "Home Library <library@localhost>".to_string()),
+        })
+    }
+}
+
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    Config(String),
+    Message(lettre::error::Error),
+    Smtp(lettre::transport::smtp::Error),
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailError::Config(message) => write!(f, "Invalid SMTP configuration: {message}"),
+            MailError::Message(err) => write!(f, "Could not build email: {err}"),
+            MailError::Smtp(err) => write!(f, "Could not send email: {err}"),
+        }
+    }
+}
+
+impl Mailer {
+    pub fn new(config: &SmtpConfig) -> Result<Mailer, MailError> {
+        let from: Mailbox = config.from.parse()
+            .map_err(|_| MailError::Config(format!("{} is not a valid sender", config.from)))?;
+        let mut builder = match config.security {
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host).map_err(MailError::Smtp)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host).map_err(MailError::Smtp)?,
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(Mailer { transport: builder.build(), from })
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), MailError> {
+        let to: Mailbox = to.parse()
+            .map_err(|_| MailError::Config(format!("{to} is not a valid recipient")))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(MailError::Message)?;
+        self.transport.send(message).await.map_err(MailError::Smtp)?;
+        Ok(())
+    }
+}
+
+fn format_date(date: OffsetDateTime) -> String {
+    let utc = date.to_offset(UtcOffset::UTC);
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", utc.year(), utc.month() as u8, utc.day(), utc.hour(), utc.minute())
+}
+
+/// Subject and body of the email for the reminder
+pub fn render(reminder: &Reminder) -> (String, String) {
+    let title = &reminder.title;
+    let date = format_date(reminder.date);
+    // How far off the date is depends on when the reminder goes out, so subjects name the day
+    let day = reminder.date.to_offset(UtcOffset::UTC).date();
+    let for_borrower = reminder.borrower.as_ref()
+        .map(|borrower| format!(" for {borrower}"))
+        .unwrap_or_default();
+    let (subject, text) = match reminder.kind {
+        ReminderKind::ReservationStarting => (
+            format!("Your reservation of {title} starts on {day}"),
+            format!("your reservation of {title}{for_borrower} starts {date}. \
+                The copy {} is on {}.", reminder.code, reminder.shelf),
+        ),
+        ReminderKind::DueSoon => (
+            format!("{title} is due on {day}"),
+            format!("the copy {} of {title} you borrowed{for_borrower} is due back on {date}. \
+                It goes back to {}.", reminder.code, reminder.shelf),
+        ),
+        ReminderKind::Overdue => (
+            format!("{title} is overdue"),
+            format!("the copy {} of {title} you borrowed{for_borrower} was due back on {date}. \
+                Please return it to {} or extend the loan.", reminder.code, reminder.shelf),
+        ),
+        ReminderKind::HoldAvailable => (
+            format!("{title} is ready for you"),
+            format!("a copy of {title} you are waiting for is free. \
+                The copy {} on {} is kept for you until {date}.", reminder.code, reminder.shelf),
+        ),
+    };
+    (subject, format!("Hi {},\n\n{text}\n\nHome Library\n", reminder.username))
+}
+
+/// Sends the reminders that are due, each once. Returns how many went out,
+/// failed ones are tried again on the next run
+pub async fn send_reminders(pool: &SqlitePool, mailer: &Mailer) -> Result<u32, sqlx::Error> {
+    let mut sent = 0;
+    for reminder in reminders::get_pending_reminders(pool, OffsetDateTime::now_utc()).await? {
+        let (subject, body) = render(&reminder);
+        match mailer.send(&reminder.email, &subject, body).await {
+            Ok(()) => {
+                reminders::mark_sent(pool, reminder.kind, reminder.subject, reminder.user).await?;
+                sent += 1;
+            }
+            Err(err) => log::warn!("Reminder to {} failed: {err}", reminder.email),
+        }
+    }
+    Ok(sent)
+}
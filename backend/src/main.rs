@@ -1,13 +1,25 @@
-use hll::{auth, database, routes, AppState};
+use hll::{auth, database, ical, mailer, opds, routes, AppState};
 
-use std::{env, vec};
+use std::{env, time::Duration, vec};
 use actix_cors::Cors;
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::{http, HttpMessage};
+use actix_web::{http, HttpMessage, HttpResponse};
 use actix_web::middleware::{self, Logger};
 use actix_web::{web::Data, App, HttpServer};
-use actix_files;
+
+/// Paths that clients without cookie support (e.g. e-readers reading the OPDS feed)
+/// may access with an API token or HTTP basic auth
+fn accepts_header_auth(path: &str) -> bool {
+    path == "/opds" || path.starts_with("/opds/") || path.starts_with("/ebook/") || path.starts_with("/book_cover/")
+}
+
+fn basic_auth_challenge(reason: &'static str) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized()
+        .insert_header((http::header::WWW_AUTHENTICATE, "Basic realm=\"Home Library\", charset=\"UTF-8\""))
+        .body(reason);
+    actix_web::error::InternalError::from_response(reason, response).into()
+}
 
 async fn session_middleware(
     state: Data<AppState>,
@@ -15,11 +27,27 @@ async fn session_middleware(
     next: middleware::Next<impl MessageBody>) 
     -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
     let path = req.path();
-    if path == "/login_user" || path == "/register_user" {
+    // Scanned labels only redirect browsers to the frontend, which handles the login,
+    // calendar feeds carry their own token in the URL
+    let scanned_label = path.starts_with("/c/") && routes::wants_html(req.request());
+    if path == "/login_user" || path == "/register_user" || scanned_label || path.starts_with("/calendar/") {
         return next.call(req).await;
     }
+    if accepts_header_auth(path) && req.cookie(auth::AUTH_COOKIE).is_none() {
+        let Some(credentials) = auth::parse_authorization_header(req.headers().get(http::header::AUTHORIZATION)) else {
+            return Err(basic_auth_challenge("Could not find credentials"));
+        };
+        return match auth::validate_credentials(&state.db, credentials).await {
+            Ok(Some(user)) => {
+                req.extensions_mut().insert(auth::HeaderAuthUser(user));
+                next.call(req).await
+            },
+            Ok(None) => Err(basic_auth_challenge("Credentials unauthorized")),
+            Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+        };
+    }
     match auth::parse_auth_cookie(req.cookie(auth::AUTH_COOKIE)) {
-        None => return Err(actix_web::error::ErrorUnauthorized("Could not find session token")),
+        None => Err(actix_web::error::ErrorUnauthorized("Could not find session token")),
         Some(token) => {
             return match auth::validate_session(&state.db, token).await {
                 Ok(Some(session)) => {
@@ -42,6 +70,30 @@ async fn main() -> std::io::Result<()> {
         .expect("Could not initialize database");
 
     let frontend_url = env::var("ALLOWED_ORIGIN").unwrap(); // Frontend
+    let public_url = env::var("PUBLIC_URL").unwrap_or_default(); // This backend as seen from outside
+
+    // Reservations end and claim windows run out without any request coming in
+    let holds_pool = pool.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(5 * 60));
+        loop {
+            interval.tick().await;
+            let _ = database::holds::process_holds(&holds_pool).await;
+        }
+    });
+
+    // Reminder emails are only sent when an SMTP relay is configured
+    if let Some(config) = mailer::SmtpConfig::from_env() {
+        let mailer = mailer::Mailer::new(&config).expect("Could not set up SMTP relay");
+        let reminders_pool = pool.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(Duration::from_secs(15 * 60));
+            loop {
+                interval.tick().await;
+                let _ = mailer::send_reminders(&reminders_pool, &mailer).await;
+            }
+        });
+    }
 
     HttpServer::new(move || {
         let cors = Cors::default()
@@ -54,25 +106,116 @@ async fn main() -> std::io::Result<()> {
             .wrap(Logger::default())
             .wrap(cors)
             .wrap(middleware::from_fn(session_middleware))
-            .app_data(Data::new(AppState { db: pool.clone() }))
+            .app_data(Data::new(AppState { db: pool.clone(), public_url: public_url.clone(), frontend_url: frontend_url.clone() }))
             .service(routes::get_book)
             .service(routes::get_books)
             .service(routes::get_search_suggestions)
+            .service(routes::create_collection)
+            .service(routes::edit_collection)
+            .service(routes::remove_collection)
+            .service(routes::get_collections)
+            .service(routes::get_collection)
+            .service(routes::export_collection)
             .service(routes::register_book)
             .service(routes::edit_book)
             .service(routes::delete_book)
             .service(routes::add_physical_book)
             .service(routes::edit_physical_book)
+            .service(routes::get_collection_value)
+            .service(routes::get_labels)
+            .service(routes::mark_labels_printed)
+            .service(routes::get_copy_by_code)
+            .service(routes::start_stocktake)
+            .service(routes::stocktake_scan)
+            .service(routes::get_stocktake_report)
+            .service(routes::get_stocktakes)
+            .service(routes::finish_stocktake)
+            .service(routes::get_copy_history)
+            .service(routes::get_shelf_moves)
+            .service(routes::bulk_add_copies)
+            .service(routes::bulk_move_copies)
+            .service(routes::bulk_remove_copies)
+            .service(routes::create_location)
+            .service(routes::edit_location)
+            .service(routes::remove_location)
+            .service(routes::get_locations)
+            .service(routes::get_location_node)
+            .service(routes::place_shelf)
+            .service(routes::get_copy_location)
+            .service(routes::get_copy_map)
+            .service(routes::get_room_map)
+            .service(routes::get_shelf_fill)
+            .service(routes::suggest_shelf)
+            .service(routes::set_shelf_width)
             .service(routes::get_shelves)
+            .service(routes::get_shelf_detail)
+            .service(routes::create_shelf)
+            .service(routes::edit_shelf)
+            .service(routes::merge_shelves)
+            .service(routes::remove_shelf)
+            .service(routes::get_reshelve_plan)
+            .service(routes::arrange_shelf)
             .service(routes::register_user)
             .service(routes::login_user)
             .service(routes::logout_user)
             .service(routes::get_user)
             .service(routes::get_user_reservations)
             .service(routes::reserve_physical_book)
+            .service(routes::reserve_book)
+            .service(routes::get_copy_availability)
+            .service(routes::get_book_availability)
             .service(routes::remove_reservation)
+            .service(routes::get_reservation_requests)
+            .service(routes::approve_reservation)
+            .service(routes::decline_reservation)
+            .service(routes::edit_reservation)
+            .service(routes::place_hold)
+            .service(routes::cancel_hold)
+            .service(routes::claim_hold)
+            .service(routes::get_holds)
+            .service(routes::get_book_holds)
+            .service(routes::check_out)
+            .service(routes::check_in)
+            .service(routes::get_loans)
+            .service(routes::get_copy_loans)
+            .service(routes::get_user_loans)
+            .service(routes::create_borrower)
+            .service(routes::edit_borrower)
+            .service(routes::remove_borrower)
+            .service(routes::get_borrowers)
+            .service(routes::get_borrower_detail)
+            .service(routes::get_overdue_report)
+            .service(routes::create_lending_policy)
+            .service(routes::edit_lending_policy)
+            .service(routes::remove_lending_policy)
+            .service(routes::get_lending_policies)
+            .service(routes::get_lending_limits)
+            .service(routes::set_user_role)
+            .service(routes::set_user_admin)
             .service(routes::change_username)
             .service(routes::change_personal_color)
+            .service(routes::add_ebook)
+            .service(routes::remove_ebook)
+            .service(routes::download_ebook)
+            .service(routes::create_api_token)
+            .service(routes::get_api_tokens)
+            .service(routes::revoke_api_token)
+            .service(routes::create_calendar_feed)
+            .service(routes::get_calendar_feeds)
+            .service(routes::revoke_calendar_feed)
+            .service(ical::calendar_feed)
+            .service(routes::get_reminder_settings)
+            .service(routes::edit_reminder_settings)
+            .service(opds::catalog)
+            .service(opds::recent_feed)
+            .service(opds::authors_feed)
+            .service(opds::author_feed)
+            .service(opds::genres_feed)
+            .service(opds::genre_feed)
+            .service(opds::shelves_feed)
+            .service(opds::shelf_feed)
+            .service(opds::search_feed)
+            .service(opds::opensearch_description)
             .service(actix_files::Files::new("/book_cover", "./db/images/book_covers/"))
     })
     .bind(("0.0.0.0", 8080))?
@@ -0,0 +1,210 @@
+use crate::{labels::escape_xml, types};
+
+// Used when a bookcase has not been measured yet, in centimetres
+const DEFAULT_BOOKCASE_WIDTH: f64 = 80.0;
+const DEFAULT_BOOKCASE_DEPTH: f64 = 30.0;
+const DEFAULT_LEVEL_HEIGHT: f64 = 35.0;
+const MARGIN: f64 = 20.0;
+const HIGHLIGHT: &str = "#e4572e";
+
+/// Spot inside a bookcase to point out on the map
+pub struct Highlight {
+    pub bookcase: u32,
+    pub level: Option<u32>,
+    pub section: Option<u32>,
+}
+
+struct Placed<'a> {
+    bookcase: &'a types::Location,
+    x: f64,
+    y: f64,
+    width: f64,
+    depth: f64,
+    rotation: f64,
+}
+
+impl Placed<'_> {
+    fn corners(&self) -> [(f64, f64); 4] {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        [(0.0, 0.0), (self.width, 0.0), (self.width, self.depth), (0.0, self.depth)]
+            .map(|(x, y)| (self.x + x * cos - y * sin, self.y + x * sin + y * cos))
+    }
+
+    fn center(&self) -> (f64, f64) {
+        let corners = self.corners();
+        ((corners[0].0 + corners[2].0) / 2.0, (corners[0].1 + corners[2].1) / 2.0)
+    }
+}
+
+/// Bookcases without a position are lined up along the back wall
+fn place(bookcases: &[types::Location]) -> Vec<Placed<'_>> {
+    let mut next_x = 0.0;
+    bookcases.iter().map(|bookcase| {
+        let layout = &bookcase.layout;
+        let width = layout.width.unwrap_or(DEFAULT_BOOKCASE_WIDTH);
+        let (x, y) = match (layout.x, layout.y) {
+            (Some(x), Some(y)) => (x, y),
+            _ => {
+                let x = next_x;
+                next_x += width + 10.0;
+                (x, 0.0)
+            }
+        };
+        Placed {
+            bookcase,
+            x,
+            y,
+            width,
+            depth: layout.depth.unwrap_or(DEFAULT_BOOKCASE_DEPTH),
+            rotation: layout.rotation.unwrap_or(0.0),
+        }
+    }).collect()
+}
+
+fn ordinal(n: u32) -> String {
+    const WORDS: [&str; 10] = ["first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth", "tenth"];
+    match WORDS.get(n.wrapping_sub(1) as usize) {
+        Some(word) => word.to_string(),
+        None => match (n % 10, n % 100) {
+            (1, 11) | (2, 12) | (3, 13) => format!("{n}th"),
+            (1, _) => format!("{n}st"),
+            (2, _) => format!("{n}nd"),
+            (3, _) => format!("{n}rd"),
+            _ => format!("{n}th"),
+        },
+    }
+}
+
+/// "third shelf from the top, left side of the Billy 2 bookcase in the Living room"
+pub fn describe(
+    shelf: &types::Shelf,
+    level: Option<u32>,
+    section: Option<u32>,
+    bookcase: Option<&types::Location>,
+    room: Option<&types::Location>,
+) -> String {
+    let mut parts = vec![];
+    match (level, bookcase.and_then(|b| b.layout.levels)) {
+        (Some(level), Some(levels)) if level == levels && levels > 1 => parts.push("bottom shelf".to_string()),
+        (Some(level), Some(levels)) if level > levels.div_ceil(2) => {
+            parts.push(format!("{} shelf from the bottom", ordinal(levels + 1 - level)));
+        }
+        (Some(1), _) => parts.push("top shelf".to_string()),
+        (Some(level), _) => parts.push(format!("{} shelf from the top", ordinal(level))),
+        (None, _) => parts.push(format!("shelf {}", shelf.name)),
+    }
+    match (section, bookcase.and_then(|b| b.layout.sections)) {
+        (Some(1), Some(2)) => parts.push("left side".to_string()),
+        (Some(2), Some(2)) => parts.push("right side".to_string()),
+        (Some(1), Some(3)) => parts.push("left section".to_string()),
+        (Some(2), Some(3)) => parts.push("middle section".to_string()),
+        (Some(3), Some(3)) => parts.push("right section".to_string()),
+        (Some(section), Some(sections)) if sections > 1 => parts.push(format!("{} section from the left", ordinal(section))),
+        _ => {}
+    }
+
+    let mut description = parts.join(", ");
+    if let Some(bookcase) = bookcase {
+        if bookcase.name.to_lowercase().contains("bookcase") {
+            description += &format!(" of the {}", bookcase.name);
+        } else {
+            description += &format!(" of the {} bookcase", bookcase.name);
+        }
+    }
+    if let Some(room) = room {
+        description += &format!(" in the {}", room.name);
+    }
+    description
+}
+
+fn draw_floor_plan(body: &mut String, room: &types::Location, placed: &[Placed], highlight: Option<&Highlight>, width: f64, depth: f64, font: f64) {
+    *body += &format!("<rect x=\"0\" y=\"0\" width=\"{width:.1}\" height=\"{depth:.1}\" fill=\"#fafafa\" stroke=\"#444\" stroke-width=\"{:.2}\"/>\n", font / 6.0);
+    *body += &format!("<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"{font:.1}\" fill=\"#444\">{}</text>\n", font / 2.0, font * 1.2, escape_xml(&room.name));
+
+    for bookcase in placed {
+        let highlighted = highlight.is_some_and(|h| h.bookcase == bookcase.bookcase.id);
+        let fill = if highlighted { HIGHLIGHT } else { "#c9b79c" };
+        *body += &format!(
+            "<g transform=\"translate({:.1} {:.1}) rotate({:.1})\">\n<rect width=\"{:.1}\" height=\"{:.1}\" fill=\"{fill}\" stroke=\"#444\" stroke-width=\"{:.2}\"/>\n",
+            bookcase.x, bookcase.y, bookcase.rotation, bookcase.width, bookcase.depth, font / 8.0,
+        );
+        // The open front of the bookcase
+        *body += &format!(
+            "<line x1=\"0\" y1=\"{d:.1}\" x2=\"{w:.1}\" y2=\"{d:.1}\" stroke=\"#222\" stroke-width=\"{:.2}\"/>\n</g>\n",
+            font / 3.0, d = bookcase.depth, w = bookcase.width,
+        );
+        let (cx, cy) = bookcase.center();
+        *body += &format!(
+            "<text x=\"{cx:.1}\" y=\"{:.1}\" font-size=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+            cy + font / 3.0, font * 0.8, escape_xml(&bookcase.bookcase.name),
+        );
+    }
+}
+
+fn draw_elevation(body: &mut String, bookcase: &Placed, highlight: &Highlight, x: f64, font: f64) -> (f64, f64) {
+    let layout = &bookcase.bookcase.layout;
+    let levels = layout.levels.unwrap_or(1).max(highlight.level.unwrap_or(1)).max(1);
+    let sections = layout.sections.unwrap_or(1).max(highlight.section.unwrap_or(1)).max(1);
+    let width = bookcase.width;
+    let height = layout.height.unwrap_or(levels as f64 * DEFAULT_LEVEL_HEIGHT);
+    let y = font * 2.0;
+    let (level_height, section_width) = (height / levels as f64, width / sections as f64);
+
+    *body += &format!("<text x=\"{x:.1}\" y=\"{:.1}\" font-size=\"{font:.1}\" fill=\"#444\">{}</text>\n", font * 1.2, escape_xml(&bookcase.bookcase.name));
+    *body += &format!("<rect x=\"{x:.1}\" y=\"{y:.1}\" width=\"{width:.1}\" height=\"{height:.1}\" fill=\"#c9b79c\" stroke=\"#444\" stroke-width=\"{:.2}\"/>\n", font / 6.0);
+    if let Some(level) = highlight.level {
+        let (cell_x, cell_width) = match highlight.section {
+            Some(section) => (x + (section - 1) as f64 * section_width, section_width),
+            None => (x, width),
+        };
+        *body += &format!(
+            "<rect x=\"{cell_x:.1}\" y=\"{:.1}\" width=\"{cell_width:.1}\" height=\"{level_height:.1}\" fill=\"{HIGHLIGHT}\"/>\n",
+            y + (level - 1) as f64 * level_height,
+        );
+    }
+    for level in 1..levels {
+        let line_y = y + level as f64 * level_height;
+        *body += &format!("<line x1=\"{x:.1}\" y1=\"{line_y:.1}\" x2=\"{:.1}\" y2=\"{line_y:.1}\" stroke=\"#444\" stroke-width=\"{:.2}\"/>\n", x + width, font / 8.0);
+    }
+    for section in 1..sections {
+        let line_x = x + section as f64 * section_width;
+        *body += &format!("<line x1=\"{line_x:.1}\" y1=\"{y:.1}\" x2=\"{line_x:.1}\" y2=\"{:.1}\" stroke=\"#444\" stroke-width=\"{:.2}\"/>\n", y + height, font / 8.0);
+    }
+    (width, y + height)
+}
+
+/// Floor plan of a room, next to a front view of the highlighted bookcase if any
+pub fn render_room(room: &types::Location, bookcases: &[types::Location], highlight: Option<&Highlight>) -> String {
+    let placed = place(bookcases);
+    let (mut width, mut depth) = placed.iter()
+        .flat_map(|bookcase| bookcase.corners())
+        .fold((0.0_f64, 0.0_f64), |(w, d), (x, y)| (w.max(x + MARGIN), d.max(y + MARGIN)));
+    width = room.layout.width.unwrap_or(width).max(100.0);
+    depth = room.layout.depth.unwrap_or(depth).max(100.0);
+    let font = width.max(depth) / 40.0;
+
+    let mut body = String::new();
+    draw_floor_plan(&mut body, room, &placed, highlight, width, depth, font);
+
+    let (mut total_width, mut total_height) = (width, depth);
+    let highlighted = highlight.and_then(|h| placed.iter().find(|b| b.bookcase.id == h.bookcase).map(|b| (h, b)));
+    if let Some((highlight, bookcase)) = highlighted {
+        let x = width + MARGIN * 2.0;
+        let (elevation_width, elevation_height) = draw_elevation(&mut body, bookcase, highlight, x, font);
+        total_width = x + elevation_width;
+        total_height = total_height.max(elevation_height);
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{x:.1} {y:.1} {w:.1} {h:.1}\">
+<g font-family=\"Helvetica, Arial, sans-serif\">
+{body}</g>
+</svg>
+",
+        x = -MARGIN,
+        y = -MARGIN,
+        w = total_width + MARGIN * 2.0,
+        h = total_height + MARGIN * 2.0,
+    )
+}
@@ -0,0 +1,300 @@
+// OPDS 1.2 catalog (https://specs.opds.io/opds-1.2) so e-readers can browse and download ebooks
+use std::{collections::BTreeMap, path::Path};
+
+use actix_web::{get, web::{self, Data}, HttpResponse, Responder, Result};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{database::{crud, search}, types, AppState};
+
+const NAVIGATION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+const ACQUISITION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+const OPENSEARCH_TYPE: &str = "application/opensearchdescription+xml";
+
+struct NavigationEntry {
+    href: String,
+    title: String,
+    content: String,
+    /// Whether the linked feed lists books rather than further navigation
+    acquisition: bool,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn encode_segment(s: &str) -> String {
+    utf8_percent_encode(s, NON_ALPHANUMERIC).to_string()
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+fn now() -> String {
+    format_timestamp(OffsetDateTime::now_utc().unix_timestamp())
+}
+
+fn feed(base: &str, path: &str, title: &str, kind: &str, entries: String) -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/terms/" xmlns:opds="http://opds-spec.org/2010/catalog">
+  <id>urn:hll:{id}</id>
+  <title>{title}</title>
+  <updated>{updated}</updated>
+  <author><name>Home Library</name></author>
+  <link rel="self" href="{base}{path}" type="{kind}"/>
+  <link rel="start" href="{base}/opds" type="{NAVIGATION_TYPE}"/>
+  <link rel="search" href="{base}/opds/opensearch.xml" type="{OPENSEARCH_TYPE}"/>
+{entries}</feed>
+"#,
+        id = escape(path),
+        title = escape(title),
+        updated = now(),
+        base = escape(base),
+        path = escape(path),
+    )
+}
+
+fn navigation_feed(base: &str, path: &str, title: &str, entries: Vec<NavigationEntry>) -> HttpResponse {
+    let updated = now();
+    let entries: String = entries.iter().map(|entry| format!(r#"  <entry>
+    <title>{title}</title>
+    <id>urn:hll:{id}</id>
+    <updated>{updated}</updated>
+    <content type="text">{content}</content>
+    <link rel="subsection" href="{base}{href}" type="{kind}"/>
+  </entry>
+"#,
+        title = escape(&entry.title),
+        id = escape(&entry.href),
+        content = escape(&entry.content),
+        base = escape(base),
+        href = escape(&entry.href),
+        kind = if entry.acquisition { ACQUISITION_TYPE } else { NAVIGATION_TYPE },
+    )).collect();
+
+    HttpResponse::Ok()
+        .content_type(NAVIGATION_TYPE)
+        .body(feed(base, path, title, NAVIGATION_TYPE, entries))
+}
+
+fn book_entry(base: &str, book: &types::Book, ebooks: &[types::Ebook]) -> String {
+    let mut entry = String::from("  <entry>\n");
+    entry += &format!("    <title>{}</title>\n", escape(&book.title));
+    entry += &format!("    <id>urn:uuid:{}</id>\n", book.uuid);
+    let updated = ebooks.iter().map(|e| e.added_at).max().unwrap_or_default();
+    entry += &format!("    <updated>{}</updated>\n", format_timestamp(updated));
+    for author in &book.authors {
+        entry += &format!("    <author><name>{}</name></author>\n", escape(author));
+    }
+    for genre in &book.genres {
+        entry += &format!("    <category term=\"{0}\" label=\"{0}\"/>\n", escape(genre));
+    }
+    if let Some(isbn) = &book.isbn {
+        entry += &format!("    <dc:identifier>urn:isbn:{}</dc:identifier>\n", escape(isbn));
+    }
+    if let Some(language) = &book.language {
+        entry += &format!("    <dc:language>{}</dc:language>\n", escape(language));
+    }
+    if let Some(year) = book.publication_year {
+        entry += &format!("    <dc:issued>{year}</dc:issued>\n");
+    }
+    if Path::new(&format!("./db/images/book_covers/{}.webp", book.uuid)).exists() {
+        for rel in ["http://opds-spec.org/image", "http://opds-spec.org/image/thumbnail"] {
+            entry += &format!("    <link rel=\"{rel}\" href=\"{}/book_cover/{}.webp\" type=\"image/webp\"/>\n",
+                escape(base), book.uuid);
+        }
+    }
+    for ebook in ebooks {
+        entry += &format!("    <link rel=\"http://opds-spec.org/acquisition\" href=\"{}/ebook/{}\" type=\"{}\" length=\"{}\" title=\"{}\"/>\n",
+            escape(base), ebook.id, escape(&ebook.media_type), ebook.size, escape(&ebook.file_name));
+    }
+    entry += "  </entry>\n";
+    entry
+}
+
+async fn acquisition_feed(state: &AppState, path: &str, title: &str, books: &[types::Book]) -> Result<HttpResponse> {
+    let mut entries = String::new();
+    for book in books {
+        let ebooks = crud::get_book_ebooks(&state.db, book.id).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+        entries += &book_entry(&state.public_url, book, &ebooks);
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ACQUISITION_TYPE)
+        .body(feed(&state.public_url, path, title, ACQUISITION_TYPE, entries)))
+}
+
+async fn books_with_ebooks(state: &AppState, shelf: Option<u32>) -> Result<Vec<types::Book>> {
+    crud::get_books_with_ebooks(&state.db, shelf).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))
+}
+
+fn count_by<'a>(books: &'a [types::Book], key: impl Fn(&'a types::Book) -> &'a Vec<String>) -> BTreeMap<&'a str, usize> {
+    let mut counts = BTreeMap::new();
+    for book in books {
+        for value in key(book) {
+            *counts.entry(value.as_str()).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+fn book_count(count: usize) -> String {
+    match count {
+        1 => String::from("1 book"),
+        n => format!("{n} books"),
+    }
+}
+
+#[get("/opds")]
+pub async fn catalog(state: Data<AppState>) -> Result<impl Responder> {
+    let entries = vec![
+        NavigationEntry { href: "/opds/recent".into(), title: "Recently added".into(), content: "Newest ebooks in the library".into(), acquisition: true },
+        NavigationEntry { href: "/opds/authors".into(), title: "Authors".into(), content: "Browse by author".into(), acquisition: false },
+        NavigationEntry { href: "/opds/genres".into(), title: "Genres".into(), content: "Browse by genre".into(), acquisition: false },
+        NavigationEntry { href: "/opds/shelves".into(), title: "Shelves".into(), content: "Browse by shelf".into(), acquisition: false },
+    ];
+    Ok(navigation_feed(&state.public_url, "/opds", "Home Library", entries))
+}
+
+#[get("/opds/recent")]
+pub async fn recent_feed(state: Data<AppState>) -> Result<impl Responder> {
+    let books = books_with_ebooks(&state, None).await?;
+    acquisition_feed(&state, "/opds/recent", "Recently added", &books[..books.len().min(50)]).await
+}
+
+#[get("/opds/authors")]
+pub async fn authors_feed(state: Data<AppState>) -> Result<impl Responder> {
+    let books = books_with_ebooks(&state, None).await?;
+    let entries = count_by(&books, |b| &b.authors).into_iter()
+        .map(|(author, count)| NavigationEntry {
+            href: format!("/opds/authors/{}", encode_segment(author)),
+            title: author.to_string(),
+            content: book_count(count),
+            acquisition: true,
+        })
+        .collect();
+    Ok(navigation_feed(&state.public_url, "/opds/authors", "Authors", entries))
+}
+
+#[get("/opds/authors/{author}")]
+pub async fn author_feed(state: Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let author = path.into_inner().0;
+    let books: Vec<types::Book> = books_with_ebooks(&state, None).await?
+        .into_iter()
+        .filter(|b| b.authors.contains(&author))
+        .collect();
+    acquisition_feed(&state, &format!("/opds/authors/{}", encode_segment(&author)), &author, &books).await
+}
+
+#[get("/opds/genres")]
+pub async fn genres_feed(state: Data<AppState>) -> Result<impl Responder> {
+    let books = books_with_ebooks(&state, None).await?;
+    let entries = count_by(&books, |b| &b.genres).into_iter()
+        .map(|(genre, count)| NavigationEntry {
+            href: format!("/opds/genres/{}", encode_segment(genre)),
+            title: genre.to_string(),
+            content: book_count(count),
+            acquisition: true,
+        })
+        .collect();
+    Ok(navigation_feed(&state.public_url, "/opds/genres", "Genres", entries))
+}
+
+#[get("/opds/genres/{genre}")]
+pub async fn genre_feed(state: Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let genre = path.into_inner().0;
+    let books: Vec<types::Book> = books_with_ebooks(&state, None).await?
+        .into_iter()
+        .filter(|b| b.genres.contains(&genre))
+        .collect();
+    acquisition_feed(&state, &format!("/opds/genres/{}", encode_segment(&genre)), &genre, &books).await
+}
+
+#[get("/opds/shelves")]
+pub async fn shelves_feed(state: Data<AppState>) -> Result<impl Responder> {
+    let shelves = crud::get_shelves(&state.db).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let mut entries = vec![];
+    for shelf in shelves {
+        let count = books_with_ebooks(&state, Some(shelf.id)).await?.len();
+        if count > 0 {
+            entries.push(NavigationEntry {
+                href: format!("/opds/shelves/{}", shelf.id),
+                title: shelf.name,
+                content: book_count(count),
+                acquisition: true,
+            });
+        }
+    }
+    Ok(navigation_feed(&state.public_url, "/opds/shelves", "Shelves", entries))
+}
+
+#[get("/opds/shelves/{shelf_id}")]
+pub async fn shelf_feed(state: Data<AppState>, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let shelf_id = path.into_inner().0;
+    let Some(shelf) = crud::get_shelf(&state.db, Some(shelf_id), None).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))? else {
+        return Err(actix_web::error::ErrorNotFound("Could not find shelf"));
+    };
+    let books = books_with_ebooks(&state, Some(shelf.id)).await?;
+    acquisition_feed(&state, &format!("/opds/shelves/{}", shelf.id), &shelf.name, &books).await
+}
+
+#[derive(serde::Deserialize)]
+struct OpdsSearchParams {
+    q: String
+}
+
+#[get("/opds/search")]
+pub async fn search_feed(state: Data<AppState>, query: web::Query<OpdsSearchParams>) -> Result<impl Responder> {
+    let spellfix_candidates = search::get_spelling_candidates(&state.db, &query.q, 1).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+
+    let search_str = match spellfix_candidates {
+        Some(c) => c.get_top_candidate(),
+        None => query.q.clone()
+    };
+    let books = crud::query_books(&state.db, Some(&search_str), None, Some(100), false).await
+        .map_err(|err| match err {
+            // Plain SQLITE_ERROR comes from a query the full text index can not parse
+            sqlx::Error::Database(err) if err.code().as_deref() == Some("1") =>
+                actix_web::error::ErrorBadRequest(format!("Invalid search query: {}", err.message())),
+            err => actix_web::error::ErrorInternalServerError(err.to_string()),
+        })?;
+
+    let mut with_ebooks = vec![];
+    for book in books {
+        let has_ebooks = !crud::get_book_ebooks(&state.db, book.id).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?
+            .is_empty();
+        if has_ebooks {
+            with_ebooks.push(book);
+        }
+    }
+
+    let path = format!("/opds/search?q={}", encode_segment(&query.q));
+    acquisition_feed(&state, &path, &format!("Search: {}", query.q), &with_ebooks).await
+}
+
+#[get("/opds/opensearch.xml")]
+pub async fn opensearch_description(state: Data<AppState>) -> Result<impl Responder> {
+    let description = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+  <ShortName>Home Library</ShortName>
+  <Description>Search the home library catalog</Description>
+  <InputEncoding>UTF-8</InputEncoding>
+  <OutputEncoding>UTF-8</OutputEncoding>
+  <Url type="{ACQUISITION_TYPE}" template="{}/opds/search?q={{searchTerms}}"/>
+</OpenSearchDescription>
+"#, escape(&state.public_url));
+    Ok(HttpResponse::Ok().content_type(OPENSEARCH_TYPE).body(description))
+}
@@ -0,0 +1,301 @@
+use std::cmp::Ordering;
+
+use serde::Serialize;
+
+use crate::{database::shelves::ShelvedCopy, types::SortRule};
+
+#[derive(Serialize, Clone)]
+pub struct Neighbour {
+    pub copy_id: u32,
+    pub code: String,
+    pub title: String,
+}
+
+#[derive(Serialize)]
+pub struct PlannedCopy {
+    pub position: u32,
+    #[serde(flatten)]
+    pub copy: Neighbour,
+}
+
+/// One copy to take out and put back between `after` and `before`
+#[derive(Serialize)]
+pub struct ReshelveStep {
+    #[serde(flatten)]
+    pub copy: Neighbour,
+    /// None for copies that were never put in order
+    pub from_position: Option<u32>,
+    pub to_position: u32,
+    pub after: Option<Neighbour>,
+    pub before: Option<Neighbour>,
+}
+
+#[derive(Serialize)]
+pub struct Insertion {
+    pub position: u32,
+    pub after: Option<Neighbour>,
+    pub before: Option<Neighbour>,
+}
+
+#[derive(Serialize)]
+pub struct ReshelvePlan {
+    pub sort_rule: SortRule,
+    /// The shelf as it should look afterwards, from the left
+    pub order: Vec<PlannedCopy>,
+    /// As few moves as possible to get there
+    pub steps: Vec<ReshelveStep>,
+    /// Where a new copy of the requested book would go
+    pub insert: Option<Insertion>,
+}
+
+/// "Le Guin, Ursula K." gives "le guin" but "Ursula K. Le Guin" only "guin",
+/// compound surnames need to be written surname first
+fn surname(author: &str) -> String {
+    let author = author.trim();
+    let surname = match author.split_once(',') {
+        Some((surname, _)) => surname,
+        None => author.rsplit(' ').next().unwrap_or(author),
+    };
+    surname.trim().to_lowercase()
+}
+
+fn first_author(copy: &ShelvedCopy) -> (String, String) {
+    let author = copy.authors.lines().next().unwrap_or_default();
+    (surname(author), author.to_lowercase())
+}
+
+/// Greys go after the colours, light to dark, unknown colours last
+fn color_key(color: Option<&str>) -> (u8, f64, f64) {
+    let Some(rgb) = color.and_then(|c| u32::from_str_radix(c.trim_start_matches('#'), 16).ok()) else {
+        return (2, 0.0, 0.0);
+    };
+    let [r, g, b] = [(rgb >> 16) & 0xff, (rgb >> 8) & 0xff, rgb & 0xff].map(|c| c as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta < 0.08 {
+        return (1, -lightness, 0.0);
+    }
+    let hue = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (0, hue, -lightness)
+}
+
+fn none_last<T>(a: &Option<T>, b: &Option<T>, cmp: impl Fn(&T, &T) -> Ordering) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => cmp(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+fn compare(rule: SortRule, a: &ShelvedCopy, b: &ShelvedCopy) -> Ordering {
+    let by_author = || first_author(a).cmp(&first_author(b));
+    let by_series = || {
+        none_last(&a.series.as_ref().map(|s| s.to_lowercase()), &b.series.as_ref().map(|s| s.to_lowercase()), Ord::cmp)
+            .then_with(|| none_last(&a.series_index, &b.series_index, f64::total_cmp))
+    };
+    let by_year = || none_last(&a.publication_year, &b.publication_year, Ord::cmp);
+    let by_title = || a.title.to_lowercase().cmp(&b.title.to_lowercase());
+    // Keeps equal copies where they already are
+    let by_position = || none_last(&a.position, &b.position, Ord::cmp).then(a.copy_id.cmp(&b.copy_id));
+
+    match rule {
+        SortRule::Author => by_author().then_with(by_series).then_with(by_year).then_with(by_title),
+        SortRule::Series => by_series().then_with(by_author).then_with(by_year).then_with(by_title),
+        SortRule::Color => {
+            let (a_key, b_key) = (color_key(a.color.as_deref()), color_key(b.color.as_deref()));
+            a_key.0.cmp(&b_key.0)
+                .then(a_key.1.total_cmp(&b_key.1))
+                .then(a_key.2.total_cmp(&b_key.2))
+                .then_with(by_author)
+        }
+        SortRule::Manual => Ordering::Equal,
+    }
+    .then_with(by_position)
+}
+
+fn neighbour(copy: &ShelvedCopy) -> Neighbour {
+    Neighbour { copy_id: copy.copy_id, code: copy.code.clone(), title: copy.title.clone() }
+}
+
+/// Indices into `sequence` forming its longest strictly increasing subsequence
+fn longest_increasing(sequence: &[usize]) -> Vec<usize> {
+    // tails[k] is the index of the smallest possible end of an increasing run of length k + 1
+    let mut tails: Vec<usize> = vec![];
+    let mut previous = vec![None; sequence.len()];
+    for (i, value) in sequence.iter().enumerate() {
+        let k = tails.partition_point(|&t| sequence[t] < *value);
+        previous[i] = k.checked_sub(1).map(|k| tails[k]);
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+    let mut run = vec![];
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        run.push(i);
+        next = previous[i];
+    }
+    run.reverse();
+    run
+}
+
+/// `copies` have to be in their current shelf order, unpositioned copies last
+pub fn plan(copies: &[ShelvedCopy], rule: SortRule, new_copy: Option<&ShelvedCopy>) -> ReshelvePlan {
+    let mut target: Vec<&ShelvedCopy> = copies.iter().collect();
+    target.sort_by(|a, b| compare(rule, a, b));
+
+    // Copies already in the right order relative to each other stay, the rest get moved
+    let positioned: Vec<usize> = copies.iter()
+        .filter(|copy| copy.position.is_some())
+        .filter_map(|copy| target.iter().position(|t| t.copy_id == copy.copy_id))
+        .collect();
+    let staying: Vec<usize> = longest_increasing(&positioned).into_iter().map(|i| positioned[i]).collect();
+
+    let steps = target.iter().enumerate()
+        .filter(|(index, _)| !staying.contains(index))
+        .map(|(index, copy)| ReshelveStep {
+            copy: neighbour(copy),
+            from_position: copy.position,
+            to_position: index as u32 + 1,
+            after: index.checked_sub(1).map(|i| neighbour(target[i])),
+            before: target.get(index + 1).map(|c| neighbour(c)),
+        })
+        .collect();
+
+    let insert = new_copy.map(|new_copy| {
+        let index = match rule {
+            SortRule::Manual => target.len(),
+            _ => target.partition_point(|copy| compare(rule, copy, new_copy) == Ordering::Less),
+        };
+        Insertion {
+            position: index as u32 + 1,
+            after: index.checked_sub(1).map(|i| neighbour(target[i])),
+            before: target.get(index).map(|c| neighbour(c)),
+        }
+    });
+
+    ReshelvePlan {
+        sort_rule: rule,
+        order: target.iter().enumerate()
+            .map(|(index, copy)| PlannedCopy { position: index as u32 + 1, copy: neighbour(copy) })
+            .collect(),
+        steps,
+        insert,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy(copy_id: u32, author: &str, title: &str, position: Option<u32>) -> ShelvedCopy {
+        ShelvedCopy {
+            copy_id,
+            code: format!("abc-de{copy_id}"),
+            title: title.to_string(),
+            authors: author.to_string(),
+            series: None,
+            series_index: None,
+            publication_year: None,
+            color: None,
+            position,
+        }
+    }
+
+    fn order(plan: &ReshelvePlan) -> Vec<u32> {
+        plan.order.iter().map(|c| c.copy.copy_id).collect()
+    }
+
+    fn moved(plan: &ReshelvePlan) -> Vec<u32> {
+        plan.steps.iter().map(|s| s.copy.copy_id).collect()
+    }
+
+    #[test]
+    fn longest_increasing_finds_a_longest_run() {
+        assert_eq!(longest_increasing(&[]), Vec::<usize>::new());
+        assert_eq!(longest_increasing(&[3, 2, 1]).len(), 1);
+        let run = longest_increasing(&[0, 4, 1, 5, 2, 3]);
+        assert_eq!(run, vec![0, 2, 4, 5]);
+    }
+
+    #[test]
+    fn sorted_shelf_needs_no_moves() {
+        let copies = vec![
+            copy(1, "Asimov, Isaac", "Foundation", Some(1)),
+            copy(2, "Herbert, Frank", "Dune", Some(2)),
+            copy(3, "Le Guin, Ursula K.", "The Dispossessed", Some(3)),
+        ];
+        let plan = plan(&copies, SortRule::Author, None);
+        assert_eq!(order(&plan), vec![1, 2, 3]);
+        assert!(plan.steps.is_empty());
+    }
+
+    #[test]
+    fn reversed_shelf_keeps_one_copy_in_place() {
+        let copies = vec![
+            copy(3, "Le Guin, Ursula K.", "The Dispossessed", Some(1)),
+            copy(2, "Herbert, Frank", "Dune", Some(2)),
+            copy(1, "Asimov, Isaac", "Foundation", Some(3)),
+        ];
+        let plan = plan(&copies, SortRule::Author, None);
+        assert_eq!(order(&plan), vec![1, 2, 3]);
+        assert_eq!(plan.steps.len(), 2);
+        let step = plan.steps.iter().find(|s| s.copy.copy_id == 3).unwrap();
+        assert_eq!((step.from_position, step.to_position), (Some(1), 3));
+        assert_eq!(step.after.as_ref().map(|n| n.copy_id), Some(2));
+        assert!(step.before.is_none());
+    }
+
+    #[test]
+    fn unpositioned_copies_are_always_placed() {
+        let copies = vec![
+            copy(1, "Asimov, Isaac", "Foundation", Some(1)),
+            copy(3, "Le Guin, Ursula K.", "The Dispossessed", Some(2)),
+            copy(2, "Herbert, Frank", "Dune", None),
+        ];
+        let plan = plan(&copies, SortRule::Author, None);
+        assert_eq!(order(&plan), vec![1, 2, 3]);
+        assert_eq!(moved(&plan), vec![2]);
+        assert_eq!(plan.steps[0].from_position, None);
+        assert_eq!(plan.steps[0].to_position, 2);
+    }
+
+    #[test]
+    fn manual_shelves_keep_their_order() {
+        let copies = vec![
+            copy(2, "Herbert, Frank", "Dune", Some(1)),
+            copy(1, "Asimov, Isaac", "Foundation", Some(2)),
+            copy(3, "Le Guin, Ursula K.", "The Dispossessed", None),
+        ];
+        let new_copy = copy(0, "Banks, Iain M.", "Excession", None);
+        let plan = plan(&copies, SortRule::Manual, Some(&new_copy));
+        assert_eq!(order(&plan), vec![2, 1, 3]);
+        // Only the copy that never had a position is put on the shelf
+        assert_eq!(moved(&plan), vec![3]);
+        assert_eq!(plan.insert.unwrap().position, 4);
+    }
+
+    #[test]
+    fn new_copy_goes_between_its_neighbours() {
+        let copies = vec![
+            copy(1, "Asimov, Isaac", "Foundation", Some(1)),
+            copy(2, "Herbert, Frank", "Dune", Some(2)),
+        ];
+        let new_copy = copy(0, "Banks, Iain M.", "Excession", None);
+        let insert = plan(&copies, SortRule::Author, Some(&new_copy)).insert.unwrap();
+        assert_eq!(insert.position, 2);
+        assert_eq!(insert.after.map(|n| n.copy_id), Some(1));
+        assert_eq!(insert.before.map(|n| n.copy_id), Some(2));
+    }
+}
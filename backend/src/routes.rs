@@ -1,14 +1,15 @@
-use std::{fs, io::BufReader, path::PathBuf};
+use std::{fs, io::BufReader, path::{Path, PathBuf}};
 use image::{self, ImageReader};
 
-use actix_web::{get, post, web::{self, Data}, HttpMessage, HttpRequest, Responder, Result};
+use actix_files::NamedFile;
+use actix_web::{get, http::header::{ContentDisposition, DispositionParam, DispositionType, ACCEPT, LOCATION}, post, web::{self, Data}, HttpMessage, HttpRequest, HttpResponse, Responder, Result};
 
 use actix_multipart::form::{json::Json as MpJson, tempfile::TempFile, MultipartForm};
 use serde::{Serialize, Deserialize};
 use serde_with::rust::double_option;
 use time::OffsetDateTime;
 use uuid::Uuid;
-use crate::{auth::{self, Session}, database::{crud, search}, types, AppState};
+use crate::{auth::{self, Session}, database::{availability, borrowers, bulk, capacity, collections, crud, history, holds, loans, locations, policies, reminders, search, shelves, stocktake}, labels, map, reshelve, types, AppState};
 
 #[derive(Debug, MultipartForm)]
 struct BookAndCoverForm {
@@ -31,6 +32,10 @@ pub struct BookForm {
     pub page_count: Option<Option<u16>>,
     #[serde(default, with = "double_option")]
     pub language: Option<Option<String>>,
+    #[serde(default, with = "double_option")]
+    pub series: Option<Option<String>>,
+    #[serde(default, with = "double_option")]
+    pub series_index: Option<Option<f64>>,
 }
 
 #[post("/register_book")]
@@ -63,7 +68,7 @@ pub async fn edit_book(state: Data<AppState>, MultipartForm(form): MultipartForm
         let reader = BufReader::new(file.file.reopen()?);
         let img = ImageReader::new(reader).with_guessed_format()?.decode()
             .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
-        img.save(format!("./db/images/book_covers/{}.webp", uuid.to_string()))
+        img.save(format!("./db/images/book_covers/{}.webp", uuid))
             .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
     }
     
@@ -72,99 +77,1085 @@ pub async fn edit_book(state: Data<AppState>, MultipartForm(form): MultipartForm
 }
 
 #[post("/delete_book/{book_uuid}")]
-pub async fn delete_book(state: Data<AppState>, path: web::Path<(Uuid,)>) -> Result<impl Responder> {
+pub async fn delete_book(state: Data<AppState>, req: HttpRequest, path: web::Path<(Uuid,)>) -> Result<impl Responder> {
     let uuid = path.into_inner().0;
-    match crud::delete_book(&state.db, uuid).await {
+    // Ebook rows are removed by the cascade, the files have to be removed here
+    if let Ok(book) = crud::get_book(&state.db, None, Some(uuid)).await {
+        let ebooks = crud::get_book_ebooks(&state.db, book.id).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+        for ebook in ebooks {
+            let _ = fs::remove_file(ebook_path(&ebook));
+        }
+    }
+    let user_id = req.extensions().get::<Session>().map(|session| session.user);
+    match crud::delete_book(&state.db, uuid, user_id).await {
         Ok(()) => {},
         Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
     }
-    let file_path: PathBuf = format!("./db/images/book_covers/{}.webp", uuid.to_string()).into();
+    let file_path: PathBuf = format!("./db/images/book_covers/{}.webp", uuid).into();
     if file_path.exists() {
         let _ = fs::remove_file(file_path);
     }
-    Ok(format!("Deleted book with UUID {}", uuid.to_string()))
+    Ok(format!("Deleted book with UUID {}", uuid))
+}
+
+#[derive(Debug, MultipartForm)]
+struct EbookForm {
+    #[multipart(limit = "500MB")]
+    file: TempFile,
+}
+
+fn ebook_media_type(extension: &str) -> Option<&'static str> {
+    match extension.to_lowercase().as_str() {
+        "epub" => Some("application/epub+zip"),
+        "pdf" => Some("application/pdf"),
+        "mobi" => Some("application/x-mobipocket-ebook"),
+        "azw3" => Some("application/vnd.amazon.ebook"),
+        "fb2" => Some("application/x-fictionbook+xml"),
+        "cbz" => Some("application/vnd.comicbook+zip"),
+        "txt" => Some("text/plain"),
+        _ => None
+    }
+}
+
+fn ebook_path(ebook: &types::Ebook) -> PathBuf {
+    let extension = Path::new(&ebook.file_name).extension()
+        .and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    format!("./db/ebooks/{}.{}", ebook.id, extension).into()
+}
+
+#[post("/add_ebook/{book_uuid}")]
+pub async fn add_ebook(state: Data<AppState>, MultipartForm(form): MultipartForm<EbookForm>, path: web::Path<(Uuid,)>) -> Result<impl Responder> {
+    let book = crud::get_book(&state.db, None, Some(path.into_inner().0)).await
+        .map_err(|_| actix_web::error::ErrorNotFound("Could not find book"))?;
+
+    let Some(file_name) = form.file.file_name.clone() else {
+        return Err(actix_web::error::ErrorBadRequest("Ebook file needs a file name"));
+    };
+    let extension = Path::new(&file_name).extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let Some(media_type) = ebook_media_type(extension) else {
+        return Err(actix_web::error::ErrorBadRequest(format!("Unsupported ebook format '{extension}'")));
+    };
+
+    let ebook_id = crud::insert_ebook(&state.db, book.id, &file_name, media_type, form.file.size as i64).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let ebook = crud::get_ebook(&state.db, ebook_id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("Could not store ebook"))?;
+
+    if let Err(err) = fs::create_dir_all("./db/ebooks").and_then(|_| fs::copy(form.file.file.path(), ebook_path(&ebook))) {
+        let _ = crud::remove_ebook(&state.db, ebook.id).await;
+        return Err(actix_web::error::ErrorInternalServerError(err.to_string()));
+    }
+
+    Ok(format!("Added ebook {} to {}", ebook.file_name, book.title))
+}
+
+#[post("/remove_ebook/{ebook_id}")]
+pub async fn remove_ebook(state: Data<AppState>, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let Some(ebook) = crud::get_ebook(&state.db, path.into_inner().0).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))? else {
+        return Err(actix_web::error::ErrorNotFound("Could not find ebook"));
+    };
+    crud::remove_ebook(&state.db, ebook.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let _ = fs::remove_file(ebook_path(&ebook));
+    Ok(format!("Removed ebook {}", ebook.file_name))
+}
+
+#[get("/ebook/{ebook_id}")]
+pub async fn download_ebook(state: Data<AppState>, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let Some(ebook) = crud::get_ebook(&state.db, path.into_inner().0).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))? else {
+        return Err(actix_web::error::ErrorNotFound("Could not find ebook"));
+    };
+    let file = NamedFile::open(ebook_path(&ebook))?
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(ebook.file_name)],
+        });
+    Ok(file)
 }
 
 #[derive(Deserialize)]
 struct ShelfInfo {
     uuid: Uuid,
-    name: String
+    /// Left out to put the copy on the shelf suggested by its size
+    name: Option<String>
 }
 
 #[post("/add_physical_book")]
-pub async fn add_physical_book(state: Data<AppState>, shelf_data: web::Json<ShelfInfo>) -> actix_web::Result<String> {
-    let shelf = crud::get_shelf(&state.db, None, Some(&shelf_data.name)).await
-        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+pub async fn add_physical_book(state: Data<AppState>, req: HttpRequest, shelf_data: web::Json<ShelfInfo>) -> actix_web::Result<String> {
+    let user_id = req.extensions().get::<Session>().map(|session| session.user);
     let book = crud::get_book(&state.db, None, Some(shelf_data.uuid)).await
         .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
-    return match (book, shelf) {
+    let Some(name) = &shelf_data.name else {
+        let thickness = capacity::copy_thickness(book.page_count, None, None);
+        let suggestions = capacity::suggest_shelves(&state.db, &book, thickness).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+        let Some(suggestion) = suggestions.into_iter().next() else {
+            return Err(actix_web::error::ErrorConflict(format!("No measured shelf has {thickness:.1} cm free for {}, pick a shelf", book.title)));
+        };
+        crud::create_physical_book(&state.db, book.id, suggestion.shelf.id, user_id).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+        return Ok(format!("Added a physical copy of {} to suggested shelf {} ({:.1} cm free)", book.title, suggestion.shelf.breadcrumb, suggestion.free - thickness));
+    };
+    let shelf = crud::get_shelf(&state.db, None, Some(name)).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    match (book, shelf) {
         (book, Some(shelf)) => {
-            crud::create_physical_book(&state.db, book.id, shelf.id).await
+            crud::create_physical_book(&state.db, book.id, shelf.id, user_id).await
                 .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
             Ok(format!("Added a physical copy of {} to shelf {}", book.title, shelf.name))
         },
         _ => Err(actix_web::error::ErrorNotFound("Couldn't find book or shelf")),
-    };
+    }
+}
+
+#[derive(Deserialize)]
+struct ShelfFillParams {
+    #[serde(default)]
+    overflowing: bool
+}
+
+#[get("/shelf_fill")]
+pub async fn get_shelf_fill(state: Data<AppState>, params: web::Query<ShelfFillParams>) -> Result<impl Responder> {
+    match capacity::get_shelf_fills(&state.db).await {
+        Ok(fills) => Ok(web::Json(fills.into_iter().filter(|fill| !params.overflowing || fill.overflowing).collect::<Vec<_>>())),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct SuggestShelfParams {
+    format: Option<types::CopyFormat>,
+    thickness: Option<f64>
+}
+
+#[get("/suggest_shelf/{uuid}")]
+pub async fn suggest_shelf(state: Data<AppState>, path: web::Path<(Uuid,)>, params: web::Query<SuggestShelfParams>) -> Result<impl Responder> {
+    let book = crud::get_book(&state.db, None, Some(path.into_inner().0)).await
+        .map_err(|_| actix_web::error::ErrorNotFound("Could not find book"))?;
+    let thickness = capacity::copy_thickness(book.page_count, params.format, params.thickness);
+    match capacity::suggest_shelves(&state.db, &book, thickness).await {
+        Ok(suggestions) => Ok(web::Json(suggestions)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct ShelfWidthData {
+    shelf: String,
+    /// Usable width in centimetres, null to fall back to the bookcase
+    width: Option<f64>
+}
+
+#[post("/set_shelf_width")]
+pub async fn set_shelf_width(state: Data<AppState>, data: web::Json<ShelfWidthData>) -> Result<impl Responder> {
+    if data.width.is_some_and(|width| !width.is_finite() || width <= 0.0) {
+        return Err(actix_web::error::ErrorBadRequest("Width has to be positive"));
+    }
+    let shelf = get_shelf_by_name(&state, &data.shelf).await?;
+    match capacity::set_shelf_width(&state.db, shelf.id, data.width).await {
+        Ok(_) => Ok(format!("Updated width of shelf {}", shelf.name)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+
+fn deserialize_double_option_date<'de, D>(deserializer: D) -> Result<Option<Option<OffsetDateTime>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    time::serde::iso8601::option::deserialize(deserializer).map(Some)
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct CopyDetailsForm {
+    #[serde(default, with = "double_option")]
+    pub condition: Option<Option<types::CopyCondition>>,
+    #[serde(default, deserialize_with = "deserialize_double_option_date")]
+    pub acquired_at: Option<Option<OffsetDateTime>>,
+    #[serde(default, with = "double_option")]
+    pub purchase_price: Option<Option<f64>>,
+    #[serde(default, with = "double_option")]
+    pub currency: Option<Option<String>>,
+    #[serde(default, with = "double_option")]
+    pub source: Option<Option<types::CopySource>>,
+    #[serde(default, with = "double_option")]
+    pub owner: Option<Option<u32>>,
+    pub signed: Option<bool>,
+    pub first_edition: Option<bool>,
+    #[serde(default, with = "double_option")]
+    pub notes: Option<Option<String>>,
+    #[serde(default, with = "double_option")]
+    pub format: Option<Option<types::CopyFormat>>,
+    #[serde(default, with = "double_option")]
+    pub thickness: Option<Option<f64>>,
+    #[serde(default, with = "double_option")]
+    pub color: Option<Option<String>>,
+    pub requires_approval: Option<bool>,
+}
+
+fn normalize_color(color: &str) -> Option<String> {
+    let hex = color.trim().trim_start_matches('#');
+    (hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit())).then(|| format!("#{}", hex.to_lowercase()))
+}
 
 #[derive(Deserialize)]
 struct EditPhysicalBookData {
-    copy_id: u32,
-    new_shelf_name: String
+    copy_id: types::CopyRef,
+    new_shelf_name: Option<String>,
+    /// Kept in the copy's location history when it is moved
+    move_reason: Option<String>,
+    #[serde(flatten)]
+    details: CopyDetailsForm,
+}
+
+async fn resolve_copy(state: &AppState, copy: &types::CopyRef) -> Result<u32> {
+    match crud::resolve_copy(&state.db, copy).await {
+        Ok(Some(copy_id)) => Ok(copy_id),
+        Ok(None) => Err(actix_web::error::ErrorNotFound(format!("Could not find copy {copy}"))),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
 }
 
 #[post("/edit_physical_book")] 
-pub async fn edit_physical_book(state: Data<AppState>, edit_data: web::Json<EditPhysicalBookData>) -> Result<impl Responder> {
+pub async fn edit_physical_book(state: Data<AppState>, req: HttpRequest, edit_data: web::Json<EditPhysicalBookData>) -> Result<impl Responder> {
+    let copy_id = resolve_copy(&state, &edit_data.copy_id).await?;
+    let user_id = req.extensions().get::<Session>().map(|session| session.user);
     // Can remove phyiscal book if new shelf name is left blank
-    if edit_data.new_shelf_name == "" {
-        match crud::remove_physical_book(&state.db, edit_data.copy_id).await {
-            Ok(_) => Ok(format!("Removed physical copy {}", edit_data.copy_id)),
+    if edit_data.new_shelf_name.as_deref() == Some("") {
+        return match crud::remove_physical_book(&state.db, copy_id, user_id).await {
+            Ok(_) => Ok(format!("Removed physical copy {}", copy_id)),
             Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+        };
+    }
+
+    if let Some(Some(price)) = edit_data.details.purchase_price {
+        if price < 0.0 {
+            return Err(actix_web::error::ErrorBadRequest("Purchase price cannot be negative"));
         }
-    } else {
-        match crud::move_physical_book(&state.db, edit_data.copy_id, &edit_data.new_shelf_name).await {
-            Ok(Some(shelf_id)) => Ok(format!("Moved physical copy {} to shelf {} ({})", edit_data.copy_id, edit_data.new_shelf_name, shelf_id)),
-            Ok(None) => Err(actix_web::error::ErrorInternalServerError(format!("Could not find shelf {}", edit_data.new_shelf_name))),
+    }
+    if let Some(Some(thickness)) = edit_data.details.thickness {
+        if !thickness.is_finite() || thickness <= 0.0 {
+            return Err(actix_web::error::ErrorBadRequest("Thickness has to be positive"));
+        }
+    }
+
+    if let Some(Some(owner)) = edit_data.details.owner {
+        crud::get_user(&state.db, owner).await.map_err(|err| match err {
+            sqlx::Error::RowNotFound => actix_web::error::ErrorNotFound(format!("Could not find user {owner}")),
+            err => actix_web::error::ErrorInternalServerError(err.to_string()),
+        })?;
+    }
+
+    if edit_data.details.owner.is_some() || edit_data.details.requires_approval.is_some() {
+        let current_owner = get_copy(&state, copy_id).await?.owner.map(|owner| owner.id);
+        let is_owner = current_owner.is_some() && current_owner == user_id;
+        let admin = match user_id {
+            Some(user) => is_admin(&state, user).await?,
+            None => false,
+        };
+        // Nobody but the owner or an admin gives a copy away or decides who has to ask for it,
+        // anyone else can only claim a copy nobody owns for themselves
+        if current_owner.is_some() && !is_owner && !admin {
+            return Err(actix_web::error::ErrorForbidden("Only the owner or an admin can change who owns the copy or whether it requires approval"));
+        }
+        if let Some(Some(owner)) = edit_data.details.owner {
+            if Some(owner) != user_id && !is_owner && !admin {
+                return Err(actix_web::error::ErrorForbidden("Only the owner of the copy or an admin can give it to someone else"));
+            }
+        }
+        if edit_data.details.requires_approval == Some(true) && edit_data.details.owner.unwrap_or(current_owner).is_none() {
+            return Err(actix_web::error::ErrorBadRequest("Only a copy with an owner can require approval"));
+        }
+    }
+
+    let mut details = edit_data.details.clone();
+    details.currency = details.currency.map(|c| c.map(|c| c.trim().to_uppercase()));
+    if let Some(Some(color)) = &details.color {
+        let Some(color) = normalize_color(color) else {
+            return Err(actix_web::error::ErrorBadRequest("Colour has to be given as #rrggbb"));
+        };
+        details.color = Some(Some(color));
+    }
+    let new_shelf_name = edit_data.new_shelf_name.as_deref();
+    if let Some(name) = new_shelf_name {
+        check_shelf_name(name)?;
+    }
+    match crud::edit_physical_book(&state.db, copy_id, details, new_shelf_name, user_id, edit_data.move_reason.as_deref()).await {
+        Ok(Some(shelf_id)) => Ok(format!("Moved physical copy {} to shelf {} ({})", copy_id, new_shelf_name.unwrap_or_default(), shelf_id)),
+        Ok(None) => Ok(format!("Updated physical copy {}", copy_id)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/collection_value")]
+pub async fn get_collection_value(state: Data<AppState>) -> Result<impl Responder> {
+    match crud::get_collection_value(&state.db).await {
+        Ok(value) => Ok(web::Json(value)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum LabelFormat {
+    Svg,
+    Pdf
+}
+
+#[derive(Deserialize)]
+struct LabelQueryParams {
+    format: Option<LabelFormat>,
+    code: Option<labels::CodeKind>,
+    shelf: Option<String>,
+    book: Option<Uuid>,
+    copies: Option<String>, // Comma separated copy ids or codes
+    unlabeled: Option<bool>,
+    preset: Option<String>,
+    columns: Option<u32>,
+    rows: Option<u32>,
+    page_width: Option<f64>,
+    page_height: Option<f64>,
+    label_width: Option<f64>,
+    label_height: Option<f64>,
+    margin_top: Option<f64>,
+    margin_left: Option<f64>,
+    horizontal_pitch: Option<f64>,
+    vertical_pitch: Option<f64>,
+    skip: Option<usize>,
+    outline: Option<bool>,
+    page: Option<usize>,
+}
+
+async fn resolve_copies(state: &AppState, copies: &[types::CopyRef]) -> Result<Vec<u32>> {
+    let mut copy_ids = vec![];
+    for copy in copies {
+        copy_ids.push(resolve_copy(state, copy).await?);
+    }
+    Ok(copy_ids)
+}
+
+#[get("/labels")]
+pub async fn get_labels(state: Data<AppState>, query: web::Query<LabelQueryParams>) -> Result<impl Responder> {
+    let preset = query.preset.as_deref().unwrap_or("avery-l7160");
+    let Some(mut layout) = labels::SheetLayout::preset(preset) else {
+        return Err(actix_web::error::ErrorBadRequest(format!("Unknown label preset '{preset}'")));
+    };
+    // Any measurement of the preset can be overridden to match other sheets
+    layout.columns = query.columns.unwrap_or(layout.columns);
+    layout.rows = query.rows.unwrap_or(layout.rows);
+    layout.page_width = query.page_width.unwrap_or(layout.page_width);
+    layout.page_height = query.page_height.unwrap_or(layout.page_height);
+    layout.label_width = query.label_width.unwrap_or(layout.label_width);
+    layout.label_height = query.label_height.unwrap_or(layout.label_height);
+    layout.margin_top = query.margin_top.unwrap_or(layout.margin_top);
+    layout.margin_left = query.margin_left.unwrap_or(layout.margin_left);
+    layout.horizontal_pitch = query.horizontal_pitch.unwrap_or(layout.label_width.max(layout.horizontal_pitch));
+    layout.vertical_pitch = query.vertical_pitch.unwrap_or(layout.label_height.max(layout.vertical_pitch));
+    if !layout.is_valid() {
+        return Err(actix_web::error::ErrorBadRequest("Labels have to fit on the page without overlapping"));
+    }
+
+    let copy_refs: Vec<types::CopyRef> = query.copies.as_deref().unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| types::CopyRef::from(s.to_string()))
+        .collect();
+    let copy_ids = resolve_copies(&state, &copy_refs).await?;
+    let copies = crud::get_label_copies(&state.db, 
+        query.shelf.as_deref(), 
+        query.book, 
+        &copy_ids, 
+        query.unlabeled.unwrap_or(false)
+        ).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+
+    let label_list: Vec<labels::Label> = copies.into_iter()
+        .map(|copy| labels::Label {
+            url: format!("{}/c/{}", state.public_url, copy.code),
+            code: copy.code,
+            title: copy.title,
+            shelf: copy.shelf,
+        })
+        .collect();
+
+    let options = labels::SheetOptions {
+        layout,
+        code: query.code.unwrap_or(labels::CodeKind::Qr),
+        skip: query.skip.unwrap_or(0),
+        outline: query.outline.unwrap_or(false),
+    };
+    if options.code == labels::CodeKind::Code128 && label_list.iter().any(|l| labels::code128_widths(&l.url).is_none()) {
+        return Err(actix_web::error::ErrorBadRequest("Copy URL cannot be encoded as Code 128, use a QR code"));
+    }
+
+    let page_count = labels::page_count(&label_list, &options);
+    match query.format.unwrap_or(LabelFormat::Svg) {
+        LabelFormat::Svg => {
+            let Some(svg) = labels::render_svg(&label_list, &options, query.page.unwrap_or(1)) else {
+                return Err(actix_web::error::ErrorNotFound(format!("Label sheet only has {page_count} pages")));
+            };
+            Ok(HttpResponse::Ok()
+                .content_type("image/svg+xml")
+                .insert_header(("X-Page-Count", page_count.to_string()))
+                .body(svg))
+        },
+        LabelFormat::Pdf => Ok(HttpResponse::Ok()
+            .content_type("application/pdf")
+            .insert_header(("X-Page-Count", page_count.to_string()))
+            .body(labels::render_pdf(&label_list, &options))),
+    }
+}
+
+#[derive(Deserialize)]
+struct CopyIdList {
+    copy_ids: Vec<types::CopyRef>
+}
+
+#[post("/mark_labels_printed")]
+pub async fn mark_labels_printed(state: Data<AppState>, data: web::Json<CopyIdList>) -> Result<impl Responder> {
+    let copy_ids = resolve_copies(&state, &data.copy_ids).await?;
+    match crud::mark_copies_labeled(&state.db, &copy_ids).await {
+        Ok(count) => Ok(format!("Marked {count} copies as labeled")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Serialize)]
+struct CopyWithBookResponse {
+    book: types::Book,
+    copy: types::PhysicalBook
+}
+
+/// Browsers asking for a page rather than data, e.g. a phone that scanned a label
+pub fn wants_html(req: &HttpRequest) -> bool {
+    req.headers().get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Target of the scannable label codes. Sends browsers to the copy's book page,
+/// where the frontend handles the login, and returns the copy to everyone else
+#[get("/c/{code}")]
+pub async fn get_copy_by_code(state: Data<AppState>, req: HttpRequest, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let copy_id = resolve_copy(&state, &types::CopyRef::Code(path.into_inner().0)).await?;
+    if wants_html(&req) {
+        return match crud::get_copy_book_uuid(&state.db, copy_id).await {
+            Ok(Some(uuid)) => Ok(HttpResponse::Found()
+                .insert_header((LOCATION, format!("{}/book/{uuid}?copy={copy_id}", state.frontend_url)))
+                .finish()),
+            Ok(None) => Err(actix_web::error::ErrorNotFound("Could not find copy")),
             Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+        };
+    }
+    match crud::get_copy_with_book(&state.db, copy_id).await {
+        Ok(Some((book, copy))) => Ok(HttpResponse::Ok().json(CopyWithBookResponse { book, copy })),
+        Ok(None) => Err(actix_web::error::ErrorNotFound("Could not find copy")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct StartStocktakeData {
+    shelves: Vec<String>
+}
+
+async fn get_shelf_by_name(state: &AppState, name: &str) -> Result<types::Shelf> {
+    match crud::get_shelf(&state.db, None, Some(name)).await {
+        Ok(Some(shelf)) => Ok(shelf),
+        Ok(None) => Err(actix_web::error::ErrorNotFound(format!("Could not find shelf {name}"))),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+async fn get_location(state: &AppState, id: u32) -> Result<types::Location> {
+    match locations::get_location(&state.db, id).await {
+        Ok(Some(location)) => Ok(location),
+        Ok(None) => Err(actix_web::error::ErrorNotFound(format!("Could not find location {id}"))),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+/// Rooms go in homes and bookcases in rooms, levels may be skipped
+async fn check_location_parent(state: &AppState, kind: types::LocationKind, parent: Option<u32>) -> Result<()> {
+    let Some(parent) = parent else {
+        return Ok(());
+    };
+    let parent = get_location(state, parent).await?;
+    if parent.kind >= kind {
+        return Err(actix_web::error::ErrorBadRequest(format!("Cannot place a {kind} inside a {}", parent.kind)));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct LocationLayoutForm {
+    #[serde(default, with = "double_option")]
+    pub width: Option<Option<f64>>,
+    #[serde(default, with = "double_option")]
+    pub depth: Option<Option<f64>>,
+    #[serde(default, with = "double_option")]
+    pub height: Option<Option<f64>>,
+    #[serde(default, with = "double_option")]
+    pub x: Option<Option<f64>>,
+    #[serde(default, with = "double_option")]
+    pub y: Option<Option<f64>>,
+    #[serde(default, with = "double_option")]
+    pub rotation: Option<Option<f64>>,
+    #[serde(default, with = "double_option")]
+    pub levels: Option<Option<u32>>,
+    #[serde(default, with = "double_option")]
+    pub sections: Option<Option<u32>>,
+}
+
+fn check_location_layout(layout: &LocationLayoutForm) -> Result<()> {
+    let sizes = [layout.width, layout.depth, layout.height];
+    if sizes.iter().any(|size| matches!(size, Some(Some(size)) if !size.is_finite() || *size <= 0.0)) {
+        return Err(actix_web::error::ErrorBadRequest("Sizes have to be positive"));
+    }
+    if [layout.levels, layout.sections].contains(&Some(Some(0))) {
+        return Err(actix_web::error::ErrorBadRequest("A bookcase needs at least one level and section"));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct CreateLocationData {
+    name: String,
+    kind: types::LocationKind,
+    parent: Option<u32>,
+    #[serde(flatten)]
+    layout: LocationLayoutForm
+}
+
+#[post("/create_location")]
+pub async fn create_location(state: Data<AppState>, data: web::Json<CreateLocationData>) -> Result<impl Responder> {
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("Location name cannot be empty"));
+    }
+    check_location_parent(&state, data.kind, data.parent).await?;
+    check_location_layout(&data.layout)?;
+    let id = locations::create_location(&state.db, name, data.kind, data.parent).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    locations::edit_location(&state.db, id, None, None, data.layout.clone()).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_location(&state, id).await?))
+}
+
+#[derive(Deserialize)]
+struct EditLocationData {
+    name: Option<String>,
+    #[serde(default, with = "double_option")]
+    parent: Option<Option<u32>>,
+    #[serde(flatten)]
+    layout: LocationLayoutForm
+}
+
+#[post("/edit_location/{location_id}")]
+pub async fn edit_location(state: Data<AppState>, path: web::Path<(u32,)>, data: web::Json<EditLocationData>) -> Result<impl Responder> {
+    let location = get_location(&state, path.into_inner().0).await?;
+    let name = data.name.as_deref().map(str::trim);
+    if name == Some("") {
+        return Err(actix_web::error::ErrorBadRequest("Location name cannot be empty"));
+    }
+    check_location_layout(&data.layout)?;
+    if let Some(parent) = data.parent {
+        check_location_parent(&state, location.kind, parent).await?;
+        if let Some(parent) = parent {
+            let cycle = locations::is_descendant(&state.db, location.id, parent).await
+                .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+            if cycle {
+                return Err(actix_web::error::ErrorBadRequest("A location cannot be placed inside itself"));
+            }
         }
     }
+    locations::edit_location(&state.db, location.id, name, data.parent, data.layout.clone()).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_location(&state, location.id).await?))
+}
+
+#[post("/remove_location/{location_id}")]
+pub async fn remove_location(state: Data<AppState>, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let location = get_location(&state, path.into_inner().0).await?;
+    match locations::remove_location(&state.db, location.id).await {
+        Ok(_) => Ok(format!("Removed location {}", location.breadcrumb)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/locations")]
+pub async fn get_locations(state: Data<AppState>) -> Result<impl Responder> {
+    match locations::get_location_tree(&state.db).await {
+        Ok(tree) => Ok(web::Json(tree)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/location/{location_id}")]
+pub async fn get_location_node(state: Data<AppState>, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    match locations::get_location_node(&state.db, path.into_inner().0).await {
+        Ok(Some(node)) => Ok(web::Json(node)),
+        Ok(None) => Err(actix_web::error::ErrorNotFound("Could not find location")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
 }
 
 #[derive(Deserialize)]
-struct PhysicalBookReservation {
+struct PlaceShelfData {
+    shelf: String,
+    /// Leave out to take the shelf out of the location tree
+    location: Option<u32>,
+    /// Level from the top and section from the left inside a bookcase
+    level: Option<u32>,
+    section: Option<u32>
+}
+
+#[post("/place_shelf")]
+pub async fn place_shelf(state: Data<AppState>, data: web::Json<PlaceShelfData>) -> Result<impl Responder> {
+    let shelf = get_shelf_by_name(&state, &data.shelf).await?;
+    if data.level == Some(0) || data.section == Some(0) {
+        return Err(actix_web::error::ErrorBadRequest("Levels and sections start at 1"));
+    }
+    if let Some(location) = data.location {
+        let location = get_location(&state, location).await?;
+        if data.level.is_some_and(|level| location.layout.levels.is_some_and(|levels| level > levels)) {
+            return Err(actix_web::error::ErrorBadRequest(format!("{} only has {} levels", location.name, location.layout.levels.unwrap_or_default())));
+        }
+        if data.section.is_some_and(|section| location.layout.sections.is_some_and(|sections| section > sections)) {
+            return Err(actix_web::error::ErrorBadRequest(format!("{} only has {} sections", location.name, location.layout.sections.unwrap_or_default())));
+        }
+    }
+    locations::set_shelf_location(&state.db, shelf.id, data.location, data.level, data.section).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_shelf_by_name(&state, &shelf.name).await?))
+}
+
+#[derive(Serialize)]
+struct CopyPlacement {
     copy_id: u32,
-    #[serde(with = "time::serde::iso8601")]
-    start: OffsetDateTime,
-    #[serde(with = "time::serde::iso8601")]
-    end: OffsetDateTime,
+    shelf: types::Shelf,
+    level: Option<u32>,
+    section: Option<u32>,
+    bookcase: Option<types::Location>,
+    room: Option<types::Location>,
+    /// Human readable, e.g. "third shelf from the top, left side of the Billy 2 bookcase in the Living room"
+    description: String,
+    map_url: Option<String>
 }
 
-#[post("/reserve_physical_book")] 
-pub async fn reserve_physical_book(state: Data<AppState>, req: HttpRequest, reservation_data: web::Json<PhysicalBookReservation>) -> Result<impl Responder> {
-    let user_id = match auth::get_user_from_cookie(&state.db, req.cookie(auth::AUTH_COOKIE)).await {
-        Ok(Some(user_id)) => user_id,
-        Ok(None) => return Err(actix_web::error::ErrorUnauthorized("Could not find user to complete request")),
-        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
-    };  
+async fn get_copy_placement(state: &AppState, copy: types::CopyRef) -> Result<CopyPlacement> {
+    let copy_id = resolve_copy(state, &copy).await?;
+    let Some((_, copy)) = crud::get_copy_with_book(&state.db, copy_id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))? else {
+        return Err(actix_web::error::ErrorNotFound("Could not find copy"));
+    };
+    let (level, section) = locations::get_shelf_placement(&state.db, copy.shelf.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let ancestors = match copy.shelf.location {
+        Some(location) => locations::get_location_ancestors(&state.db, location).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?,
+        None => vec![],
+    };
+    let mut bookcase = None;
+    let mut room = None;
+    for location in ancestors {
+        match location.kind {
+            types::LocationKind::Bookcase if bookcase.is_none() => bookcase = Some(location),
+            types::LocationKind::Room if room.is_none() => room = Some(location),
+            _ => {}
+        }
+    }
+    Ok(CopyPlacement {
+        copy_id,
+        description: map::describe(&copy.shelf, level, section, bookcase.as_ref(), room.as_ref()),
+        map_url: room.as_ref().map(|_| format!("{}/copy_map/{copy_id}", state.public_url)),
+        shelf: copy.shelf,
+        level,
+        section,
+        bookcase,
+        room,
+    })
+}
 
-    match crud::reserve_physical_book(&state.db, 
-        user_id, reservation_data.copy_id, reservation_data.start, reservation_data.end).await {
-        Ok(true) => Ok(format!("Reserved physical copy {} to user {}", reservation_data.copy_id, user_id)),
-        Ok(false) => Err(actix_web::error::ErrorConflict("Reservation overlaps with another reservation")),
+async fn render_room_map(state: &AppState, room: &types::Location, highlight: Option<&map::Highlight>) -> Result<HttpResponse> {
+    let children = locations::get_child_locations(&state.db, room.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let bookcases: Vec<types::Location> = children.into_iter()
+        .filter(|location| location.kind == types::LocationKind::Bookcase)
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .body(map::render_room(room, &bookcases, highlight)))
+}
+
+#[get("/copy_location/{copy}")]
+pub async fn get_copy_location(state: Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder> {
+    Ok(web::Json(get_copy_placement(&state, types::CopyRef::from(path.into_inner().0)).await?))
+}
+
+#[get("/copy_map/{copy}")]
+pub async fn get_copy_map(state: Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let placement = get_copy_placement(&state, types::CopyRef::from(path.into_inner().0)).await?;
+    let Some(room) = &placement.room else {
+        return Err(actix_web::error::ErrorNotFound("Copy is not on a shelf inside a room"));
+    };
+    let highlight = placement.bookcase.as_ref().map(|bookcase| map::Highlight {
+        bookcase: bookcase.id,
+        level: placement.level,
+        section: placement.section,
+    });
+    render_room_map(&state, room, highlight.as_ref()).await
+}
+
+#[get("/room_map/{location_id}")]
+pub async fn get_room_map(state: Data<AppState>, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let room = get_location(&state, path.into_inner().0).await?;
+    if room.kind != types::LocationKind::Room {
+        return Err(actix_web::error::ErrorBadRequest(format!("{} is not a room", room.name)));
+    }
+    render_room_map(&state, &room, None).await
+}
+
+async fn get_stocktake(state: &AppState, id: u32) -> Result<stocktake::Stocktake> {
+    match stocktake::get_stocktake(&state.db, id).await {
+        Ok(Some(stocktake)) => Ok(stocktake),
+        Ok(None) => Err(actix_web::error::ErrorNotFound("Could not find stocktake")),
         Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
     }
 }
 
-#[post("/remove_reservation/{reservation_id}")]
-pub async fn remove_reservation(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>) -> Result<impl Responder> {
-    let user_id = match auth::get_user_from_cookie(&state.db, req.cookie(auth::AUTH_COOKIE)).await {
-        Ok(Some(user_id)) => user_id,
-        Ok(None) => return Err(actix_web::error::ErrorUnauthorized("Could not find user to complete request")),
-        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+#[post("/start_stocktake")]
+pub async fn start_stocktake(state: Data<AppState>, req: HttpRequest, data: web::Json<StartStocktakeData>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
     };
+    if data.shelves.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("At least one shelf is needed for a stocktake"));
+    }
+    let mut shelf_ids = vec![];
+    for name in &data.shelves {
+        shelf_ids.push(get_shelf_by_name(&state, name).await?.id);
+    }
+    let stocktake_id = stocktake::start_stocktake(&state.db, session.user, &shelf_ids).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_stocktake(&state, stocktake_id).await?))
+}
 
-    let reservation = match crud::get_reservation(&state.db, path.into_inner().0).await {
+#[derive(Deserialize)]
+struct StocktakeScanData {
+    /// Shelf the copies were seen on, can be left out when the stocktake covers a single shelf
+    shelf: Option<String>,
+    copies: Vec<types::CopyRef>
+}
+
+#[post("/stocktake_scan/{stocktake_id}")]
+pub async fn stocktake_scan(state: Data<AppState>, path: web::Path<(u32,)>, data: web::Json<StocktakeScanData>) -> Result<impl Responder> {
+    let stocktake = get_stocktake(&state, path.into_inner().0).await?;
+    if stocktake.finished_at.is_some() {
+        return Err(actix_web::error::ErrorConflict("Stocktake is already finished"));
+    }
+    let shelf = match (&data.shelf, stocktake.shelves.as_slice()) {
+        (Some(name), _) => get_shelf_by_name(&state, name).await?,
+        (None, [shelf]) => shelf.clone(),
+        (None, _) => return Err(actix_web::error::ErrorBadRequest("Shelf is required when the stocktake covers several shelves"))
+    };
+    if !stocktake.shelves.iter().any(|s| s.id == shelf.id) {
+        return Err(actix_web::error::ErrorBadRequest(format!("Shelf {} is not part of the stocktake", shelf.name)));
+    }
+
+    let mut scans = vec![];
+    for copy in &data.copies {
+        // Scanned labels give the full short URL, only its last part identifies the copy
+        let copy = match copy {
+            types::CopyRef::Code(code) => types::CopyRef::from(code.rsplit('/').next().unwrap_or_default().to_string()),
+            types::CopyRef::Id(id) => types::CopyRef::Id(*id)
+        };
+        let copy_id = crud::resolve_copy(&state.db, &copy).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+        scans.push((copy.to_string(), copy_id));
+    }
+
+    match stocktake::record_scans(&state.db, stocktake.id, &shelf, &scans).await {
+        Ok(results) => Ok(web::Json(results)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/stocktake/{stocktake_id}")]
+pub async fn get_stocktake_report(state: Data<AppState>, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    match stocktake::get_stocktake_report(&state.db, path.into_inner().0).await {
+        Ok(Some(report)) => Ok(web::Json(report)),
+        Ok(None) => Err(actix_web::error::ErrorNotFound("Could not find stocktake")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/stocktakes")]
+pub async fn get_stocktakes(state: Data<AppState>) -> Result<impl Responder> {
+    match stocktake::get_stocktakes(&state.db).await {
+        Ok(stocktakes) => Ok(web::Json(stocktakes)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct FinishStocktakeData {
+    /// Moves misplaced copies to the shelf they were found on
+    #[serde(default)]
+    move_misplaced: bool
+}
+
+#[post("/finish_stocktake/{stocktake_id}")]
+pub async fn finish_stocktake(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>, data: web::Json<FinishStocktakeData>) -> Result<impl Responder> {
+    let stocktake = get_stocktake(&state, path.into_inner().0).await?;
+    // Scans of a finished stocktake are stale, the copies may have been moved since
+    if stocktake.finished_at.is_some() {
+        return Err(actix_web::error::ErrorConflict("Stocktake is already finished"));
+    }
+    let stocktake_id = stocktake.id;
+    let moves = match data.move_misplaced {
+        true => stocktake::get_stocktake_report(&state.db, stocktake_id).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?
+            .ok_or_else(|| actix_web::error::ErrorNotFound("Could not find stocktake"))?
+            .misplaced,
+        false => vec![],
+    };
+    let user_id = req.extensions().get::<Session>().map(|session| session.user);
+    let finished = stocktake::finish_stocktake(&state.db, stocktake_id, &moves, user_id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    if !finished {
+        return Err(actix_web::error::ErrorConflict("Stocktake is already finished"));
+    }
+    match stocktake::get_stocktake_report(&state.db, stocktake_id).await {
+        Ok(Some(report)) => Ok(web::Json(report)),
+        Ok(None) => Err(actix_web::error::ErrorNotFound("Could not find stocktake")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/copy_history/{copy}")]
+pub async fn get_copy_history(state: Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let copy = types::CopyRef::from(path.into_inner().0);
+    let copy_id = crud::resolve_copy(&state.db, &copy).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let history = match (copy_id, &copy) {
+        (Some(copy_id), _) => history::get_copy_history(&state.db, copy_id).await,
+        // A removed copy can still be traced by the code on its label
+        (None, types::CopyRef::Code(code)) => match auth::normalize_copy_code(code) {
+            Some(code) => history::get_removed_copy_history(&state.db, &code).await,
+            None => Ok(vec![]),
+        },
+        (None, types::CopyRef::Id(_)) => Ok(vec![]),
+    }.map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    if copy_id.is_none() && history.is_empty() {
+        return Err(actix_web::error::ErrorNotFound(format!("Could not find copy {copy}")));
+    }
+    Ok(web::Json(history))
+}
+
+#[derive(Deserialize)]
+struct ShelfMovesParams {
+    limit: Option<u32>
+}
+
+#[get("/shelf_moves/{shelf_name}")]
+pub async fn get_shelf_moves(state: Data<AppState>, path: web::Path<(String,)>, params: web::Query<ShelfMovesParams>) -> Result<impl Responder> {
+    let shelf = get_shelf_by_name(&state, &path.into_inner().0).await?;
+    match history::get_shelf_moves(&state.db, shelf.id, params.limit.unwrap_or(50)).await {
+        Ok(moves) => Ok(web::Json(moves)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct BulkAddData {
+    uuid: Uuid,
+    shelf: String,
+    count: u32,
+    #[serde(default)]
+    dry_run: bool
+}
+
+#[post("/bulk_add_copies")]
+pub async fn bulk_add_copies(state: Data<AppState>, req: HttpRequest, data: web::Json<BulkAddData>) -> Result<impl Responder> {
+    if !(1..=100).contains(&data.count) {
+        return Err(actix_web::error::ErrorBadRequest("Can add between 1 and 100 copies at a time"));
+    }
+    let shelf = get_shelf_by_name(&state, &data.shelf).await?;
+    let book = crud::get_book(&state.db, None, Some(data.uuid)).await
+        .map_err(|_| actix_web::error::ErrorNotFound("Could not find book"))?;
+    let user_id = req.extensions().get::<Session>().map(|session| session.user);
+    match bulk::add_copies(&state.db, book.id, shelf.id, data.count, user_id, data.dry_run).await {
+        Ok(result) => Ok(web::Json(result)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct BulkMoveData {
+    /// Either a list of copies or a shelf to empty
+    #[serde(default)]
+    copies: Vec<types::CopyRef>,
+    from_shelf: Option<String>,
+    to_shelf: String,
+    reason: Option<String>,
+    #[serde(default)]
+    dry_run: bool
+}
+
+#[post("/bulk_move_copies")]
+pub async fn bulk_move_copies(state: Data<AppState>, req: HttpRequest, data: web::Json<BulkMoveData>) -> Result<impl Responder> {
+    let mut copy_ids = resolve_copies(&state, &data.copies).await?;
+    if let Some(from_shelf) = &data.from_shelf {
+        let from_shelf = get_shelf_by_name(&state, from_shelf).await?;
+        copy_ids.extend(bulk::get_shelf_copy_ids(&state.db, from_shelf.id).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?);
+    }
+    if copy_ids.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("No copies to move"));
+    }
+    let to_shelf = get_shelf_by_name(&state, &data.to_shelf).await?;
+    let user_id = req.extensions().get::<Session>().map(|session| session.user);
+    match bulk::move_copies(&state.db, &copy_ids, to_shelf.id, user_id, data.reason.as_deref(), data.dry_run).await {
+        Ok(result) => Ok(web::Json(result)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct BulkRemoveData {
+    copies: Vec<types::CopyRef>,
+    #[serde(default)]
+    dry_run: bool
+}
+
+#[post("/bulk_remove_copies")]
+pub async fn bulk_remove_copies(state: Data<AppState>, req: HttpRequest, data: web::Json<BulkRemoveData>) -> Result<impl Responder> {
+    let mut copy_ids = resolve_copies(&state, &data.copies).await?;
+    copy_ids.sort();
+    copy_ids.dedup();
+    if copy_ids.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("No copies to remove"));
+    }
+    let user_id = req.extensions().get::<Session>().map(|session| session.user);
+    match bulk::remove_copies(&state.db, &copy_ids, user_id, data.dry_run).await {
+        Ok(result) => Ok(web::Json(result)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct PhysicalBookReservation {
+    copy_id: types::CopyRef,
+    /// Reserves the copy for someone without an account
+    borrower: Option<u32>,
+    #[serde(with = "time::serde::iso8601")]
+    start: OffsetDateTime,
+    /// Left out to keep the copy until it is returned
+    #[serde(default, with = "time::serde::iso8601::option")]
+    end: Option<OffsetDateTime>,
+}
+
+#[post("/reserve_physical_book")] 
+pub async fn reserve_physical_book(state: Data<AppState>, req: HttpRequest, reservation_data: web::Json<PhysicalBookReservation>) -> Result<impl Responder> {
+    let user_id = match auth::get_user_from_cookie(&state.db, req.cookie(auth::AUTH_COOKIE)).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return Err(actix_web::error::ErrorUnauthorized("Could not find user to complete request")),
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    };  
+
+    if reservation_data.end.is_some_and(|end| end <= reservation_data.start) {
+        return Err(actix_web::error::ErrorBadRequest("Reservation has to end after it starts"));
+    }
+    let copy_id = resolve_copy(&state, &reservation_data.copy_id).await?;
+    let borrower = match reservation_data.borrower {
+        Some(id) => Some(get_borrower(&state, id).await?),
+        None => None,
+    };
+    match crud::reserve_physical_book(&state.db, 
+        user_id, borrower.as_ref().map(|borrower| borrower.id), copy_id, reservation_data.start, reservation_data.end).await {
+        Ok(crud::Reserve::Reserved(_)) => match borrower {
+            Some(borrower) => Ok(format!("Reserved physical copy {} to {}", copy_id, borrower.name)),
+            None => Ok(format!("Reserved physical copy {} to user {}", copy_id, user_id)),
+        },
+        Ok(crud::Reserve::Requested(_)) => Ok(format!("Requested physical copy {}, waiting for its owner to approve", copy_id)),
+        Ok(crud::Reserve::Unavailable) => Err(actix_web::error::ErrorConflict("Reservation overlaps with another reservation, place a hold to get in line")),
+        Ok(crud::Reserve::Refused(violation)) => Err(actix_web::error::ErrorForbidden(violation.to_string())),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct BookReservationData {
+    uuid: Uuid,
+    /// Copies on this shelf are tried first
+    shelf: Option<String>,
+    borrower: Option<u32>,
+    #[serde(with = "time::serde::iso8601")]
+    start: OffsetDateTime,
+    /// Left out to keep the copy until it is returned
+    #[serde(default, with = "time::serde::iso8601::option")]
+    end: Option<OffsetDateTime>,
+}
+
+#[derive(Serialize)]
+struct UnavailableResponse {
+    message: String,
+    /// The earliest time any copy is free for as long, None when there is no copy to wait for
+    earliest: Option<availability::FreeSlot>,
+}
+
+#[post("/reserve_book")]
+pub async fn reserve_book(state: Data<AppState>, req: HttpRequest, data: web::Json<BookReservationData>) -> Result<HttpResponse> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    if data.end.is_some_and(|end| end <= data.start) {
+        return Err(actix_web::error::ErrorBadRequest("Reservation has to end after it starts"));
+    }
+    if data.start < OffsetDateTime::now_utc() {
+        return Err(actix_web::error::ErrorBadRequest("Reservation cannot start in the past"));
+    }
+    let book = crud::get_book(&state.db, None, Some(data.uuid)).await
+        .map_err(|_| actix_web::error::ErrorNotFound("Could not find book"))?;
+    if book.copy_ids.is_empty() {
+        return Err(actix_web::error::ErrorConflict(format!("There are no copies of {}", book.title)));
+    }
+    let shelf = match &data.shelf {
+        Some(name) => Some(get_shelf_by_name(&state, name).await?),
+        None => None,
+    };
+    let borrower = match data.borrower {
+        Some(id) => Some(get_borrower(&state, id).await?),
+        None => None,
+    };
+
+    match availability::reserve_book(&state.db, &book, session.user, borrower.map(|borrower| borrower.id),
+        data.start, data.end, shelf.map(|shelf| shelf.id)).await {
+        Ok(availability::BookReservation::Reserved { reservation_id, .. }) =>
+            Ok(HttpResponse::Ok().json(get_book_reservation(&state, reservation_id).await?)),
+        Ok(availability::BookReservation::Unavailable(earliest)) => Ok(HttpResponse::Conflict().json(UnavailableResponse {
+            message: format!("No copy of {} is free for the whole reservation", book.title),
+            earliest,
+        })),
+        Ok(availability::BookReservation::Refused(violation)) => Err(actix_web::error::ErrorForbidden(violation.to_string())),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[post("/remove_reservation/{reservation_id}")]
+pub async fn remove_reservation(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let user_id = match auth::get_user_from_cookie(&state.db, req.cookie(auth::AUTH_COOKIE)).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return Err(actix_web::error::ErrorUnauthorized("Could not find user to complete request")),
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    };
+
+    let reservation = match crud::get_reservation(&state.db, path.into_inner().0).await {
         Ok(Some(reservation)) => reservation,
         Ok(None) => return Err(actix_web::error::ErrorNotFound("Could not find the reservation to remove")),
         Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
@@ -174,41 +1165,830 @@ pub async fn remove_reservation(state: Data<AppState>, req: HttpRequest, path: w
         return Err(actix_web::error::ErrorForbidden("User does not own reservation"))
     }
 
-    crud::remove_reservation(&state.db, reservation.id).await
-        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
-    Ok(format!("User {user_id} removed reservation {}", reservation.id))
+    crud::remove_reservation(&state.db, reservation.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    // The copy might be free for the next in line now
+    holds::process_holds(&state.db).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(format!("User {user_id} removed reservation {}", reservation.id))
+}
+
+/// Reservations of the user's copies that wait for their approval
+#[get("/reservation_requests")]
+pub async fn get_reservation_requests(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    match crud::get_reservation_requests(&state.db, session.user).await {
+        Ok(requests) => Ok(web::Json(requests)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[post("/approve_reservation/{reservation_id}")]
+pub async fn approve_reservation(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let request = get_request_for_owner(&state, path.into_inner().0, session.user).await?;
+    match crud::approve_reservation(&state.db, &request.reservation, request.copy_id).await {
+        Ok(crud::Reserve::Unavailable) => return Err(actix_web::error::ErrorConflict("The copy has been given to another reservation for some of the time")),
+        Ok(_) => {}
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+    Ok(web::Json(get_book_reservation(&state, request.reservation.id).await?))
+}
+
+#[post("/decline_reservation/{reservation_id}")]
+pub async fn decline_reservation(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let request = get_request_for_owner(&state, path.into_inner().0, session.user).await?;
+    crud::decline_reservation(&state.db, request.reservation.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_book_reservation(&state, request.reservation.id).await?))
+}
+
+#[derive(Deserialize)]
+struct AvailabilityParams {
+    /// Defaults to now
+    #[serde(default, with = "time::serde::iso8601::option")]
+    from: Option<OffsetDateTime>,
+    /// Defaults to 30 days after `from`
+    #[serde(default, with = "time::serde::iso8601::option")]
+    to: Option<OffsetDateTime>,
+    /// Looks for a stretch this long for the next available date
+    days: Option<u32>,
+}
+
+impl AvailabilityParams {
+    fn range(&self) -> Result<(OffsetDateTime, OffsetDateTime, Option<time::Duration>)> {
+        let max_days = availability::MAX_CALENDAR_DAYS;
+        let from = self.from.unwrap_or_else(OffsetDateTime::now_utc);
+        let to = match self.to {
+            Some(to) => to,
+            None => from.checked_add(time::Duration::days(30))
+                .ok_or_else(|| actix_web::error::ErrorBadRequest("The range starts too far ahead"))?,
+        };
+        if to <= from {
+            return Err(actix_web::error::ErrorBadRequest("The range has to end after it starts"));
+        }
+        if to - from > time::Duration::days(max_days as i64) {
+            return Err(actix_web::error::ErrorBadRequest(format!("The range can span at most {max_days} days")));
+        }
+        if self.days == Some(0) {
+            return Err(actix_web::error::ErrorBadRequest("Days has to be at least one"));
+        }
+        if self.days.is_some_and(|days| days > max_days) {
+            return Err(actix_web::error::ErrorBadRequest(format!("Days can be at most {max_days}")));
+        }
+        Ok((from, to, self.days.map(|days| time::Duration::days(days as i64))))
+    }
+}
+
+#[get("/copy_availability/{copy}")]
+pub async fn get_copy_availability(state: Data<AppState>, req: HttpRequest, path: web::Path<(String,)>, params: web::Query<AvailabilityParams>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let (from, to, length) = params.range()?;
+    let copy_id = resolve_copy(&state, &types::CopyRef::from(path.into_inner().0)).await?;
+    let Some((_, copy)) = crud::get_copy_with_book(&state.db, copy_id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))? else {
+        return Err(actix_web::error::ErrorNotFound(format!("Could not find copy {copy_id}")));
+    };
+    match availability::get_copy_calendar(&state.db, &copy, Some(session.user), from, to, length).await {
+        Ok(calendar) => Ok(web::Json(calendar)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/book_availability/{uuid}")]
+pub async fn get_book_availability(state: Data<AppState>, req: HttpRequest, path: web::Path<(Uuid,)>, params: web::Query<AvailabilityParams>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let (from, to, length) = params.range()?;
+    let book = crud::get_book(&state.db, None, Some(path.into_inner().0)).await
+        .map_err(|_| actix_web::error::ErrorNotFound("Could not find book"))?;
+    match availability::get_book_calendar(&state.db, &book, Some(session.user), from, to, length).await {
+        Ok(calendar) => Ok(web::Json(calendar)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct EditReservationData {
+    /// Moves the reservation to another copy
+    copy_id: Option<types::CopyRef>,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    start: Option<OffsetDateTime>,
+    /// Null keeps the copy until it is returned
+    #[serde(default, deserialize_with = "deserialize_double_option_date")]
+    end: Option<Option<OffsetDateTime>>,
+}
+
+#[post("/edit_reservation/{reservation_id}")]
+pub async fn edit_reservation(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>, data: web::Json<EditReservationData>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let current = get_book_reservation(&state, path.into_inner().0).await?;
+    if session.user != current.reservation.user.id {
+        return Err(actix_web::error::ErrorForbidden("User does not own reservation"));
+    }
+
+    let copy_id = match &data.copy_id {
+        Some(copy) => resolve_copy(&state, copy).await?,
+        None => current.copy_id,
+    };
+    let start = data.start.unwrap_or(current.reservation.start_date);
+    let end = data.end.unwrap_or(current.reservation.end_date);
+    if end.is_some_and(|end| end <= start) {
+        return Err(actix_web::error::ErrorBadRequest("Reservation has to end after it starts"));
+    }
+
+    match crud::edit_reservation(&state.db, &current.reservation, copy_id, start, end).await {
+        Ok(crud::Reserve::Reserved(_) | crud::Reserve::Requested(_)) => {}
+        Ok(crud::Reserve::Unavailable) => return Err(actix_web::error::ErrorConflict("Reservation overlaps with another reservation or starts in the past")),
+        Ok(crud::Reserve::Refused(violation)) => return Err(actix_web::error::ErrorForbidden(violation.to_string())),
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+    // Shortening or moving can free the copy for the next in line
+    holds::process_holds(&state.db).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_book_reservation(&state, current.reservation.id).await?))
+}
+
+#[derive(Serialize)]
+#[serde(transparent)]
+struct BookReservationsResponse {
+    reservations: Vec<crud::BookReservation>
+}
+
+#[get("/get_user_reservations")]
+pub async fn get_user_reservations(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
+    let user_id = match auth::get_user_from_cookie(&state.db, req.cookie(auth::AUTH_COOKIE)).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return Err(actix_web::error::ErrorUnauthorized("Could not find user to complete request")),
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    };
+
+    let reservations = match crud::get_user_reservations(&state.db, user_id).await {
+        Ok(reservations) => reservations,
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    };
+    
+    let mut book_reservations = vec![];
+    
+    for rsv in reservations {
+        match crud::get_book_reservation(&state.db, rsv).await {
+            Ok(Some(reservation)) => book_reservations.push(reservation),
+            Ok(None) => {},
+            Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+        };
+    }
+
+    Ok(web::Json(BookReservationsResponse { reservations: book_reservations }))
+}
+
+async fn get_book_reservation(state: &AppState, id: u32) -> Result<crud::BookReservation> {
+    let reservation = match crud::get_reservation(&state.db, id).await {
+        Ok(Some(reservation)) => reservation,
+        Ok(None) => return Err(actix_web::error::ErrorNotFound("Could not find reservation")),
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    };
+    match crud::get_book_reservation(&state.db, reservation).await {
+        Ok(Some(reservation)) => Ok(reservation),
+        Ok(None) => Err(actix_web::error::ErrorNotFound("Could not find the reserved copy")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+async fn is_admin(state: &AppState, user: u32) -> Result<bool> {
+    crud::is_admin(&state.db, user).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))
+}
+
+/// Copies are handed out and taken back by their owner or an admin, anyone else only deals with their own loans
+async fn may_lend(state: &AppState, copy: &types::PhysicalBook, user: u32) -> Result<bool> {
+    if copy.owner.as_ref().is_some_and(|owner| owner.id == user) {
+        return Ok(true);
+    }
+    is_admin(state, user).await
+}
+
+async fn get_copy(state: &AppState, id: u32) -> Result<types::PhysicalBook> {
+    match crud::get_copy_with_book(&state.db, id).await {
+        Ok(Some((_, copy))) => Ok(copy),
+        Ok(None) => Err(actix_web::error::ErrorNotFound("Could not find copy")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+/// A reservation still waiting for a decision by the session's user as owner of the copy
+async fn get_request_for_owner(state: &AppState, id: u32, owner: u32) -> Result<crud::BookReservation> {
+    let request = get_book_reservation(state, id).await?;
+    if get_copy(state, request.copy_id).await?.owner.is_none_or(|copy_owner| copy_owner.id != owner) {
+        return Err(actix_web::error::ErrorForbidden("User does not own the reserved copy"));
+    }
+    if request.reservation.status != types::ReservationStatus::Pending {
+        return Err(actix_web::error::ErrorConflict("Reservation is not waiting for approval"));
+    }
+    Ok(request)
+}
+
+async fn get_own_hold(state: &AppState, id: u32, user: u32) -> Result<holds::Hold> {
+    let hold = match holds::get_hold(&state.db, id).await {
+        Ok(Some(hold)) => hold,
+        Ok(None) => return Err(actix_web::error::ErrorNotFound("Could not find hold")),
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    };
+    if hold.user.id != user {
+        return Err(actix_web::error::ErrorForbidden("User does not own hold"));
+    }
+    Ok(hold)
+}
+
+#[derive(Deserialize)]
+struct PlaceHoldData {
+    uuid: Uuid,
+    /// How long to keep the book once it is your turn
+    days: Option<u32>
+}
+
+#[post("/place_hold")]
+pub async fn place_hold(state: Data<AppState>, req: HttpRequest, data: web::Json<PlaceHoldData>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let book = crud::get_book(&state.db, None, Some(data.uuid)).await
+        .map_err(|_| actix_web::error::ErrorNotFound("Could not find book"))?;
+    if book.copy_ids.is_empty() {
+        return Err(actix_web::error::ErrorConflict(format!("There are no copies of {}", book.title)));
+    }
+    let days = data.days.unwrap_or(holds::DEFAULT_HOLD_DAYS);
+    if days == 0 {
+        return Err(actix_web::error::ErrorBadRequest("A hold has to be for at least one day"));
+    }
+    if days > holds::MAX_HOLD_DAYS {
+        return Err(actix_web::error::ErrorBadRequest(format!("A hold can be for at most {} days", holds::MAX_HOLD_DAYS)));
+    }
+    let Some(id) = holds::place_hold(&state.db, book.id, session.user, days).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))? else {
+        return Err(actix_web::error::ErrorConflict(format!("Already in line for {}", book.title)));
+    };
+    // Offered straight away when a copy is free
+    holds::process_holds(&state.db).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_own_hold(&state, id, session.user).await?))
+}
+
+#[post("/cancel_hold/{id}")]
+pub async fn cancel_hold(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let hold = get_own_hold(&state, path.into_inner().0, session.user).await?;
+    if !matches!(hold.status, holds::HoldStatus::Waiting | holds::HoldStatus::Offered) {
+        return Err(actix_web::error::ErrorConflict("Hold is no longer in line"));
+    }
+    holds::set_status(&state.db, hold.id, holds::HoldStatus::Cancelled, None).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    // An offered copy goes to the next in line
+    holds::process_holds(&state.db).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(format!("Cancelled hold on {}", hold.title))
+}
+
+#[post("/claim_hold/{id}")]
+pub async fn claim_hold(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    holds::process_holds(&state.db).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let hold = get_own_hold(&state, path.into_inner().0, session.user).await?;
+    let (holds::HoldStatus::Offered, Some(copy_id)) = (hold.status, hold.offered_copy) else {
+        return Err(actix_web::error::ErrorConflict(match hold.status {
+            holds::HoldStatus::Expired => "The offer has expired",
+            _ => "No copy has been offered for this hold",
+        }));
+    };
+
+    let start = OffsetDateTime::now_utc();
+    let Some(end) = start.checked_add(time::Duration::days(hold.days as i64)) else {
+        return Err(actix_web::error::ErrorBadRequest(format!("A hold can be for at most {} days", holds::MAX_HOLD_DAYS)));
+    };
+    match crud::reserve_physical_book(&state.db, session.user, None, copy_id, start, Some(end)).await {
+        // The hold is done either way, an owner who declines leaves the requester to try again
+        Ok(crud::Reserve::Reserved(reservation_id) | crud::Reserve::Requested(reservation_id)) => {
+            holds::set_status(&state.db, hold.id, holds::HoldStatus::Claimed, Some(reservation_id)).await
+                .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+            Ok(web::Json(get_own_hold(&state, hold.id, session.user).await?))
+        }
+        // The offer stays, the user might make room for it in time
+        Ok(crud::Reserve::Refused(violation)) => Err(actix_web::error::ErrorForbidden(violation.to_string())),
+        Ok(crud::Reserve::Unavailable) => {
+            // Back in line, keeping the original place
+            holds::withdraw_offer(&state.db, hold.id).await
+                .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+            holds::process_holds(&state.db).await
+                .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+            Err(actix_web::error::ErrorConflict("The offered copy is no longer free"))
+        }
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/holds")]
+pub async fn get_holds(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    holds::process_holds(&state.db).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    match holds::get_user_holds(&state.db, session.user).await {
+        Ok(holds) => Ok(web::Json(holds)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/book_holds/{uuid}")]
+pub async fn get_book_holds(state: Data<AppState>, path: web::Path<(Uuid,)>) -> Result<impl Responder> {
+    let book = crud::get_book(&state.db, None, Some(path.into_inner().0)).await
+        .map_err(|_| actix_web::error::ErrorNotFound("Could not find book"))?;
+    holds::process_holds(&state.db).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    match holds::get_book_holds(&state.db, book.id).await {
+        Ok(holds) => Ok(web::Json(holds)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct CheckOutData {
+    /// Picks up a reservation, the copy, borrower and due date default to the reservation's
+    reservation: Option<u32>,
+    /// Lends a copy without a reservation
+    copy_id: Option<types::CopyRef>,
+    /// Borrower, defaults to the logged in user
+    user: Option<u32>,
+    /// Lends the copy to someone without an account instead of a user
+    borrower: Option<u32>,
+    /// Left out to lend the copy until it is returned, unless the reservation has an end
+    #[serde(default, with = "time::serde::iso8601::option")]
+    due_date: Option<OffsetDateTime>,
+}
+
+#[post("/check_out")]
+pub async fn check_out(state: Data<AppState>, req: HttpRequest, data: web::Json<CheckOutData>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let reservation = match data.reservation {
+        Some(id) => Some(get_book_reservation(&state, id).await?),
+        None => None,
+    };
+
+    let copy_id = match (&data.copy_id, &reservation) {
+        (Some(copy), _) => resolve_copy(&state, copy).await?,
+        (None, Some(reservation)) => reservation.copy_id,
+        (None, None) => return Err(actix_web::error::ErrorBadRequest("Either a reservation or a copy is needed to check out")),
+    };
+    if let Some(reservation) = &reservation {
+        if reservation.copy_id != copy_id {
+            return Err(actix_web::error::ErrorBadRequest(format!("Reservation {} is for another copy", reservation.reservation.id)));
+        }
+        if !reservation.reservation.takes_copy() {
+            return Err(actix_web::error::ErrorConflict(format!("Reservation {} has not been approved", reservation.reservation.id)));
+        }
+    }
+    if data.user.is_some() && data.borrower.is_some() {
+        return Err(actix_web::error::ErrorBadRequest("A copy is lent either to a user or a borrower"));
+    }
+    let reserved_for = reservation.as_ref().map(|reservation| &reservation.reservation);
+    let borrower = match data.user {
+        Some(_) => None,
+        None => data.borrower.or(reserved_for.and_then(|reservation| reservation.borrower.as_ref().map(|borrower| borrower.id))),
+    };
+    // Whoever hands the copy out is kept as the user when it goes to a borrower
+    let user = match borrower {
+        Some(borrower) => {
+            get_borrower(&state, borrower).await?;
+            session.user
+        }
+        None => data.user.or(reserved_for.map(|reservation| reservation.user.id)).unwrap_or(session.user),
+    };
+    crud::get_user(&state.db, user).await.map_err(|err| match err {
+        sqlx::Error::RowNotFound => actix_web::error::ErrorNotFound(format!("Could not find user {user}")),
+        err => actix_web::error::ErrorInternalServerError(err.to_string()),
+    })?;
+    let due_date = data.due_date.or(reservation.as_ref().and_then(|reservation| reservation.reservation.end_date));
+    if due_date.is_some_and(|due_date| due_date <= OffsetDateTime::now_utc()) {
+        return Err(actix_web::error::ErrorBadRequest("Due date has to be in the future"));
+    }
+
+    let Some((_, copy)) = crud::get_copy_with_book(&state.db, copy_id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))? else {
+        return Err(actix_web::error::ErrorNotFound(format!("Could not find copy {copy_id}")));
+    };
+    // Taking a copy yourself, or handing it to the borrower you reserved it for
+    let for_self = match borrower {
+        None => user == session.user,
+        Some(borrower) => reserved_for.is_some_and(|reservation| reservation.user.id == session.user
+            && reservation.borrower.as_ref().is_some_and(|reserved| reserved.id == borrower)),
+    };
+    if !for_self && !may_lend(&state, &copy, session.user).await? {
+        return Err(actix_web::error::ErrorForbidden("Only the owner of the copy can lend it to someone else"));
+    }
+    match loans::check_out(&state.db, &copy, user, borrower, due_date, data.reservation).await {
+        Ok(loans::CheckOut::Lent(loan)) => Ok(web::Json(loan)),
+        Ok(loans::CheckOut::AlreadyLent(loan)) => Err(actix_web::error::ErrorConflict(
+            format!("Copy {} is already lent to {}", loan.code, loan.borrower.map_or(loan.user.username, |borrower| borrower.name)))),
+        Ok(loans::CheckOut::Reserved(reservation)) => Err(actix_web::error::ErrorConflict(
+            format!("Copy is reserved by {} from {}",
+                reservation.borrower.map_or(reservation.user.username, |borrower| borrower.name),
+                reservation.start_date.date()))),
+        Ok(loans::CheckOut::Held(hold)) => Err(actix_web::error::ErrorConflict(
+            format!("Copy is kept for {} until they claim it", hold.user.username))),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct CheckInData {
+    copy_id: types::CopyRef
+}
+
+#[post("/check_in")]
+pub async fn check_in(state: Data<AppState>, req: HttpRequest, data: web::Json<CheckInData>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let copy_id = resolve_copy(&state, &data.copy_id).await?;
+    let active = loans::get_active_loan(&state.db, copy_id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    // The borrower, or whoever handed the copy out, returns it
+    if active.is_some_and(|loan| loan.user.id != session.user) && !may_lend(&state, &get_copy(&state, copy_id).await?, session.user).await? {
+        return Err(actix_web::error::ErrorForbidden("Only the owner of the copy can take back someone else's loan"));
+    }
+    match loans::check_in(&state.db, copy_id).await {
+        Ok(Some(loan)) => {
+            holds::process_holds(&state.db).await
+                .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+            Ok(web::Json(loan))
+        }
+        Ok(None) => Err(actix_web::error::ErrorConflict(format!("Copy {} is not lent out", data.copy_id))),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct LoansParams {
+    user: Option<u32>,
+    borrower: Option<u32>,
+    /// Only copies that are still out
+    #[serde(default)]
+    active: bool,
+    /// Only copies that are out past their due date
+    #[serde(default)]
+    overdue: bool
+}
+
+#[get("/loans")]
+pub async fn get_loans(state: Data<AppState>, req: HttpRequest, params: web::Query<LoansParams>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    if params.user.is_some_and(|user| user != session.user) && !is_admin(&state, session.user).await? {
+        return Err(actix_web::error::ErrorForbidden("Only admins can see the loans of other users"));
+    }
+    let filter = loans::LoanFilter {
+        user: params.user,
+        borrower: params.borrower,
+        active: params.active || params.overdue,
+        ..Default::default()
+    };
+    match loans::get_loans(&state.db, &filter).await {
+        Ok(loans) => Ok(web::Json(loans.into_iter().filter(|loan| loan.overdue || !params.overdue).collect::<Vec<_>>())),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/copy_loans/{copy}")]
+pub async fn get_copy_loans(state: Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let copy_id = resolve_copy(&state, &types::CopyRef::from(path.into_inner().0)).await?;
+    match loans::get_loans(&state.db, &loans::LoanFilter { copy: Some(copy_id), ..Default::default() }).await {
+        Ok(loans) => Ok(web::Json(loans)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/user_loans")]
+pub async fn get_user_loans(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    match loans::get_loans(&state.db, &loans::LoanFilter { user: Some(session.user), ..Default::default() }).await {
+        Ok(loans) => Ok(web::Json(loans)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+async fn get_borrower(state: &AppState, id: u32) -> Result<types::Borrower> {
+    match borrowers::get_borrower(&state.db, id).await {
+        Ok(Some(borrower)) => Ok(borrower),
+        Ok(None) => Err(actix_web::error::ErrorNotFound(format!("Could not find borrower {id}"))),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateBorrowerData {
+    name: String,
+    phone: Option<String>,
+    email: Option<String>,
+    notes: Option<String>
+}
+
+#[post("/create_borrower")]
+pub async fn create_borrower(state: Data<AppState>, data: web::Json<CreateBorrowerData>) -> Result<impl Responder> {
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("Borrower name cannot be empty"));
+    }
+    let id = borrowers::create_borrower(&state.db, name, data.phone.as_deref(), data.email.as_deref(), data.notes.as_deref()).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_borrower(&state, id).await?))
+}
+
+#[derive(Deserialize)]
+struct EditBorrowerData {
+    name: Option<String>,
+    #[serde(default, with = "double_option")]
+    phone: Option<Option<String>>,
+    #[serde(default, with = "double_option")]
+    email: Option<Option<String>>,
+    #[serde(default, with = "double_option")]
+    notes: Option<Option<String>>
+}
+
+#[post("/edit_borrower/{id}")]
+pub async fn edit_borrower(state: Data<AppState>, path: web::Path<(u32,)>, data: web::Json<EditBorrowerData>) -> Result<impl Responder> {
+    let borrower = get_borrower(&state, path.into_inner().0).await?;
+    let name = data.name.as_deref().map(str::trim);
+    if name == Some("") {
+        return Err(actix_web::error::ErrorBadRequest("Borrower name cannot be empty"));
+    }
+    borrowers::edit_borrower(&state.db, borrower.id, name, data.phone.clone(), data.email.clone(), data.notes.clone()).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_borrower(&state, borrower.id).await?))
+}
+
+#[post("/remove_borrower/{id}")]
+pub async fn remove_borrower(state: Data<AppState>, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let borrower = get_borrower(&state, path.into_inner().0).await?;
+    let active = loans::get_loans(&state.db, &loans::LoanFilter { borrower: Some(borrower.id), active: true, ..Default::default() }).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    if !active.is_empty() {
+        return Err(actix_web::error::ErrorConflict(format!("{} still has {} borrowed copies", borrower.name, active.len())));
+    }
+    match borrowers::remove_borrower(&state.db, borrower.id).await {
+        Ok(_) => Ok(format!("Removed borrower {}", borrower.name)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/borrowers")]
+pub async fn get_borrowers(state: Data<AppState>) -> Result<impl Responder> {
+    match borrowers::get_borrowers(&state.db).await {
+        Ok(borrowers) => Ok(web::Json(borrowers)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Serialize)]
+struct BorrowerResponse {
+    #[serde(flatten)]
+    borrower: types::Borrower,
+    /// Copies the borrower has right now
+    loans: Vec<loans::Loan>,
+    reservations: Vec<crud::BookReservation>
+}
+
+#[get("/borrower/{id}")]
+pub async fn get_borrower_detail(state: Data<AppState>, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let borrower = get_borrower(&state, path.into_inner().0).await?;
+    let loans = loans::get_loans(&state.db, &loans::LoanFilter { borrower: Some(borrower.id), active: true, ..Default::default() }).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let mut reservations = vec![];
+    for reservation in borrowers::get_borrower_reservations(&state.db, borrower.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))? {
+        if let Some(reservation) = crud::get_book_reservation(&state.db, reservation).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))? {
+            reservations.push(reservation);
+        }
+    }
+    Ok(web::Json(BorrowerResponse { borrower, loans, reservations }))
+}
+
+#[derive(Serialize)]
+struct OverdueReminder {
+    borrower: types::Borrower,
+    loans: Vec<loans::Loan>,
+    /// Ready to be sent by text or email
+    message: String
+}
+
+#[get("/overdue_report")]
+pub async fn get_overdue_report(state: Data<AppState>) -> Result<impl Responder> {
+    let overdue: Vec<loans::Loan> = loans::get_loans(&state.db, &loans::LoanFilter { active: true, ..Default::default() }).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?
+        .into_iter()
+        .filter(|loan| loan.overdue && loan.borrower.is_some())
+        .collect();
+
+    let mut report: Vec<OverdueReminder> = vec![];
+    for loan in overdue {
+        let Some(borrower) = loan.borrower.clone() else {
+            continue;
+        };
+        match report.iter_mut().find(|reminder| reminder.borrower.id == borrower.id) {
+            Some(reminder) => reminder.loans.push(loan),
+            None => report.push(OverdueReminder { borrower, loans: vec![loan], message: String::new() }),
+        }
+    }
+    for reminder in &mut report {
+        reminder.loans.sort_by_key(|loan| loan.due_date);
+        let books: Vec<String> = reminder.loans.iter()
+            .map(|loan| loan.due_date.map_or(loan.title.clone(), |due_date| format!("{} (due {})", loan.title, due_date.date())))
+            .collect();
+        reminder.message = format!("Hi {}, just a reminder that {} {} overdue: {}.",
+            reminder.borrower.name,
+            if books.len() == 1 { "this book" } else { "these books" },
+            if books.len() == 1 { "is" } else { "are" },
+            books.join(", "));
+    }
+    report.sort_by_key(|reminder| reminder.borrower.name.to_lowercase());
+    Ok(web::Json(report))
+}
+
+async fn get_policy(state: &AppState, id: u32) -> Result<policies::LendingPolicy> {
+    match policies::get_policy(&state.db, id).await {
+        Ok(Some(policy)) => Ok(policy),
+        Ok(None) => Err(actix_web::error::ErrorNotFound(format!("Could not find lending policy {id}"))),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+/// The session user, as long as they are an admin
+async fn require_admin(state: &AppState, req: &HttpRequest) -> Result<Session> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    match is_admin(state, session.user).await? {
+        true => Ok(session),
+        false => Err(actix_web::error::ErrorForbidden("Only admins can do this")),
+    }
+}
+
+fn check_limit_days(days: Option<u32>) -> Result<()> {
+    if days.is_some_and(|days| days > policies::MAX_LIMIT_DAYS) {
+        return Err(actix_web::error::ErrorBadRequest(format!("Lending policies can allow at most {} days", policies::MAX_LIMIT_DAYS)));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct CreatePolicyData {
+    /// At most one of role, shelf and copy, none for a rule that applies to everyone
+    role: Option<String>,
+    shelf: Option<String>,
+    copy_id: Option<types::CopyRef>,
+    #[serde(flatten)]
+    limits: policies::Limits,
+}
+
+#[post("/create_lending_policy")]
+pub async fn create_lending_policy(state: Data<AppState>, req: HttpRequest, data: web::Json<CreatePolicyData>) -> Result<impl Responder> {
+    require_admin(&state, &req).await?;
+    check_limit_days(data.limits.max_loan_days)?;
+    check_limit_days(data.limits.max_days_ahead)?;
+    let role = data.role.as_deref().map(str::trim);
+    if role == Some("") {
+        return Err(actix_web::error::ErrorBadRequest("Role cannot be empty"));
+    }
+    if [role.is_some(), data.shelf.is_some(), data.copy_id.is_some()].iter().filter(|scoped| **scoped).count() > 1 {
+        return Err(actix_web::error::ErrorBadRequest("A lending policy applies to either a role, a shelf or a copy"));
+    }
+    let shelf = match &data.shelf {
+        Some(name) => Some(get_shelf_by_name(&state, name).await?.id),
+        None => None,
+    };
+    let copy_id = match &data.copy_id {
+        Some(copy) => Some(resolve_copy(&state, copy).await?),
+        None => None,
+    };
+    match policies::create_policy(&state.db, role, shelf, copy_id, &data.limits).await {
+        Ok(Some(id)) => Ok(web::Json(get_policy(&state, id).await?)),
+        Ok(None) => Err(actix_web::error::ErrorConflict("There already is a lending policy for this scope")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct EditPolicyData {
+    #[serde(default, with = "double_option")]
+    max_loan_days: Option<Option<u32>>,
+    #[serde(default, with = "double_option")]
+    max_reservations: Option<Option<u32>>,
+    #[serde(default, with = "double_option")]
+    max_days_ahead: Option<Option<u32>>,
+    #[serde(default, with = "double_option")]
+    reference_only: Option<Option<bool>>
+}
+
+#[post("/edit_lending_policy/{id}")]
+pub async fn edit_lending_policy(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>, data: web::Json<EditPolicyData>) -> Result<impl Responder> {
+    require_admin(&state, &req).await?;
+    check_limit_days(data.max_loan_days.flatten())?;
+    check_limit_days(data.max_days_ahead.flatten())?;
+    let policy = get_policy(&state, path.into_inner().0).await?;
+    policies::edit_policy(&state.db, policy.id, data.max_loan_days, data.max_reservations, data.max_days_ahead, data.reference_only).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(get_policy(&state, policy.id).await?))
+}
+
+#[post("/remove_lending_policy/{id}")]
+pub async fn remove_lending_policy(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    require_admin(&state, &req).await?;
+    let policy = get_policy(&state, path.into_inner().0).await?;
+    match policies::remove_policy(&state.db, policy.id).await {
+        Ok(_) => Ok(format!("Removed lending policy {}", policy.id)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/lending_policies")]
+pub async fn get_lending_policies(state: Data<AppState>) -> Result<impl Responder> {
+    match policies::get_policies(&state.db).await {
+        Ok(policies) => Ok(web::Json(policies)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+/// What the logged in user may do with a copy
+#[get("/lending_limits/{copy}")]
+pub async fn get_lending_limits(state: Data<AppState>, req: HttpRequest, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let copy_id = resolve_copy(&state, &types::CopyRef::from(path.into_inner().0)).await?;
+    match policies::get_holder_limits(&state.db, session.user, None, copy_id).await {
+        Ok(limits) => Ok(web::Json(limits)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct UserRoleData {
+    /// None takes the user out of any role
+    role: Option<String>
 }
 
-#[derive(Serialize)]
-#[serde(transparent)]
-struct BookReservationsResponse {
-    reservations: Vec<crud::BookReservation>
+#[post("/set_user_role/{user_id}")]
+pub async fn set_user_role(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>, data: web::Json<UserRoleData>) -> Result<impl Responder> {
+    require_admin(&state, &req).await?;
+    let user = crud::get_user(&state.db, path.into_inner().0).await
+        .map_err(|_| actix_web::error::ErrorNotFound("Could not find user"))?;
+    let role = data.role.as_deref().map(str::trim).filter(|role| !role.is_empty());
+    policies::set_user_role(&state.db, user.id, role).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    match role {
+        Some(role) => Ok(format!("{} now has the role {role}", user.username)),
+        None => Ok(format!("{} no longer has a role", user.username)),
+    }
 }
 
-#[get("/get_user_reservations")]
-pub async fn get_user_reservations(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
-    let user_id = match auth::get_user_from_cookie(&state.db, req.cookie(auth::AUTH_COOKIE)).await {
-        Ok(Some(user_id)) => user_id,
-        Ok(None) => return Err(actix_web::error::ErrorUnauthorized("Could not find user to complete request")),
-        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
-    };
+#[derive(Deserialize)]
+struct UserAdminData {
+    is_admin: bool
+}
 
-    let reservations = match crud::get_user_reservations(&state.db, user_id).await {
-        Ok(reservations) => reservations,
-        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
-    };
-    
-    let mut book_reservations = vec![];
-    
-    for rsv in reservations {
-        match crud::get_book_reservation(&state.db, rsv).await {
-            Ok(Some(reservation)) => book_reservations.push(reservation),
-            Ok(None) => {},
-            Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string()))
-        };
+#[post("/set_user_admin/{user_id}")]
+pub async fn set_user_admin(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>, data: web::Json<UserAdminData>) -> Result<impl Responder> {
+    require_admin(&state, &req).await?;
+    let user = crud::get_user(&state.db, path.into_inner().0).await
+        .map_err(|_| actix_web::error::ErrorNotFound("Could not find user"))?;
+    let changed = crud::set_admin(&state.db, user.id, data.is_admin).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    match (changed, data.is_admin) {
+        (false, _) => Err(actix_web::error::ErrorConflict(format!("{} is the last admin", user.username))),
+        (true, true) => Ok(format!("{} is now an admin", user.username)),
+        (true, false) => Ok(format!("{} is no longer an admin", user.username)),
     }
-
-    Ok(web::Json(BookReservationsResponse { reservations: book_reservations }))
 }
 
 #[derive(Serialize)]
@@ -221,16 +2001,31 @@ struct MultipleBooksResponse {
 struct BookSearchQueryParams {
     search_str: Option<String>,
     limit: Option<u32>,
-    only_physical: Option<bool>
+    only_physical: Option<bool>,
+    /// Only books in this smart collection
+    collection: Option<u32>
 }
 
 #[get("/books")]
-pub async fn get_books(state: Data<AppState>, query: web::Query<BookSearchQueryParams>) -> Result<impl Responder> {
-    let only_physical = match query.only_physical {
-        Some(false) => false,
-        _ => true
+pub async fn get_books(state: Data<AppState>, req: HttpRequest, query: web::Query<BookSearchQueryParams>) -> Result<impl Responder> {
+    let only_physical = !matches!(query.only_physical, Some(false));
+    let collection = match query.collection {
+        Some(id) => {
+            let user = req.extensions().get::<Session>().map(|session| session.user);
+            Some(get_visible_collection(&state, id, user).await?)
+        }
+        None => None,
     };
     
+    // A copy code finds the book of that copy
+    if let Some(code) = query.search_str.as_deref().and_then(auth::normalize_copy_code) {
+        if let Ok(Some(copy_id)) = crud::resolve_copy(&state.db, &types::CopyRef::Code(code)).await {
+            if let Ok(Some((book, _))) = crud::get_copy_with_book(&state.db, copy_id).await {
+                return Ok(web::Json(MultipleBooksResponse { books: vec![book] }));
+            }
+        }
+    }
+
     let mut search_str = None;
 
     if let Some(search_param) = query.search_str.clone() {
@@ -244,6 +2039,7 @@ pub async fn get_books(state: Data<AppState>, query: web::Query<BookSearchQueryP
 
     match crud::query_books(&state.db, 
         search_str.as_deref(), 
+        collection.as_ref().map(|collection| &collection.filter),
         query.limit, 
         only_physical
         ).await {
@@ -264,6 +2060,21 @@ pub async fn get_search_suggestions(state: Data<AppState>, query: web::Query<Boo
         return Err(actix_web::error::ErrorBadRequest("Query parameter 'search_str' is required"));
     };
 
+    if let Some(code) = auth::normalize_copy_code(search_param) {
+        if let Ok(Some(copy_id)) = crud::resolve_copy(&state.db, &types::CopyRef::Code(code)).await {
+            if let Ok(Some((book, _))) = crud::get_copy_with_book(&state.db, copy_id).await {
+                let suggestion = types::BookSearchSuggestion {
+                    uuid: book.uuid,
+                    isbn: book.isbn,
+                    title: book.title,
+                    authors: book.authors.join("\n"),
+                    genres: Some(book.genres.join("\n")).filter(|g| !g.is_empty()),
+                };
+                return Ok(web::Json(SearchSuggestions { suggestions: vec![suggestion] }));
+            }
+        }
+    }
+
     let spellfix_candidates = search::get_spelling_candidates(&state.db, search_param, 1).await
         .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
 
@@ -279,28 +2090,411 @@ pub async fn get_search_suggestions(state: Data<AppState>, query: web::Query<Boo
 
 }
 
+async fn get_visible_collection(state: &Data<AppState>, id: u32, user: Option<u32>) -> Result<collections::Collection> {
+    match collections::get_collection(&state.db, id).await {
+        Ok(Some(collection)) if collection.shared || Some(collection.owner.id) == user => Ok(collection),
+        Ok(_) => Err(actix_web::error::ErrorNotFound("Could not find collection")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+async fn get_owned_collection(state: &Data<AppState>, id: u32, user: u32) -> Result<collections::Collection> {
+    let collection = get_visible_collection(state, id, Some(user)).await?;
+    if collection.owner.id != user {
+        return Err(actix_web::error::ErrorForbidden("User does not own collection"));
+    }
+    Ok(collection)
+}
+
+fn collection_error(err: sqlx::Error, name: &str) -> actix_web::Error {
+    match err {
+        sqlx::Error::Database(err) if err.is_unique_violation() => actix_web::error::ErrorConflict(format!("Collection {name} already exists")),
+        err => actix_web::error::ErrorInternalServerError(err.to_string()),
+    }
+}
+
+/// Runs the filter once so that broken search syntax is caught before it is saved
+async fn check_book_filter(state: &Data<AppState>, filter: &types::BookFilter) -> Result<()> {
+    if filter.published_after.zip(filter.published_before).is_some_and(|(after, before)| after >= before) {
+        return Err(actix_web::error::ErrorBadRequest("published_after has to be before published_before"));
+    }
+    crud::query_books(&state.db, None, Some(filter), Some(1), false).await
+        .map_err(|err| actix_web::error::ErrorBadRequest(format!("Invalid filter: {err}")))?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct CreateCollectionData {
+    name: String,
+    #[serde(default)]
+    shared: bool,
+    filter: types::BookFilter
+}
+
+#[post("/create_collection")]
+pub async fn create_collection(state: Data<AppState>, req: HttpRequest, data: web::Json<CreateCollectionData>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("Collection name cannot be empty"));
+    }
+    check_book_filter(&state, &data.filter).await?;
+    let id = collections::create_collection(&state.db, session.user, name, data.shared, &data.filter).await
+        .map_err(|err| collection_error(err, name))?;
+    Ok(web::Json(get_visible_collection(&state, id, Some(session.user)).await?))
+}
+
+#[derive(Deserialize)]
+struct EditCollectionData {
+    name: Option<String>,
+    shared: Option<bool>,
+    filter: Option<types::BookFilter>
+}
+
+#[post("/edit_collection/{id}")]
+pub async fn edit_collection(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>, data: web::Json<EditCollectionData>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let collection = get_owned_collection(&state, path.into_inner().0, session.user).await?;
+    let name = data.name.as_deref().map(str::trim);
+    if name == Some("") {
+        return Err(actix_web::error::ErrorBadRequest("Collection name cannot be empty"));
+    }
+    if let Some(filter) = &data.filter {
+        check_book_filter(&state, filter).await?;
+    }
+    collections::edit_collection(&state.db, collection.id, name, data.shared, data.filter.as_ref()).await
+        .map_err(|err| collection_error(err, name.unwrap_or(&collection.name)))?;
+    Ok(web::Json(get_visible_collection(&state, collection.id, Some(session.user)).await?))
+}
+
+#[post("/remove_collection/{id}")]
+pub async fn remove_collection(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let collection = get_owned_collection(&state, path.into_inner().0, session.user).await?;
+    match collections::remove_collection(&state.db, collection.id).await {
+        Ok(_) => Ok(format!("Removed collection {}", collection.name)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[get("/collections")]
+pub async fn get_collections(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    match collections::get_visible_collections(&state.db, session.user).await {
+        Ok(collections) => Ok(web::Json(collections)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Serialize)]
+struct CollectionResponse {
+    collection: collections::Collection,
+    books: Vec<types::Book>
+}
+
+async fn get_collection_with_books(state: &Data<AppState>, req: &HttpRequest, id: u32) -> Result<CollectionResponse> {
+    let user = req.extensions().get::<Session>().map(|session| session.user);
+    let collection = get_visible_collection(state, id, user).await?;
+    let books = collections::get_collection_books(&state.db, &collection.filter).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(CollectionResponse { collection, books })
+}
+
+#[get("/collection/{id}")]
+pub async fn get_collection(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>) -> Result<impl Responder> {
+    Ok(web::Json(get_collection_with_books(&state, &req, path.into_inner().0).await?))
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Csv,
+    Json
+}
+
+#[derive(Deserialize)]
+struct ExportParams {
+    #[serde(default)]
+    format: ExportFormat
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn books_csv(books: &[types::Book]) -> String {
+    let mut csv = String::from("uuid,isbn,title,authors,genres,publication_year,page_count,language,series,series_index,copies\r\n");
+    for book in books {
+        let fields = [
+            book.uuid.to_string(),
+            book.isbn.clone().unwrap_or_default(),
+            book.title.clone(),
+            book.authors.join("; "),
+            book.genres.join("; "),
+            book.publication_year.map(|year| year.to_string()).unwrap_or_default(),
+            book.page_count.map(|count| count.to_string()).unwrap_or_default(),
+            book.language.clone().unwrap_or_default(),
+            book.series.clone().unwrap_or_default(),
+            book.series_index.map(|index| index.to_string()).unwrap_or_default(),
+            book.copy_ids.len().to_string(),
+        ];
+        csv += &fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(",");
+        csv += "\r\n";
+    }
+    csv
+}
+
+#[get("/export_collection/{id}")]
+pub async fn export_collection(state: Data<AppState>, req: HttpRequest, path: web::Path<(u32,)>, params: web::Query<ExportParams>) -> Result<impl Responder> {
+    let response = get_collection_with_books(&state, &req, path.into_inner().0).await?;
+    let file_name: String = response.collection.name.chars()
+        .map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' { c } else { '_' })
+        .collect();
+    let (content_type, extension, body) = match params.format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv", books_csv(&response.books)),
+        ExportFormat::Json => ("application/json", "json", serde_json::to_string_pretty(&response)
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("{file_name}.{extension}"))],
+        })
+        .body(body))
+}
+
 #[derive(Serialize)]
 struct SingleBookResponse {
     book: types::Book,
-    copies: Vec<types::PhysicalBook>
+    copies: Vec<types::PhysicalBook>,
+    ebooks: Vec<types::Ebook>
 }
 
 #[get("/book/{identifier}")]
 pub async fn get_book(state: Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder> {
-    match crud::get_physical_copies(&state.db, path.into_inner().0).await {
-        Ok((book, copies)) => Ok(web::Json(SingleBookResponse { book, copies })),
-        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string())),
-    }
+    let (book, copies) = crud::get_physical_copies(&state.db, path.into_inner().0).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let ebooks = crud::get_book_ebooks(&state.db, book.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    Ok(web::Json(SingleBookResponse { book, copies, ebooks }))
 }
 
 #[get("/get_shelves")]
 pub async fn get_shelves(state: Data<AppState>) -> Result<impl Responder> {
-    match crud::get_shelves(&state.db).await {
-        Ok(shelves) => Ok(shelves.iter().map(|s| s.name.as_str()).collect::<Vec<_>>().join(",")),
+    match shelves::get_shelf_summaries(&state.db).await {
+        Ok(shelves) => Ok(web::Json(shelves)),
         _ => Err(actix_web::error::ErrorInternalServerError("Could not retrieve bookshelves"))
     }
 }
 
+#[get("/shelf/{shelf_name}")]
+pub async fn get_shelf_detail(state: Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let shelf = get_shelf_by_name(&state, &path.into_inner().0).await?;
+    match shelves::get_shelf_detail(&state.db, shelf.id).await {
+        Ok(Some(detail)) => Ok(web::Json(detail)),
+        Ok(None) => Err(actix_web::error::ErrorNotFound("Could not find shelf")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+/// Shelves are addressed by name as a single path segment
+fn check_shelf_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("Shelf name cannot be empty"));
+    }
+    if name.contains('/') {
+        return Err(actix_web::error::ErrorBadRequest("Shelf name cannot contain '/'"));
+    }
+    Ok(())
+}
+
+fn shelf_name_error(err: sqlx::Error, name: &str) -> actix_web::Error {
+    match err {
+        sqlx::Error::Database(err) if err.is_unique_violation() => actix_web::error::ErrorConflict(format!("Shelf {name} already exists")),
+        err => actix_web::error::ErrorInternalServerError(err.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateShelfData {
+    name: String,
+    description: Option<String>
+}
+
+#[post("/create_shelf")]
+pub async fn create_shelf(state: Data<AppState>, data: web::Json<CreateShelfData>) -> Result<impl Responder> {
+    let name = data.name.trim();
+    check_shelf_name(name)?;
+    shelves::create_shelf(&state.db, name, data.description.as_deref()).await
+        .map_err(|err| shelf_name_error(err, name))?;
+    Ok(web::Json(get_shelf_by_name(&state, name).await?))
+}
+
+#[derive(Deserialize)]
+struct EditShelfData {
+    name: Option<String>,
+    #[serde(default, with = "double_option")]
+    description: Option<Option<String>>,
+    #[serde(default, with = "double_option")]
+    sort_rule: Option<Option<types::SortRule>>
+}
+
+#[post("/edit_shelf/{shelf_name}")]
+pub async fn edit_shelf(state: Data<AppState>, path: web::Path<(String,)>, data: web::Json<EditShelfData>) -> Result<impl Responder> {
+    let shelf = get_shelf_by_name(&state, &path.into_inner().0).await?;
+    let name = data.name.as_deref().map(str::trim);
+    if let Some(name) = name {
+        check_shelf_name(name)?;
+    }
+    shelves::edit_shelf(&state.db, shelf.id, name, data.description.clone(), data.sort_rule).await
+        .map_err(|err| shelf_name_error(err, name.unwrap_or_default()))?;
+    Ok(web::Json(get_shelf_by_name(&state, name.unwrap_or(&shelf.name)).await?))
+}
+
+#[derive(Deserialize)]
+struct ReshelveParams {
+    /// Book to find a spot for
+    book: Option<Uuid>,
+    color: Option<String>
+}
+
+#[get("/reshelve/{shelf_name}")]
+pub async fn get_reshelve_plan(state: Data<AppState>, path: web::Path<(String,)>, params: web::Query<ReshelveParams>) -> Result<impl Responder> {
+    let shelf = get_shelf_by_name(&state, &path.into_inner().0).await?;
+    let sort_rule = shelves::get_sort_rule(&state.db, shelf.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let copies = shelves::get_shelved_copies(&state.db, shelf.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+
+    let new_copy = match params.book {
+        Some(uuid) => {
+            let book = crud::get_book(&state.db, None, Some(uuid)).await
+                .map_err(|_| actix_web::error::ErrorNotFound("Could not find book"))?;
+            Some(shelves::ShelvedCopy {
+                copy_id: 0,
+                code: String::new(),
+                title: book.title,
+                authors: book.authors.join("\n"),
+                series: book.series,
+                series_index: book.series_index,
+                publication_year: book.publication_year,
+                color: params.color.as_deref().and_then(normalize_color),
+                position: None,
+            })
+        }
+        None => None,
+    };
+    Ok(web::Json(reshelve::plan(&copies, sort_rule, new_copy.as_ref())))
+}
+
+#[derive(Deserialize)]
+struct ArrangeShelfData {
+    /// Copies from the left, leave out to store the order suggested by the shelf's sort rule
+    copies: Option<Vec<types::CopyRef>>
+}
+
+#[post("/arrange_shelf/{shelf_name}")]
+pub async fn arrange_shelf(state: Data<AppState>, path: web::Path<(String,)>, data: web::Json<ArrangeShelfData>) -> Result<impl Responder> {
+    let shelf = get_shelf_by_name(&state, &path.into_inner().0).await?;
+    let shelved = shelves::get_shelved_copies(&state.db, shelf.id).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let copy_ids = match &data.copies {
+        Some(copies) => {
+            let copy_ids = resolve_copies(&state, copies).await?;
+            if let Some(copy_id) = copy_ids.iter().find(|id| shelved.iter().all(|copy| copy.copy_id != **id)) {
+                return Err(actix_web::error::ErrorBadRequest(format!("Copy {copy_id} is not on shelf {}", shelf.name)));
+            }
+            if (1..copy_ids.len()).any(|i| copy_ids[i..].contains(&copy_ids[i - 1])) {
+                return Err(actix_web::error::ErrorBadRequest("A copy can only be in one place"));
+            }
+            copy_ids
+        }
+        None => {
+            let sort_rule = shelves::get_sort_rule(&state.db, shelf.id).await
+                .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+            let plan = reshelve::plan(&shelved, sort_rule, None);
+            plan.order.iter().map(|copy| copy.copy.copy_id).collect()
+        }
+    };
+    match shelves::set_positions(&state.db, shelf.id, &copy_ids).await {
+        Ok(_) => Ok(format!("Arranged {} copies on shelf {}", copy_ids.len(), shelf.name)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct MergeShelvesData {
+    shelves: Vec<String>,
+    into: String
+}
+
+#[post("/merge_shelves")]
+pub async fn merge_shelves(state: Data<AppState>, req: HttpRequest, data: web::Json<MergeShelvesData>) -> Result<impl Responder> {
+    let into = get_shelf_by_name(&state, &data.into).await?;
+    let mut from = vec![];
+    for name in &data.shelves {
+        let shelf = get_shelf_by_name(&state, name).await?;
+        if shelf.id == into.id {
+            return Err(actix_web::error::ErrorBadRequest("Cannot merge a shelf into itself"));
+        }
+        if from.iter().all(|s: &types::Shelf| s.id != shelf.id) {
+            from.push(shelf);
+        }
+    }
+    let user_id = req.extensions().get::<Session>().map(|session| session.user);
+    match shelves::merge_shelves(&state.db, &from, &into, user_id).await {
+        Ok(moved) => Ok(format!("Moved {moved} copies from {} shelves to {}", from.len(), into.name)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct RemoveShelfData {
+    /// Where the copies on the shelf go
+    move_copies_to: Option<String>,
+    /// Has to be set to remove a shelf that still holds copies without moving them
+    #[serde(default)]
+    remove_copies: bool
+}
+
+#[post("/remove_shelf/{shelf_name}")]
+pub async fn remove_shelf(state: Data<AppState>, req: HttpRequest, path: web::Path<(String,)>, data: web::Json<RemoveShelfData>) -> Result<impl Responder> {
+    let shelf = get_shelf_by_name(&state, &path.into_inner().0).await?;
+    let destination = match &data.move_copies_to {
+        Some(name) => Some(get_shelf_by_name(&state, name).await?),
+        None => None,
+    };
+    if destination.as_ref().is_some_and(|destination| destination.id == shelf.id) {
+        return Err(actix_web::error::ErrorBadRequest("Copies cannot be moved to the shelf being removed"));
+    }
+    if destination.is_none() && !data.remove_copies {
+        let copies = bulk::get_shelf_copy_ids(&state.db, shelf.id).await
+            .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+        if !copies.is_empty() {
+            return Err(actix_web::error::ErrorConflict(format!("Shelf {} still holds {} copies, move or remove them", shelf.name, copies.len())));
+        }
+    }
+    let user_id = req.extensions().get::<Session>().map(|session| session.user);
+    match shelves::remove_shelf(&state.db, &shelf, destination.as_ref(), user_id).await {
+        Ok(_) => Ok(format!("Removed shelf {}", shelf.name)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
 #[derive(Deserialize)]
 struct UserCredentials {
     username: String,
@@ -315,8 +2509,7 @@ struct SessionResponse {
 
 #[get("/get_user")]
 pub async fn get_user(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
-    let extensions = req.extensions();
-    let Some(session) = extensions.get::<Session>() else {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
         return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
     };
     match crud::get_user(&state.db, session.user).await {
@@ -327,11 +2520,10 @@ pub async fn get_user(state: Data<AppState>, req: HttpRequest) -> Result<impl Re
 
 #[post("/logout_user")]
 pub async fn logout_user(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
-    let extensions = req.extensions();
-    let Some(session) = extensions.get::<Session>() else {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
         return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
     };
-    match auth::invalidate_session(&state.db, session).await {
+    match auth::invalidate_session(&state.db, &session).await {
         Ok(()) => Ok("Logged user out"),
         Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
     }
@@ -365,6 +2557,156 @@ pub async fn register_user(state: Data<AppState>, register_data: web::Json<UserC
     }
 }
 
+#[derive(Deserialize)]
+struct ApiTokenParams {
+    name: String
+}
+
+#[derive(Serialize)]
+struct NewApiTokenResponse {
+    #[serde(flatten)]
+    info: auth::ApiToken,
+    token: String,
+}
+
+#[post("/create_api_token")]
+pub async fn create_api_token(state: Data<AppState>, req: HttpRequest, query: web::Query<ApiTokenParams>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    // The token itself is only shown once, only its hash is stored
+    match auth::create_api_token(&state.db, session.user, &query.name).await {
+        Ok(Some((info, token))) => Ok(web::Json(NewApiTokenResponse { info, token })),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string())),
+        _ => Err(actix_web::error::ErrorInternalServerError("Could not create token"))
+    }
+}
+
+#[get("/get_api_tokens")]
+pub async fn get_api_tokens(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    match auth::get_api_tokens(&state.db, session.user).await {
+        Ok(tokens) => Ok(web::Json(tokens)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[post("/revoke_api_token/{token_id}")]
+pub async fn revoke_api_token(state: Data<AppState>, req: HttpRequest, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let token_id = path.into_inner().0;
+    match auth::revoke_api_token(&state.db, session.user, &token_id).await {
+        Ok(true) => Ok(format!("Revoked API token {token_id}")),
+        Ok(false) => Err(actix_web::error::ErrorNotFound("Could not find API token")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct CalendarFeedParams {
+    name: String,
+    #[serde(default)]
+    scope: auth::FeedScope,
+}
+
+#[derive(Serialize)]
+struct NewCalendarFeedResponse {
+    #[serde(flatten)]
+    info: auth::CalendarFeed,
+    url: String,
+}
+
+#[post("/create_calendar_feed")]
+pub async fn create_calendar_feed(state: Data<AppState>, req: HttpRequest, query: web::Query<CalendarFeedParams>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    // Everyone's reservations are for admins only
+    if query.scope == auth::FeedScope::All {
+        require_admin(&state, &req).await?;
+    }
+    // Like API tokens the secret URL is only shown once
+    match auth::create_calendar_feed(&state.db, session.user, &query.name, query.scope).await {
+        Ok(Some((info, token))) => Ok(web::Json(NewCalendarFeedResponse {
+            info,
+            url: format!("{}/calendar/{token}.ics", state.public_url),
+        })),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string())),
+        _ => Err(actix_web::error::ErrorInternalServerError("Could not create calendar feed"))
+    }
+}
+
+#[get("/get_calendar_feeds")]
+pub async fn get_calendar_feeds(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    match auth::get_calendar_feeds(&state.db, session.user).await {
+        Ok(feeds) => Ok(web::Json(feeds)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[post("/revoke_calendar_feed/{feed_id}")]
+pub async fn revoke_calendar_feed(state: Data<AppState>, req: HttpRequest, path: web::Path<(String,)>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let feed_id = path.into_inner().0;
+    match auth::revoke_calendar_feed(&state.db, session.user, &feed_id).await {
+        Ok(true) => Ok(format!("Revoked calendar feed {feed_id}")),
+        Ok(false) => Err(actix_web::error::ErrorNotFound("Could not find calendar feed")),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct EditReminderSettingsData {
+    #[serde(default, with = "double_option")]
+    email: Option<Option<String>>,
+    email_reminders: Option<bool>
+}
+
+#[get("/get_reminder_settings")]
+pub async fn get_reminder_settings(state: Data<AppState>, req: HttpRequest) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    match reminders::get_reminder_settings(&state.db, session.user).await {
+        Ok(settings) => Ok(web::Json(settings)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
+#[post("/edit_reminder_settings")]
+pub async fn edit_reminder_settings(state: Data<AppState>, req: HttpRequest, data: web::Json<EditReminderSettingsData>) -> Result<impl Responder> {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
+        return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
+    };
+    let email = data.email.clone().map(|email| email.map(|email| email.trim().to_string()));
+    if let Some(Some(email)) = &email {
+        if email.parse::<lettre::Address>().is_err() {
+            return Err(actix_web::error::ErrorBadRequest(format!("{email} is not a valid email address")));
+        }
+    }
+    let settings = reminders::get_reminder_settings(&state.db, session.user).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    let has_email = email.as_ref().map_or(settings.email.is_some(), Option::is_some);
+    if data.email_reminders.unwrap_or(settings.email_reminders) && !has_email {
+        return Err(actix_web::error::ErrorBadRequest("Reminders need an email address"));
+    }
+    reminders::edit_reminder_settings(&state.db, session.user, email, data.email_reminders).await
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+    match reminders::get_reminder_settings(&state.db, session.user).await {
+        Ok(settings) => Ok(web::Json(settings)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string()))
+    }
+}
+
 #[derive(Deserialize)]
 struct NewStringQueryParam {
     new: String
@@ -372,8 +2714,7 @@ struct NewStringQueryParam {
 
 #[post("/change_username")]
 pub async fn change_username(state: Data<AppState>, req: HttpRequest, query: web::Query<NewStringQueryParam>) -> Result<impl Responder> {
-    let extensions = req.extensions();
-    let Some(session) = extensions.get::<Session>() else {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
         return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
     };
     match crud::change_username(&state.db, session.user, &query.new).await {
@@ -384,8 +2725,7 @@ pub async fn change_username(state: Data<AppState>, req: HttpRequest, query: web
 
 #[post("/change_personal_color")]
 pub async fn change_personal_color(state: Data<AppState>, req: HttpRequest, query: web::Query<NewStringQueryParam>) -> Result<impl Responder> {
-    let extensions = req.extensions();
-    let Some(session) = extensions.get::<Session>() else {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
         return Err(actix_web::error::ErrorUnauthorized("Could not verify session token"));
     };
     match crud::change_personal_color(&state.db, session.user, &query.new).await {
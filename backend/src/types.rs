@@ -34,28 +34,165 @@ pub struct User {
     pub personal_color: String,
 }
 
-#[derive(serde::Serialize)]
+/// Someone books are lent to who does not have an account
+#[derive(sqlx::FromRow, serde::Serialize, Clone)]
+pub struct Borrower {
+    pub id: u32,
+    pub name: String,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(serde::Serialize, Clone)]
 pub struct Reservation {
     pub id: u32,
+    /// Who made the reservation, for themselves unless there is a borrower
     pub user: User,
+    pub borrower: Option<Borrower>,
     pub created_at: i64,
     #[serde(with = "time::serde::iso8601")]
     pub start_date:  OffsetDateTime,
-    #[serde(with = "time::serde::iso8601")]
-    pub end_date: OffsetDateTime,
+    /// None while the copy is kept until it is returned
+    #[serde(with = "time::serde::iso8601::option")]
+    pub end_date: Option<OffsetDateTime>,
+    pub status: ReservationStatus,
+}
+
+/// Reservations of copies that require approval wait for the copy's owner
+#[derive(sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReservationStatus {
+    Pending,
+    Approved,
+    Declined,
 }
 
 impl Reservation {
-    pub fn intersects(&self, start: OffsetDateTime, end: OffsetDateTime) -> bool {
-        // Reservations can start and end on the same date
-        self.start_date < end && start < self.end_date 
+    /// Pending and declined reservations leave the copy to others
+    pub fn takes_copy(&self) -> bool {
+        self.status == ReservationStatus::Approved
+    }
+
+    pub fn intersects(&self, start: OffsetDateTime, end: Option<OffsetDateTime>) -> bool {
+        spans_intersect(self.start_date, self.end_date, start, end)
+    }
+
+    pub fn is_running(&self, at: OffsetDateTime) -> bool {
+        self.start_date <= at && self.end_date.is_none_or(|end| at <= end)
     }
 }
 
-#[derive(sqlx::FromRow, serde::Serialize)]
+/// Whether two spans of time overlap, a span without an end goes on forever
+pub fn spans_intersect(
+    start: OffsetDateTime,
+    end: Option<OffsetDateTime>,
+    other_start: OffsetDateTime,
+    other_end: Option<OffsetDateTime>,
+) -> bool {
+    // Reservations can start and end on the same date
+    other_end.is_none_or(|other_end| start < other_end) && end.is_none_or(|end| other_start < end)
+}
+
+#[derive(sqlx::FromRow, serde::Serialize, Clone)]
 pub struct Shelf {
     pub id: u32,
     pub name: String,
+    pub location: Option<u32>,
+    /// Full path through the location tree, e.g. "Living room › Billy 2 › Shelf 3"
+    pub breadcrumb: String,
+}
+
+/// How the copies on a shelf are supposed to be ordered
+#[derive(sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SortRule {
+    /// By author surname, then series and publication year
+    Author,
+    /// By series and volume, then author
+    Series,
+    /// By spine colour, like a rainbow
+    Color,
+    #[default]
+    Manual,
+}
+
+/// Conditions a book has to meet to be part of a smart collection, all of them at once
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct BookFilter {
+    /// Full-text search, same syntax as the search field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+    /// Language code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Inclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_after: Option<i16>,
+    /// Exclusive, "before 1950" leaves out 1950
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_before: Option<i16>,
+    /// Books with a copy on one of these shelves
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shelves: Option<Vec<u32>>,
+    /// Books with a copy anywhere inside this location
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<u32>,
+}
+
+#[derive(sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum LocationKind {
+    Home,
+    Room,
+    Bookcase,
+}
+
+impl std::fmt::Display for LocationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocationKind::Home => write!(f, "home"),
+            LocationKind::Room => write!(f, "room"),
+            LocationKind::Bookcase => write!(f, "bookcase"),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct Location {
+    pub id: u32,
+    pub name: String,
+    pub kind: LocationKind,
+    pub parent: Option<u32>,
+    pub breadcrumb: String,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub layout: LocationLayout,
+}
+
+/// Physical geometry in centimetres, see the bookcase layout migration
+#[derive(sqlx::FromRow, serde::Serialize, Default, Clone)]
+pub struct LocationLayout {
+    pub width: Option<f64>,
+    pub depth: Option<f64>,
+    pub height: Option<f64>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub rotation: Option<f64>,
+    pub levels: Option<u32>,
+    pub sections: Option<u32>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -69,16 +206,130 @@ pub struct Book {
     pub publication_year: Option<i16>,
     pub page_count: Option<u16>,
     pub language: Option<String>,
+    pub series: Option<String>,
+    pub series_index: Option<f64>,
     pub copy_ids: Vec<u32>
 }
 
+/// A physical copy given either by its id or its short code
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum CopyRef {
+    Id(u32),
+    Code(String),
+}
+
+impl From<String> for CopyRef {
+    fn from(value: String) -> Self {
+        match value.trim().parse::<u32>() {
+            Ok(id) => CopyRef::Id(id),
+            Err(_) => CopyRef::Code(value),
+        }
+    }
+}
+
+impl std::fmt::Display for CopyRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CopyRef::Id(id) => write!(f, "{id}"),
+            CopyRef::Code(code) => write!(f, "{code}"),
+        }
+    }
+}
+
 #[derive(serde::Serialize)]
 pub struct PhysicalBook {
     pub id: u32,
+    pub code: String,
     pub shelf: Shelf,
+    pub owner: Option<User>,
+    #[serde(flatten)]
+    pub details: CopyDetails,
     pub reservations: Vec<Reservation>,
 }
 
+impl PhysicalBook {
+    pub fn needs_approval_from(&self, user: u32) -> bool {
+        self.details.requires_approval && self.owner.as_ref().is_some_and(|owner| owner.id != user)
+    }
+}
+
+#[derive(sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum CopyCondition {
+    New,
+    Fine,
+    Good,
+    Fair,
+    Poor,
+}
+
+#[derive(sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum CopySource {
+    Bought,
+    Gift,
+    Inherited,
+    Other,
+}
+
+#[derive(sqlx::Type, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum CopyFormat {
+    Paperback,
+    Hardcover,
+    Oversized,
+}
+
+impl CopyFormat {
+    /// Rough spine thickness in centimetres, assumes 300 pages when the page count is unknown
+    pub fn spine_thickness(&self, page_count: Option<u16>) -> f64 {
+        let pages = page_count.filter(|pages| *pages > 0).unwrap_or(300) as f64;
+        let (per_page, covers) = match self {
+            CopyFormat::Paperback => (0.0055, 0.1),
+            CopyFormat::Hardcover => (0.006, 0.6),
+            CopyFormat::Oversized => (0.008, 0.8),
+        };
+        pages * per_page + covers
+    }
+}
+
+/// Per-copy information that is not shared between copies of the same book
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct CopyDetails {
+    pub condition: Option<CopyCondition>,
+    #[serde(with = "time::serde::iso8601::option")]
+    pub acquired_at: Option<OffsetDateTime>,
+    pub purchase_price: Option<f64>,
+    pub currency: Option<String>,
+    pub source: Option<CopySource>,
+    pub signed: bool,
+    pub first_edition: bool,
+    pub notes: Option<String>,
+    pub format: Option<CopyFormat>,
+    /// Measured spine thickness in centimetres, overrides the estimate from the format and page count
+    pub thickness: Option<f64>,
+    /// Spine colour as #rrggbb
+    pub color: Option<String>,
+    /// Place on the shelf from the left, None until the shelf has been put in order
+    pub position: Option<u32>,
+    /// Reservations by anyone but the owner wait for the owner's approval
+    pub requires_approval: bool,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize, Clone)]
+pub struct Ebook {
+    pub id: u32,
+    pub book: u32,
+    pub file_name: String,
+    pub media_type: String,
+    pub size: i64,
+    pub added_at: i64,
+}
+
 #[derive(sqlx::FromRow, serde::Serialize)]
 pub struct BookSearchSuggestion {
     pub uuid: Uuid,
@@ -0,0 +1,54 @@
+use std::{path::PathBuf, str::FromStr};
+
+use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
+use uuid::Uuid;
+
+/// A migrated database in the temp dir, removed again when dropped
+pub struct TestDb {
+    pub pool: SqlitePool,
+    path: PathBuf,
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        // Unlinking is fine while connections are still open
+        for suffix in ["", "-journal", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", self.path.display()));
+        }
+    }
+}
+
+pub async fn setup() -> TestDb {
+    let path = std::env::temp_dir().join(format!("hll-test-{}.sqlite", Uuid::new_v4()));
+    let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.display())).unwrap()
+        .create_if_missing(true)
+        .extension("./spellfix1");
+    let pool = SqlitePool::connect_with(options).await.unwrap();
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+    TestDb { pool, path }
+}
+
+pub async fn add_user(pool: &SqlitePool, name: &str, color: &str) -> u32 {
+    sqlx::query_scalar("
+        INSERT INTO User (username, password_hash, personal_color)
+        VALUES (?, '', ?)
+        RETURNING id").bind(name).bind(color).fetch_one(pool).await.unwrap()
+}
+
+/// Copies of one book on one shelf
+pub async fn add_copies(pool: &SqlitePool, count: u32) -> Vec<u32> {
+    let book: u32 = sqlx::query_scalar("
+        INSERT INTO Book (uuid, title, authors)
+        VALUES (?, 'Dune', 'Frank Herbert')
+        RETURNING id").bind(Uuid::new_v4()).fetch_one(pool).await.unwrap();
+    let shelf: u32 = sqlx::query_scalar("INSERT INTO Shelf (name) VALUES ('Hall') RETURNING id")
+        .fetch_one(pool).await.unwrap();
+    let mut copies = vec![];
+    for i in 0..count {
+        copies.push(sqlx::query_scalar("
+            INSERT INTO PhysicalBook (book, shelf, code)
+            VALUES (?, ?, ?)
+            RETURNING id").bind(book).bind(shelf).bind(format!("abc-de{i}")).fetch_one(pool).await.unwrap());
+    }
+    copies
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{add_copies, add_user, setup};
+use hll::{database::{crud, loans, reminders}, mailer::{self, Mailer, SmtpConfig, SmtpSecurity}};
+use sqlx::SqlitePool;
+use time::{Duration, OffsetDateTime};
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::TcpListener};
+
+/// Local SMTP server that accepts every message and keeps it, returns its port and the messages
+async fn smtp_sink() -> (u16, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let messages = Arc::new(Mutex::new(vec![]));
+    let received = messages.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let received = received.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let command = line.to_uppercase();
+                    if command.starts_with("DATA") {
+                        writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                        let mut message = String::new();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if line == "." {
+                                break;
+                            }
+                            message += &line;
+                            message += "\n";
+                        }
+                        received.lock().unwrap().push(message);
+                        writer.write_all(b"250 queued\r\n").await.unwrap();
+                    } else if command.starts_with("QUIT") {
+                        writer.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    } else {
+                        writer.write_all(b"250 ok\r\n").await.unwrap();
+                    }
+                }
+            });
+        }
+    });
+    (port, messages)
+}
+
+async fn lend(pool: &SqlitePool, copy: u32, user: u32, due_date: OffsetDateTime) {
+    let (_, copy) = crud::get_copy_with_book(pool, copy).await.unwrap().unwrap();
+    loans::check_out(pool, &copy, user, None, Some(due_date), None).await.unwrap();
+}
+
+fn sink_mailer(port: u16) -> Mailer {
+    Mailer::new(&SmtpConfig {
+        host: "127.0.0.1".to_string(),
+        port: Some(port),
+        security: SmtpSecurity::None,
+        username: None,
+        password: None,
+        from: "Home Library <library@localhost>".to_string(),
+    }).unwrap()
+}
+
+#[tokio::test]
+async fn reminders_are_sent_once_to_users_who_opted_in() {
+    let db = setup().await;
+    let pool = &db.pool;
+    let (port, messages) = smtp_sink().await;
+    let mailer = sink_mailer(port);
+
+    let ann = add_user(pool, "ann", "000000").await;
+    let bob = add_user(pool, "bob", "ffffff").await;
+    reminders::edit_reminder_settings(pool, ann, Some(Some("ann@example.com".to_string())), Some(true)).await.unwrap();
+    // An address alone does not opt in
+    reminders::edit_reminder_settings(pool, bob, Some(Some("bob@example.com".to_string())), None).await.unwrap();
+
+    let copies = add_copies(pool, 6).await;
+    let now = OffsetDateTime::now_utc();
+    let tomorrow = now + Duration::hours(12);
+    crud::reserve_physical_book(pool, ann, None, copies[0], tomorrow, Some(tomorrow + Duration::days(3))).await.unwrap();
+    crud::reserve_physical_book(pool, bob, None, copies[1], tomorrow, Some(tomorrow + Duration::days(3))).await.unwrap();
+    let next_week = now + Duration::days(7);
+    crud::reserve_physical_book(pool, ann, None, copies[2], next_week, Some(next_week + Duration::days(3))).await.unwrap();
+    lend(pool, copies[3], ann, now + Duration::days(1)).await;
+    lend(pool, copies[4], ann, now - Duration::days(1)).await;
+    lend(pool, copies[5], bob, now - Duration::days(1)).await;
+
+    assert_eq!(mailer::send_reminders(pool, &mailer).await.unwrap(), 3);
+    let sent = messages.lock().unwrap().clone();
+    assert_eq!(sent.len(), 3);
+    assert!(sent.iter().all(|message| message.contains("To: ann@example.com")));
+    assert!(sent.iter().any(|message| message.contains(&format!("Subject: Your reservation of Dune starts on {}", tomorrow.date()))));
+    assert!(sent.iter().any(|message| message.contains(&format!("Subject: Dune is due on {}", (now + Duration::days(1)).date()))));
+    assert!(sent.iter().any(|message| message.contains("Subject: Dune is overdue")));
+
+    assert_eq!(mailer::send_reminders(pool, &mailer).await.unwrap(), 0);
+    assert_eq!(messages.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn failed_reminders_are_tried_again() {
+    let db = setup().await;
+    let pool = &db.pool;
+    let ann = add_user(pool, "ann", "000000").await;
+    reminders::edit_reminder_settings(pool, ann, Some(Some("ann@example.com".to_string())), Some(true)).await.unwrap();
+    let copies = add_copies(pool, 1).await;
+    lend(pool, copies[0], ann, OffsetDateTime::now_utc() - Duration::days(1)).await;
+
+    // Nothing listens on the port of a closed sink
+    let closed_port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    assert_eq!(mailer::send_reminders(pool, &sink_mailer(closed_port)).await.unwrap(), 0);
+
+    let (port, messages) = smtp_sink().await;
+    assert_eq!(mailer::send_reminders(pool, &sink_mailer(port)).await.unwrap(), 1);
+    assert!(messages.lock().unwrap()[0].contains("Subject: Dune is overdue"));
+}
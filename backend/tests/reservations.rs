@@ -0,0 +1,221 @@
+mod common;
+
+use common::{add_copies, add_user, TestDb};
+use hll::{database::{crud::{self, Reserve}, holds, loans}, types::ReservationStatus};
+use time::{Duration, OffsetDateTime};
+
+/// A fresh database with one user and one copy, returns the database, user and copy
+async fn setup() -> (TestDb, u32, u32) {
+    let db = common::setup().await;
+    let user = add_user(&db.pool, "ann", "000000").await;
+    let copy = add_copies(&db.pool, 1).await[0];
+    (db, user, copy)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn parallel_reservations_book_a_copy_once() {
+    let (db, user, copy) = setup().await;
+    let pool = &db.pool;
+    let start = OffsetDateTime::now_utc() + Duration::days(1);
+
+    // Every request overlaps all the others by at least a day
+    let requests: Vec<_> = (0..16).map(|i| {
+        let pool = pool.clone();
+        let start = start + Duration::hours(i);
+        tokio::spawn(async move {
+            crud::reserve_physical_book(&pool, user, None, copy, start, Some(start + Duration::days(2))).await
+        })
+    }).collect();
+
+    let mut reserved = 0;
+    for request in requests {
+        if matches!(request.await.unwrap().unwrap(), Reserve::Reserved(_)) {
+            reserved += 1;
+        }
+    }
+    assert_eq!(reserved, 1);
+
+    let reservations: u32 = sqlx::query_scalar("SELECT COUNT(*) FROM Reservation").fetch_one(pool).await.unwrap();
+    let matches: u32 = sqlx::query_scalar("SELECT COUNT(*) FROM BookReservationMatch").fetch_one(pool).await.unwrap();
+    // Rejected requests leave nothing behind
+    assert_eq!(reservations, 1);
+    assert_eq!(matches, 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn parallel_edits_move_one_reservation_into_the_same_time() {
+    let (db, user, copy) = setup().await;
+    let pool = &db.pool;
+    let start = OffsetDateTime::now_utc() + Duration::days(1);
+
+    let mut reservations = vec![];
+    for i in 0..8 {
+        let start = start + Duration::days(10 * i);
+        match crud::reserve_physical_book(pool, user, None, copy, start, Some(start + Duration::days(1))).await.unwrap() {
+            Reserve::Reserved(id) => reservations.push(crud::get_reservation(pool, id).await.unwrap().unwrap()),
+            _ => panic!("The reservations do not overlap"),
+        }
+    }
+
+    // All of them want the same two days, which are free before the edits
+    let target = start + Duration::days(100);
+    let edits: Vec<_> = reservations.into_iter().map(|reservation| {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            crud::edit_reservation(&pool, &reservation, copy, target, Some(target + Duration::days(2))).await
+        })
+    }).collect();
+
+    let mut moved = 0;
+    for edit in edits {
+        if matches!(edit.await.unwrap().unwrap(), Reserve::Reserved(_)) {
+            moved += 1;
+        }
+    }
+    assert_eq!(moved, 1);
+
+    let (_, copy) = crud::get_copy_with_book(pool, copy).await.unwrap().unwrap();
+    assert_eq!(copy.reservations.iter().filter(|r| r.start_date == target).count(), 1);
+    // The rejected edits leave their reservations where they were
+    assert_eq!(copy.reservations.len(), 8);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn parallel_back_to_back_reservations_all_succeed() {
+    let (db, user, copy) = setup().await;
+    let pool = &db.pool;
+    let start = OffsetDateTime::now_utc() + Duration::days(1);
+
+    let requests: Vec<_> = (0..8).map(|i| {
+        let pool = pool.clone();
+        let start = start + Duration::days(i);
+        tokio::spawn(async move {
+            crud::reserve_physical_book(&pool, user, None, copy, start, Some(start + Duration::days(1))).await
+        })
+    }).collect();
+
+    for request in requests {
+        assert!(matches!(request.await.unwrap().unwrap(), Reserve::Reserved(_)));
+    }
+}
+
+#[tokio::test]
+async fn open_ended_reservation_blocks_the_copy_from_its_start() {
+    let (db, user, copy) = setup().await;
+    let pool = &db.pool;
+    let start = OffsetDateTime::now_utc() + Duration::days(10);
+
+    let open_ended = crud::reserve_physical_book(pool, user, None, copy, start, None).await.unwrap();
+    assert!(matches!(open_ended, Reserve::Reserved(_)));
+
+    let later = start + Duration::days(365);
+    let after = crud::reserve_physical_book(pool, user, None, copy, later, Some(later + Duration::days(1))).await.unwrap();
+    assert!(matches!(after, Reserve::Unavailable));
+
+    let before = start - Duration::days(5);
+    let ending_at_start = crud::reserve_physical_book(pool, user, None, copy, before, Some(start)).await.unwrap();
+    assert!(matches!(ending_at_start, Reserve::Reserved(_)));
+
+    let without_end = crud::reserve_physical_book(pool, user, None, copy, before - Duration::days(1), None).await.unwrap();
+    assert!(matches!(without_end, Reserve::Unavailable));
+}
+
+#[tokio::test]
+async fn requests_for_owned_copies_block_only_once_approved() {
+    let (db, owner, copy) = setup().await;
+    let pool = &db.pool;
+    let requester = add_user(pool, "bob", "ffffff").await;
+    sqlx::query("UPDATE PhysicalBook SET owner = ?, requires_approval = 1 WHERE id = ?")
+        .bind(owner).bind(copy).execute(pool).await.unwrap();
+    let start = OffsetDateTime::now_utc() + Duration::days(1);
+    let end = Some(start + Duration::days(3));
+
+    let Reserve::Requested(first) = crud::reserve_physical_book(pool, requester, None, copy, start, end).await.unwrap() else {
+        panic!("expected a request");
+    };
+    // Waiting requests leave the time open to others
+    let Reserve::Requested(second) = crud::reserve_physical_book(pool, requester, None, copy, start, end).await.unwrap() else {
+        panic!("expected a request");
+    };
+    let statuses: Vec<_> = crud::get_user_reservations(pool, requester).await.unwrap().iter().map(|r| r.status).collect();
+    assert_eq!(statuses, vec![ReservationStatus::Pending, ReservationStatus::Pending]);
+    assert_eq!(crud::get_reservation_requests(pool, owner).await.unwrap().len(), 2);
+
+    let first = crud::get_reservation(pool, first).await.unwrap().unwrap();
+    assert!(matches!(crud::approve_reservation(pool, &first, copy).await.unwrap(), Reserve::Reserved(_)));
+    let second = crud::get_reservation(pool, second).await.unwrap().unwrap();
+    assert!(matches!(crud::approve_reservation(pool, &second, copy).await.unwrap(), Reserve::Unavailable));
+    crud::decline_reservation(pool, second.id).await.unwrap();
+
+    let statuses: Vec<_> = crud::get_user_reservations(pool, requester).await.unwrap().iter().map(|r| r.status).collect();
+    assert_eq!(statuses, vec![ReservationStatus::Approved, ReservationStatus::Declined]);
+    // The owner needs nobody's approval, but the approved request now has the copy
+    let by_owner = crud::reserve_physical_book(pool, owner, None, copy, start, end).await.unwrap();
+    assert!(matches!(by_owner, Reserve::Unavailable));
+}
+
+#[tokio::test]
+async fn lent_copies_cannot_be_reserved_until_they_are_back() {
+    let (db, user, copy) = setup().await;
+    let pool = &db.pool;
+    let now = OffsetDateTime::now_utc();
+    let (_, lent) = crud::get_copy_with_book(pool, copy).await.unwrap().unwrap();
+    let due_date = now + Duration::days(7);
+    let loan = loans::check_out(pool, &lent, user, None, Some(due_date), None).await.unwrap();
+    assert!(matches!(loan, loans::CheckOut::Lent(_)));
+
+    let start = now + Duration::days(3);
+    let during = crud::reserve_physical_book(pool, user, None, copy, start, Some(start + Duration::days(2))).await.unwrap();
+    assert!(matches!(during, Reserve::Unavailable));
+    let after = crud::reserve_physical_book(pool, user, None, copy, due_date, Some(due_date + Duration::days(2))).await.unwrap();
+    assert!(matches!(after, Reserve::Reserved(_)));
+
+    // Without a due date the copy is out until it is checked in
+    loans::check_in(pool, copy).await.unwrap();
+    let (_, returned) = crud::get_copy_with_book(pool, copy).await.unwrap().unwrap();
+    let open_loan = loans::check_out(pool, &returned, user, None, None, None).await.unwrap();
+    assert!(matches!(open_loan, loans::CheckOut::Lent(_)));
+    let later = now + Duration::days(60);
+    let while_open = crud::reserve_physical_book(pool, user, None, copy, later, Some(later + Duration::days(1))).await.unwrap();
+    assert!(matches!(while_open, Reserve::Unavailable));
+}
+
+#[tokio::test]
+async fn oversized_holds_keep_the_copy_without_overflowing() {
+    let (db, user, copy) = setup().await;
+    let pool = &db.pool;
+    let other = add_user(pool, "bob", "ffffff").await;
+    let book: u32 = sqlx::query_scalar("SELECT book FROM PhysicalBook WHERE id = ?")
+        .bind(copy).fetch_one(pool).await.unwrap();
+
+    let hold = holds::place_hold(pool, book, user, u32::MAX).await.unwrap().unwrap();
+    assert_eq!(holds::process_holds(pool).await.unwrap(), vec![hold]);
+    // Processing again must not trip over the running offer either
+    assert!(holds::process_holds(pool).await.unwrap().is_empty());
+
+    let offer = holds::get_copy_offer(pool, copy).await.unwrap().unwrap();
+    assert_eq!(offer.offer_span().unwrap().1, None);
+    let later = OffsetDateTime::now_utc() + Duration::days(400);
+    let by_other = crud::reserve_physical_book(pool, other, None, copy, later, Some(later + Duration::days(1))).await.unwrap();
+    assert!(matches!(by_other, Reserve::Unavailable));
+}
+
+#[tokio::test]
+async fn offered_copies_go_only_to_the_user_in_line() {
+    let (db, user, copy) = setup().await;
+    let pool = &db.pool;
+    let other = add_user(pool, "bob", "ffffff").await;
+    let book: u32 = sqlx::query_scalar("SELECT book FROM PhysicalBook WHERE id = ?")
+        .bind(copy).fetch_one(pool).await.unwrap();
+    let hold = holds::place_hold(pool, book, user, 7).await.unwrap().unwrap();
+    assert_eq!(holds::process_holds(pool).await.unwrap(), vec![hold]);
+
+    let (_, offered) = crud::get_copy_with_book(pool, copy).await.unwrap().unwrap();
+    let by_other = loans::check_out(pool, &offered, other, None, None, None).await.unwrap();
+    assert!(matches!(by_other, loans::CheckOut::Held(offer) if offer.id == hold));
+    let loans: u32 = sqlx::query_scalar("SELECT COUNT(*) FROM Loan").fetch_one(pool).await.unwrap();
+    assert_eq!(loans, 0);
+
+    let by_user = loans::check_out(pool, &offered, user, None, None, None).await.unwrap();
+    assert!(matches!(by_user, loans::CheckOut::Lent(_)));
+}
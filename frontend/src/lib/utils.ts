@@ -10,8 +10,10 @@ export function getLabelFromLanguageCode(code: string): string | undefined {
     return languageCodes.find(lang => lang.value === code)?.label;
 }
 
-export function reservationDuration(reservation: any): string {
-    return `${getDateString(reservation.start_date)} - ${getDateString(reservation.end_date)}`;
+// Open-ended reservations have no end date and last until the copy is returned
+export function reservationDuration(reservation: { start_date: string, end_date: string | null }): string {
+    const end = reservation.end_date === null ? "tills vidare" : getDateString(reservation.end_date);
+    return `${getDateString(reservation.start_date)} - ${end}`;
 }
 
 function getDateString(dbDateString: string) {
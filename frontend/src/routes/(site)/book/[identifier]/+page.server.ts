@@ -19,15 +19,16 @@ export let load: PageServerLoad = async ({ fetch, params, url }) => {
         .then(res => res.ok ? coverImage : placeHolderImage)
         .catch(_ => placeHolderImage)
 
-    let shelvesString = await fetch(BACKEND_URL + "/get_shelves")
-        .then(resp => resp.text())
-        .catch(_ => "");
+    let shelves: string[] = await fetch(BACKEND_URL + "/get_shelves")
+        .then(resp => resp.json())
+        .then((shelves: { name: string }[]) => shelves.map(shelf => shelf.name))
+        .catch(_ => []);
 
     return {
         book: book.book,
         copies: book.copies,
         cover: coverImage,
-        shelves: shelvesString === "" ? [] : shelvesString.split(","),
+        shelves: shelves,
         copy: url.searchParams.get("copy")
 
     }